@@ -21,6 +21,7 @@ angular.module('healthCheckApp', [])
     healthCheck.configSuccess = null;
     healthCheck.showBearerPrompt = false;
     healthCheck.bearerToken = '';
+    healthCheck.categoryFilter = '';
     var refreshTimer = null;
 
     // Get bearer token from localStorage
@@ -84,7 +85,7 @@ angular.module('healthCheckApp', [])
             healthCheck.showBearerPrompt = true;
             healthCheck.configError = 'Authentication required. Please enter bearer token.';
           } else {
-            healthCheck.configError = 'Failed to load configuration: ' + (error.statusText || error.message);
+            healthCheck.configError = 'Failed to load configuration: ' + (error.data || error.statusText || error.message);
           }
           console.error('Error loading config:', error);
         });
@@ -147,63 +148,64 @@ angular.module('healthCheckApp', [])
       return editConfig;
     };
 
-    // Convert visual editor format back to API format
+    // Convert visual editor format back to API format, keeping fields the editor doesn't show
     healthCheck.convertVisualEditorToConfig = function() {
-      var config = {
-        telegram_token: healthCheck.editConfig.telegram_token,
-        telegram_chat_id: healthCheck.editConfig.telegram_chat_id,
-        check_interval_success: healthCheck.editConfig.check_interval_success,
-        check_interval_fail: healthCheck.editConfig.check_interval_fail,
-        notify_failures: healthCheck.editConfig.notify_failures,
-        rereport: healthCheck.editConfig.rereport,
-        web_port: healthCheck.editConfig.web_port,
-        services: {}
-      };
+      var original = healthCheck.config || {};
+      var config = angular.copy(original);
+      config.telegram_token = healthCheck.editConfig.telegram_token;
+      config.telegram_chat_id = healthCheck.editConfig.telegram_chat_id;
+      config.check_interval_success = healthCheck.editConfig.check_interval_success;
+      config.check_interval_fail = healthCheck.editConfig.check_interval_fail;
+      config.notify_failures = healthCheck.editConfig.notify_failures;
+      config.rereport = healthCheck.editConfig.rereport;
+      config.web_port = healthCheck.editConfig.web_port;
+      config.services = {};
 
       // Convert services
       for (var uuid in healthCheck.editConfig.services) {
         var editService = healthCheck.editConfig.services[uuid];
-        var service = {
-          enabled: editService.enabled,
-          name: editService.name,
-          description: editService.description
-        };
+        var service = angular.copy((original.services || {})[uuid] || {});
+        service.enabled = editService.enabled;
+        service.name = editService.name;
+        service.description = editService.description;
 
         // Add optional fields only if set
-        if (editService.check_interval_success) {
-          service.check_interval_success = editService.check_interval_success;
-        }
-        if (editService.check_interval_fail) {
-          service.check_interval_fail = editService.check_interval_fail;
-        }
-        if (editService.notify_failures) {
-          service.notify_failures = editService.notify_failures;
-        }
-        if (editService.rereport) {
-          service.rereport = editService.rereport;
-        }
+        ['check_interval_success', 'check_interval_fail', 'notify_failures', 'rereport'].forEach(function(field) {
+          if (editService[field]) {
+            service[field] = editService[field];
+          } else {
+            delete service[field];
+          }
+        });
 
-        // Convert check based on type
+        // Convert check based on type, keeping extra options when the type is unchanged
+        var originalChecks = service.check || {};
+        var originalCheck = originalChecks[editService.checkType] || {};
         service.check = {};
         if (editService.checkType === 'http') {
-          service.check.http = {
+          service.check.http = angular.extend(originalCheck, {
             url: editService.check.http.url
-          };
+          });
           if (editService.check.http.expected_status) {
             service.check.http.expected_status = editService.check.http.expected_status;
+          } else {
+            delete service.check.http.expected_status;
           }
         } else if (editService.checkType === 'certificate') {
-          service.check.certificate = {
+          service.check.certificate = angular.extend(originalCheck, {
             host: editService.check.certificate.host,
             port: editService.check.certificate.port,
             days_before_expiry: editService.check.certificate.days_before_expiry
-          };
+          });
         } else if (editService.checkType === 'tcpPing') {
-          service.check.tcpPing = {
+          service.check.tcpPing = angular.extend(originalCheck, {
             host: editService.check.tcpPing.host,
             port: editService.check.tcpPing.port,
             timeout_ms: editService.check.tcpPing.timeout_ms
-          };
+          });
+        } else {
+          // Check types without a visual editor are kept as they are
+          service.check = originalChecks;
         }
 
         config.services[uuid] = service;
@@ -353,32 +355,34 @@ angular.module('healthCheckApp', [])
       }
     };
 
-    // Count services by state
-    healthCheck.countByState = function(stateType) {
+    // Count services by status ("up", "down", "warning" or "unknown")
+    healthCheck.countByStatus = function(status) {
       return healthCheck.services.filter(function(service) {
-        if (typeof service.state === 'string') {
-          return service.state === stateType;
-        } else if (typeof service.state === 'object' && service.state !== null) {
-          // Handle Rust enum format: { "Failure": "error message" } or "Success"
-          if (stateType === 'Failure') {
-            return service.state.Failure !== undefined;
-          }
-          return false;
-        }
-        return false;
+        return service.status === status;
       }).length;
     };
 
-    // Get state class for CSS
-    healthCheck.getStateClass = function(state) {
-      if (typeof state === 'string') {
-        return state.toLowerCase();
-      } else if (typeof state === 'object' && state !== null) {
-        if (state.Failure !== undefined) {
-          return 'failure';
+    // Distinct failure categories of the failing services, for the category filter
+    healthCheck.failureCategories = function() {
+      var categories = [];
+      healthCheck.services.forEach(function(service) {
+        if (service.failure && categories.indexOf(service.failure.category) === -1) {
+          categories.push(service.failure.category);
         }
-      }
-      return 'unknown';
+      });
+      return categories.sort();
+    };
+
+    // Whether a service passes the category filter, every service does without one
+    healthCheck.matchesCategory = function(service) {
+      return !healthCheck.categoryFilter ||
+        (service.failure !== undefined && service.failure.category === healthCheck.categoryFilter);
+    };
+
+    // Get state class for CSS
+    var STATUS_CLASSES = { up: 'success', down: 'failure', warning: 'warning', unknown: 'unknown' };
+    healthCheck.getStateClass = function(status) {
+      return STATUS_CLASSES[status] || 'unknown';
     };
 
     // Get state label for display
@@ -390,6 +394,9 @@ angular.module('healthCheckApp', [])
           var msg = state.Failure;
           return 'Failure' + (msg ? ': ' + msg : '');
         }
+        if (state.Warning !== undefined) {
+          return 'Warning' + (state.Warning ? ': ' + state.Warning : '');
+        }
       }
       return 'Unknown';
     };
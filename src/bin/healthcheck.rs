@@ -1,13 +1,52 @@
-use std::path::Path;
+use std::process::ExitCode;
+
+use clap::Parser;
 use tracing_subscriber::prelude::*;
 
-use healthcheck::{AppState, Config};
+use healthcheck::{AppState, Config, State};
 
 const CONFIG_ENV: &str = "HEALTHCHECK_CONFIG";
 const CONFIG_VAL: &str = "healthcheck.yaml";
 
+#[derive(Parser)]
+#[command(name = "healthcheck")]
+#[command(about = "Service monitor with web dashboard and Telegram notifications")]
+struct Cli {
+    /// Path to configuration file, repeat to merge services from several files
+    #[arg(short, long)]
+    config: Vec<String>,
+
+    /// Check every enabled service once, print a summary and exit (non-zero if any check failed)
+    #[arg(long)]
+    once: bool,
+}
+
+// Run a single check cycle, exit code 1 if any service failed
+async fn run_once(app_state: AppState) -> ExitCode {
+    let services = app_state.run_once().await;
+    let mut failed = 0;
+    for service in &services {
+        match &service.state {
+            State::Success => println!("✓ {}", service.name),
+            State::Warning(reason) => println!("! {}: {}", service.name, reason),
+            State::Failure(reason) => {
+                failed += 1;
+                println!("✗ {}: {}", service.name, reason);
+            }
+            State::Unknown => println!("? {}", service.name),
+        }
+    }
+    println!("{} services checked, {} failed", services.len(), failed);
+
+    if failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     // Initialize tracing
     let fmt_layer = tracing_subscriber::fmt::layer();
     let rust_tls = tracing_subscriber::filter::Targets::new()
@@ -19,19 +58,42 @@ async fn main() -> anyhow::Result<()> {
         .with(rust_tls)
         .init();
 
-    // Load configuration
-    let config_path = std::env::var(CONFIG_ENV).unwrap_or_else(|_| CONFIG_VAL.to_string());
-    let config = Config::load(Path::new(&config_path))?;
+    let cli = Cli::parse();
 
-    tracing::info!("Loaded configuration from {}", config_path);
+    // Load configuration - fall back to the environment variable when no file is given
+    let config_paths = if cli.config.is_empty() {
+        vec![std::env::var(CONFIG_ENV).unwrap_or_else(|_| CONFIG_VAL.to_string())]
+    } else {
+        cli.config
+    };
+    let mut config = Config::load_all(&config_paths)?;
+    config.resolve_telegram_chat_id().await?;
+    if cli.once {
+        config = config.for_single_run();
+    }
+
+    tracing::info!("Loaded configuration from {}", config_paths.join(", "));
     let enabled_count = config.services.values().filter(|s| s.enabled).count();
     tracing::info!("Monitoring {} enabled services (total: {})", enabled_count, config.services.len());
+    if config.api_bearer_token.is_none() {
+        tracing::info!("api_bearer_token is not set, the configuration API is disabled");
+    }
 
     // Create application state
-    let app_state = AppState::new(config.clone(), config_path);
+    let app_state = AppState::new(config.clone(), config_paths);
+
+    if cli.once {
+        return Ok(run_once(app_state).await);
+    }
+
+    app_state.notify_startup().await;
 
     // Start service monitoring tasks
     app_state.start_monitoring_tasks().await;
+    app_state.start_watchdog();
+    if config.telegram_actions.unwrap_or(false) {
+        app_state.start_telegram_actions();
+    }
 
     // Start web server
     let web_port = config.web_port.unwrap_or(8080);
@@ -46,5 +108,49 @@ async fn main() -> anyhow::Result<()> {
     // Wait for web server (monitoring tasks run indefinitely in background)
     web_handle.await?;
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app_state(port: u16) -> AppState {
+        let yaml = format!(
+            r#"
+telegram_token: "token"
+telegram_chat_id: 1
+# Nothing listens there, alerts fail right away instead of reaching the real API
+telegram_api_url: "http://127.0.0.1:1"
+notification_retries: 0
+check_interval_success: 60000
+check_interval_fail: 10000
+notify_failures: 3
+rereport: 10
+services:
+  database:
+    enabled: true
+    name: "Database"
+    description: "Primary database"
+    check: !tcpPing
+      host: "127.0.0.1"
+      port: {}
+"#,
+            port
+        );
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        AppState::new(config.for_single_run(), vec!["unused.yaml".to_string()])
+    }
+
+    #[tokio::test]
+    async fn test_once_exit_code_reflects_failures() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert_eq!(run_once(app_state(port)).await, ExitCode::SUCCESS);
+
+        drop(listener);
+        let state = app_state(port);
+        assert_eq!(run_once(state.clone()).await, ExitCode::FAILURE);
+        assert_eq!(state.get_all_services().await[0].total_checks, 1);
+    }
 }
@@ -1,6 +1,10 @@
+use std::io::IsTerminal;
 use std::path::Path;
-use clap::{Parser, Subcommand};
-use healthcheck::{Config, TelegramClient};
+use std::process::ExitCode;
+use std::time::Instant;
+use clap::{Args, Parser, Subcommand};
+use owo_colors::OwoColorize;
+use healthcheck::{Config, State};
 
 const CONFIG_ENV: &str = "HEALTHCHECK_CONFIG";
 const CONFIG_VAL: &str = "healthcheck.yaml";
@@ -13,10 +17,96 @@ struct Cli {
     #[arg(short, long, default_value = CONFIG_VAL)]
     config: String,
 
+    #[command(flatten)]
+    output: OutputArgs,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Args, Clone, Copy, Debug)]
+struct OutputArgs {
+    /// Print check details and debug logs
+    #[arg(short, long, global = true, conflicts_with = "quiet")]
+    verbose: bool,
+
+    /// Only print results, warnings and errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Disable colored output (also disabled by NO_COLOR or when not writing to a terminal)
+    #[arg(long, global = true)]
+    no_color: bool,
+}
+
+// How much a line matters, `--quiet` keeps only essential ones and `--verbose` adds details
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+enum Level {
+    Detail,
+    Info,
+    Essential,
+}
+
+// Verbosity and color of the CLI's own output
+struct Output {
+    level: Level,
+    color: bool,
+}
+
+impl Output {
+    fn new(args: &OutputArgs, no_color_env: bool, terminal: bool) -> Self {
+        let level = if args.verbose {
+            Level::Detail
+        } else if args.quiet {
+            Level::Essential
+        } else {
+            Level::Info
+        };
+        Self { level, color: !args.no_color && !no_color_env && terminal }
+    }
+
+    fn tracing_level(&self) -> tracing::Level {
+        match self.level {
+            Level::Detail => tracing::Level::DEBUG,
+            Level::Info => tracing::Level::INFO,
+            Level::Essential => tracing::Level::ERROR,
+        }
+    }
+
+    // The line as printed, None when the verbosity suppresses it
+    fn line(&self, level: Level, text: &str) -> Option<String> {
+        (level >= self.level).then(|| text.to_string())
+    }
+
+    fn print(&self, level: Level, text: &str) {
+        if let Some(line) = self.line(level, text) {
+            println!("{}", line);
+        }
+    }
+
+    fn success(&self, text: &str) -> String {
+        if self.color { text.green().to_string() } else { text.to_string() }
+    }
+
+    fn warning(&self, text: &str) -> String {
+        if self.color { text.yellow().to_string() } else { text.to_string() }
+    }
+
+    fn failure(&self, text: &str) -> String {
+        if self.color { text.red().bold().to_string() } else { text.to_string() }
+    }
+
+    // Result line of a check, always printed
+    fn result(&self, state: &State) -> String {
+        match state {
+            State::Success => self.success("✓ Service check PASSED"),
+            State::Warning(reason) => self.warning(&format!("! Service check WARNING: {}", reason)),
+            State::Failure(reason) => self.failure(&format!("✗ Service check FAILED: {}", reason)),
+            State::Unknown => self.warning("? Service check returned UNKNOWN state"),
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Send a Telegram message
@@ -33,15 +123,55 @@ enum Commands {
     TestService {
         /// ID of the service to test
         id: String,
+
+        #[command(flatten)]
+        exit_codes: ExitCodes,
     },
+
+    /// Validate the configuration file and print warnings
+    Validate,
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    // Initialize basic tracing
-    tracing_subscriber::fmt::init();
+// Exit codes of `test-service` per check result, so CI pipelines can tell them apart
+#[derive(Args, Clone, Copy, Debug, PartialEq)]
+struct ExitCodes {
+    /// Exit code when the check passes
+    #[arg(long, default_value_t = 0)]
+    exit_success: u8,
+
+    /// Exit code when the check passes with a warning
+    #[arg(long, default_value_t = 0)]
+    exit_warning: u8,
+
+    /// Exit code when the check fails
+    #[arg(long, default_value_t = 1)]
+    exit_failure: u8,
 
+    /// Exit code when the check returns an unknown state
+    #[arg(long, default_value_t = 2)]
+    exit_unknown: u8,
+}
+
+impl ExitCodes {
+    fn for_state(&self, state: &State) -> u8 {
+        match state {
+            State::Success => self.exit_success,
+            State::Warning(_) => self.exit_warning,
+            State::Failure(_) => self.exit_failure,
+            State::Unknown => self.exit_unknown,
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<ExitCode> {
     let cli = Cli::parse();
+    // NO_COLOR disables color when set to any non-empty value (https://no-color.org)
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let output = Output::new(&cli.output, no_color_env, std::io::stdout().is_terminal());
+
+    // Initialize basic tracing
+    tracing_subscriber::fmt().with_max_level(output.tracing_level()).init();
 
     // Load configuration - check environment variable first
     let config_path = if cli.config == CONFIG_VAL {
@@ -53,34 +183,38 @@ async fn main() -> anyhow::Result<()> {
 
     match &cli.command {
         Commands::Telegram { message_type, message } => {
-            handle_telegram_command(&config, message_type, message).await?;
+            let mut config = config.clone();
+            config.resolve_telegram_chat_id().await?;
+            handle_telegram_command(&config, &output, message_type, message).await?;
         }
-        Commands::TestService { id } => {
-            handle_test_service_command(&config, id).await?;
+        Commands::TestService { id, exit_codes } => {
+            let state = handle_test_service_command(&config, &output, id).await?;
+            return Ok(ExitCode::from(exit_codes.for_state(&state)));
+        }
+        Commands::Validate => {
+            handle_validate_command(&config, &output);
         }
     }
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
 
 async fn handle_telegram_command(
     config: &Config,
+    output: &Output,
     message_type: &str,
     message: &str,
 ) -> anyhow::Result<()> {
-    let telegram = TelegramClient::new(
-        config.telegram_token.clone(),
-        config.telegram_chat_id,
-    );
+    let telegram = config.telegram_client();
 
     match message_type {
         "success" => {
             telegram.send_recovery("CLI", message).await?;
-            println!("Success message sent to Telegram");
+            output.print(Level::Info, "Success message sent to Telegram");
         }
         "error" => {
             telegram.send_alert("CLI", message).await?;
-            println!("Error message sent to Telegram");
+            output.print(Level::Info, "Error message sent to Telegram");
         }
         _ => {
             anyhow::bail!("Invalid message type: {}", message_type);
@@ -90,44 +224,109 @@ async fn handle_telegram_command(
     Ok(())
 }
 
+// Loading already rejected invalid configurations, so only warnings are left to report
+fn handle_validate_command(config: &Config, output: &Output) {
+    let warnings = config.warnings();
+    for warning in &warnings {
+        output.print(Level::Essential, &output.warning(&format!("! {}", warning)));
+    }
+    let summary = format!(
+        "✓ Configuration is valid ({} services, {} warnings)",
+        config.services.len(),
+        warnings.len()
+    );
+    output.print(Level::Essential, &output.success(&summary));
+}
+
 async fn handle_test_service_command(
     config: &Config,
+    output: &Output,
     id: &str,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<State> {
     // Find service in config
     let service = config
         .services
         .get(id)
         .ok_or_else(|| anyhow::anyhow!("Service with ID '{}' not found", id))?;
 
-    println!("Testing service: {}", service.name);
-    println!("Description: {}", service.description);
+    output.print(Level::Info, &format!("Testing service: {}", service.name));
+    output.print(Level::Info, &format!("Description: {}", service.description));
+    output.print(Level::Detail, &format!("Check: {:?}", service.check));
 
     if !service.enabled {
-        println!("Warning: Service is disabled in configuration");
+        output.print(Level::Info, &output.warning("Warning: Service is disabled in configuration"));
     }
 
     // Run the check
-    use healthcheck::config::{CheckType, State};
-    let state = match &service.check {
-        CheckType::Certificate(cert) => cert.check().await,
-        CheckType::Http(http) => http.check().await,
-        CheckType::TcpPing(tcp) => tcp.check().await,
-    };
+    let started = Instant::now();
+    let state = service.check(&config.check_context()).await;
+    output.print(Level::Detail, &format!("Completed in {}ms", started.elapsed().as_millis()));
 
     // Display result
-    match state {
-        State::Success => {
-            println!("✓ Service check PASSED");
-            Ok(())
-        }
-        State::Failure(reason) => {
-            println!("✗ Service check FAILED: {}", reason);
-            std::process::exit(1);
-        }
-        State::Unknown => {
-            println!("? Service check returned UNKNOWN state");
-            std::process::exit(2);
+    output.print(Level::Essential, &output.result(&state));
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use healthcheck::failure::FailureCategory;
+
+    fn exit_codes(args: &[&str]) -> ExitCodes {
+        let cli = Cli::try_parse_from(["healthcheck_cli", "test-service", "api"].iter().chain(args)).unwrap();
+        match cli.command {
+            Commands::TestService { exit_codes, .. } => exit_codes,
+            _ => unreachable!(),
         }
     }
+
+    fn output(args: &[&str]) -> Output {
+        let cli = Cli::try_parse_from(["healthcheck_cli"].iter().chain(args).chain(&["validate"])).unwrap();
+        Output::new(&cli.output, false, true)
+    }
+
+    #[test]
+    fn test_quiet_and_no_color_output() {
+        let failure = State::failure(FailureCategory::Connection, "Connection refused");
+
+        // Defaults: informational lines and colored results, no details
+        let default = output(&[]);
+        assert!(default.line(Level::Info, "Testing service: API").is_some());
+        assert!(default.line(Level::Detail, "Completed in 5ms").is_none());
+        assert!(default.result(&failure).contains('\x1b'));
+
+        let quiet = output(&["--quiet"]);
+        assert!(quiet.line(Level::Info, "Testing service: API").is_none());
+        assert_eq!(quiet.line(Level::Essential, "✓ Service check PASSED").as_deref(), Some("✓ Service check PASSED"));
+
+        let verbose = output(&["-v"]);
+        assert!(verbose.line(Level::Detail, "Completed in 5ms").is_some());
+        assert!(Cli::try_parse_from(["healthcheck_cli", "-v", "-q", "validate"]).is_err());
+
+        let plain = output(&["--no-color"]);
+        assert_eq!(plain.result(&failure), "✗ Service check FAILED: Connection refused");
+        assert!(!plain.warning("! warning").contains('\x1b'));
+        // NO_COLOR and non-terminal output disable color as well
+        let cli = Cli::try_parse_from(["healthcheck_cli", "validate"]).unwrap();
+        assert!(!Output::new(&cli.output, true, true).result(&failure).contains('\x1b'));
+        assert!(!Output::new(&cli.output, false, false).result(&failure).contains('\x1b'));
+    }
+
+    #[test]
+    fn test_exit_codes_per_state() {
+        let warning = State::Warning("Certificate expires in 5 days".to_string());
+        let failure = State::failure(FailureCategory::Connection, "Connection refused");
+
+        let defaults = exit_codes(&[]);
+        assert_eq!(defaults.for_state(&State::Success), 0);
+        assert_eq!(defaults.for_state(&warning), 0);
+        assert_eq!(defaults.for_state(&failure), 1);
+        assert_eq!(defaults.for_state(&State::Unknown), 2);
+
+        let custom = exit_codes(&["--exit-warning", "10", "--exit-failure", "20", "--exit-unknown", "3"]);
+        assert_eq!(custom.for_state(&State::Success), 0);
+        assert_eq!(custom.for_state(&warning), 10);
+        assert_eq!(custom.for_state(&failure), 20);
+        assert_eq!(custom.for_state(&State::Unknown), 3);
+    }
 }
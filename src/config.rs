@@ -1,503 +1,7455 @@
-use std::{collections::HashMap, hash::Hash, sync::Arc, time::Duration};
+use std::{
+    any::Any,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    future::Future,
+    hash::Hash,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    panic::AssertUnwindSafe,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
 
 use chrono::{DateTime, Utc};
+use encoding_rs::Encoding;
+use futures::FutureExt;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
-use tracing;
+use tokio::sync::{OwnedSemaphorePermit, RwLock, Semaphore};
+use tracing::{self, Instrument};
 
-use crate::telegram::TelegramClient;
+use crate::dead_letter::{self, DeadLetter};
+use crate::dns;
+use crate::email::{self, EmailClient};
+use crate::events::{CheckEvent, EventLog};
+use crate::failure::{FailureCategory, FailureReason};
+use crate::grpc_web;
+use crate::history::{self, CheckRecord, Incident, MaintenanceWindow, UptimeReport, MAX_INCIDENTS};
+use crate::json_diff::{self, JsonDocument};
+use crate::postgres::{self, PostgresResults, ResultWriter};
+use crate::promql;
+use crate::resolver::{self, CachingResolver, Resolver, SystemResolver};
+use crate::slack::{self, SlackClient};
+use crate::slo::{self, LatencyHistogram, SloReport, DEFAULT_LATENCY_BUCKETS_MS, LATENCY_WINDOW};
+use crate::socks;
+use crate::sqlite::StateStore;
+use crate::telegram::{self, ChatId, NotificationEmoji, TelegramClient};
+
+// Number of check intervals a monitoring loop may miss before it is considered stalled
+const DEFAULT_WATCHDOG_INTERVALS: u64 = 3;
+// Number of check intervals after which the last result is reported as Unknown
+const DEFAULT_STALE_INTERVALS: u64 = 3;
+// How often the watchdog looks for stalled monitoring loops
+const WATCHDOG_PERIOD: Duration = Duration::from_secs(10);
+// Upper bound for a whole check, including DNS resolution, unless overridden per service
+const DEFAULT_CHECK_TIMEOUT_MS: u64 = 30000;
+// Number of times a failed notification is retried before it goes to the dead-letter log
+const DEFAULT_NOTIFICATION_RETRIES: u32 = 3;
+// Delay before the first notification retry, doubled for each further one
+const NOTIFICATION_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+// Long-poll timeout when waiting for Telegram button presses
+const TELEGRAM_POLL_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_HEALTH_WEIGHT: u32 = 1;
+// Number of most recent check results kept per service for /api/services/:id/history
+const DEFAULT_HISTORY_SIZE: usize = 100;
+// Days check results are kept in `database_path`, the counters outlive them
+const DEFAULT_DATABASE_RETENTION_DAYS: u64 = 30;
+// Minimum time between two warnings about failing database writes
+const DEFAULT_DATABASE_ALERT_INTERVAL_MS: u64 = 3600000;
+// Environment variables starting with this override configuration values
+const ENV_OVERRIDE_PREFIX: &str = "HEALTHCHECK__";
+// Backoff bounds for restarting a panicked monitoring task
+const RESPAWN_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RESPAWN_BACKOFF_MAX: Duration = Duration::from_secs(60);
+// Time an on_failure_command may take before it is killed, and the output kept of it
+const ON_FAILURE_COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_REMEDIATION_OUTPUT: usize = 2000;
 
 #[derive(Default, Hash, Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub enum State {
     #[default]
     Unknown,
     Success,
-    Failure(String),
+    // The service works but needs attention
+    Warning(String),
+    // Serialized as its message, the category is reported separately as `ServiceState::failure`
+    Failure(#[serde(with = "crate::failure::detail")] FailureReason),
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
-pub struct ServiceHttp {
-    pub url: String,
-    pub expected_status: Option<u16>,
+impl State {
+    pub fn failure(category: FailureCategory, detail: impl Into<String>) -> Self {
+        State::Failure(FailureReason::new(category, detail))
+    }
 }
 
-impl ServiceHttp {
-    pub async fn check(&self) -> State {
-        tracing::debug!("Starting HTTP check for url: {}", self.url);
-
-        let result = match reqwest::get(&self.url).await {
-            Ok(response) => {
-                let status = response.status().as_u16();
-                let expected = self.expected_status.unwrap_or(200);
-                if status == expected {
-                    State::Success
-                } else {
-                    State::Failure(format!("Unexpected status: {}", status))
-                }
-            }
-            Err(e) => State::Failure(format!("Request failed: {}", e)),
-        };
+// Status is a flat, machine-readable summary of a State for API clients
+#[derive(Default, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Down,
+    Warning,
+    #[default]
+    Unknown,
+}
 
-        tracing::debug!(
-            "HTTP check for url: {} completed with state: {:?}",
-            self.url,
-            result
-        );
-        result
+impl From<&State> for Status {
+    fn from(state: &State) -> Self {
+        match state {
+            State::Success => Status::Up,
+            State::Failure(_) => Status::Down,
+            State::Warning(_) => Status::Warning,
+            State::Unknown => Status::Unknown,
+        }
     }
 }
-#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
-pub struct ServiceCertificate {
-    pub host: String,
-    pub port: u16,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub days_before_expiry: Option<u64>,
-}
 
-impl ServiceCertificate {
-    pub async fn check(&self) -> State {
-        tracing::debug!(
-            "Starting certificate check for host: {}:{}",
-            self.host,
-            self.port
-        );
+// BodyFormat is a document format the HTTP response body must parse as
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyFormat {
+    Json,
+    Xml,
+}
 
-        let result = self.check_certificate().await;
+// HeaderThreshold bounds a numeric response header, e.g. a queue depth
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HeaderThreshold {
+    pub header: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+}
 
-        tracing::debug!(
-            "Certificate check for host: {}:{} completed with state: {:?}",
-            self.host,
-            self.port,
-            result
-        );
-        result
+// f64 has no Hash, the bounds are hashed by their bits
+impl std::hash::Hash for HeaderThreshold {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.header.hash(state);
+        self.min.map(f64::to_bits).hash(state);
+        self.max.map(f64::to_bits).hash(state);
     }
+}
 
-    async fn check_certificate(&self) -> State {
-        use native_tls::TlsConnector;
-        use tokio::net::TcpStream;
-
-        // Connect to the server
-        let addr = format!("{}:{}", self.host, self.port);
-        let tcp_stream = match TcpStream::connect(&addr).await {
-            Ok(stream) => stream,
-            Err(e) => return State::Failure(format!("TCP connection failed: {}", e)),
-        };
-
-        // Create TLS connector
-        let connector = match TlsConnector::new() {
-            Ok(c) => c,
-            Err(e) => return State::Failure(format!("Failed to create TLS connector: {}", e)),
-        };
-
-        let connector = tokio_native_tls::TlsConnector::from(connector);
-
-        // Perform TLS handshake
-        let tls_stream = match connector.connect(&self.host, tcp_stream).await {
-            Ok(stream) => stream,
-            Err(e) => return State::Failure(format!("TLS handshake failed: {}", e)),
-        };
-
-        // Get the peer certificate
-        let cert = match tls_stream.get_ref().peer_certificate() {
-            Ok(Some(cert)) => cert,
-            Ok(None) => return State::Failure("No peer certificate found".to_string()),
-            Err(e) => return State::Failure(format!("Failed to get peer certificate: {}", e)),
-        };
-
-        // Parse the certificate to get expiration date
-        let der = cert.to_der().unwrap();
-        let (_, parsed_cert) = match x509_parser::parse_x509_certificate(&der) {
-            Ok(result) => result,
-            Err(e) => return State::Failure(format!("Failed to parse certificate: {}", e)),
-        };
-
-        // Get the not_after timestamp
-        let not_after = parsed_cert.validity().not_after;
-        let expiry_timestamp = not_after.timestamp();
-
-        // Calculate days until expiration
-        let now = chrono::Utc::now().timestamp();
-        let seconds_until_expiry = expiry_timestamp - now;
-        let days_until_expiry = seconds_until_expiry / 86400; // 86400 seconds in a day
-
-        let threshold = self.days_before_expiry.unwrap_or(30);
-
-        if days_until_expiry < 0 {
-            State::Failure(format!("Certificate expired {} days ago", -days_until_expiry))
-        } else if days_until_expiry < threshold as i64 {
-            State::Failure(format!(
-                "Certificate expires in {} days (threshold: {} days)",
-                days_until_expiry, threshold
-            ))
-        } else {
-            State::Success
+impl HeaderThreshold {
+    fn check(&self, headers: &reqwest::header::HeaderMap) -> Result<(), String> {
+        let value = headers
+            .get(&self.header)
+            .ok_or_else(|| format!("Missing header {}", self.header))?;
+        let value = value.to_str().unwrap_or("").trim();
+        let number: f64 = value
+            .parse()
+            .map_err(|_| format!("Header {} is not a number: {}", self.header, value))?;
+        if let Some(min) = self.min.filter(|min| number < *min) {
+            return Err(format!("Header {} is {}, below minimum {}", self.header, value, min));
+        }
+        if let Some(max) = self.max.filter(|max| number > *max) {
+            return Err(format!("Header {} is {}, above maximum {}", self.header, value, max));
         }
+        Ok(())
     }
 }
 
+// CacheHeaders are expectations on a response's caching headers, e.g. for content behind a CDN
 #[derive(Deserialize, Serialize, Debug, Clone, Hash)]
-pub struct ServiceTcpPing {
-    pub host: String,
-    pub port: u16,
+pub struct CacheHeaders {
+    // Cache-Control must allow caching for at least this many seconds, s-maxage counting over max-age
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub timeout_ms: Option<u64>,
+    pub min_max_age: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_etag: Option<bool>,
+    // Upper bound on the Age header, the seconds a cache has held the response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age_header: Option<u64>,
 }
 
-impl ServiceTcpPing {
-    pub async fn check(&self) -> State {
-        tracing::debug!("Starting TCP ping for host: {}:{}", self.host, self.port);
+impl CacheHeaders {
+    fn is_empty(&self) -> bool {
+        self.min_max_age.is_none() && self.require_etag.is_none() && self.max_age_header.is_none()
+    }
 
-        let addr = format!("{}:{}", self.host, self.port);
-        let timeout_ms = self.timeout_ms.unwrap_or(1000);
-        let timeout = Duration::from_millis(timeout_ms);
+    fn check(&self, headers: &reqwest::header::HeaderMap) -> Result<(), String> {
+        use reqwest::header::{AGE, CACHE_CONTROL, ETAG};
 
-        let result =
-            match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(&addr)).await {
-                Ok(Ok(_)) => State::Success,
-                Ok(Err(e)) => State::Failure(format!("Connection failed: {}", e)),
-                Err(_) => State::Failure(format!("Timeout after {}ms", timeout_ms)),
+        let header = |name| headers.get(name).map(|v| v.to_str().unwrap_or("").trim());
+        if let Some(min) = self.min_max_age {
+            let cache_control = header(CACHE_CONTROL).ok_or_else(|| "Missing header Cache-Control".to_string())?;
+            let directives: Vec<(String, &str)> = cache_control
+                .split(',')
+                .map(|directive| match directive.split_once('=') {
+                    Some((name, value)) => (name.trim().to_lowercase(), value.trim().trim_matches('"')),
+                    None => (directive.trim().to_lowercase(), ""),
+                })
+                .collect();
+            let directive = |name: &str| directives.iter().find(|(n, _)| n == name).map(|(_, value)| *value);
+            if directive("no-store").is_some() {
+                return Err(format!("Cache-Control forbids caching: {}", cache_control));
+            }
+            let (name, value) = match (directive("s-maxage"), directive("max-age")) {
+                (Some(value), _) => ("s-maxage", value),
+                (None, Some(value)) => ("max-age", value),
+                (None, None) => return Err(format!("Cache-Control has no max-age: {}", cache_control)),
             };
-
-        tracing::debug!(
-            "TCP ping for host: {}:{} completed with state: {:?}",
-            self.host,
-            self.port,
-            result
-        );
-        result
+            let seconds: u64 = value
+                .parse()
+                .map_err(|_| format!("Cache-Control {} is not a number: {}", name, value))?;
+            if seconds < min {
+                return Err(format!("Cache-Control {}={} is below minimum {}", name, seconds, min));
+            }
+        }
+        if self.require_etag == Some(true) && header(ETAG).is_none_or(str::is_empty) {
+            return Err("Missing header ETag".to_string());
+        }
+        // Responses served fresh from the origin have no Age
+        if let (Some(max), Some(value)) = (self.max_age_header, header(AGE)) {
+            let age: u64 = value.parse().map_err(|_| format!("Header Age is not a number: {}", value))?;
+            if age > max {
+                return Err(format!("Header Age is {}, above maximum {}", age, max));
+            }
+        }
+        Ok(())
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
-#[serde(rename_all = "camelCase")]
-pub enum CheckType {
-    Http(ServiceHttp),
-    Certificate(ServiceCertificate),
-    #[serde(rename = "tcpPing")]
-    TcpPing(ServiceTcpPing),
+// IpVersion restricts connections to one IP family
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IpVersion {
+    Ipv4,
+    Ipv6,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
-pub struct Service {
-    pub enabled: bool,
-    pub name: String,
-    pub description: String,
+// ConnectionOptions are the connection settings shared by the HTTP, TCP and certificate checks,
+// written directly on the check
+#[derive(Deserialize, Serialize, Debug, Clone, Default, Hash)]
+pub struct ConnectionOptions {
+    // Bounds connecting (and for HTTP the whole request), each check type has its own default
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub check_interval_success: Option<u64>,
+    pub timeout_ms: Option<u64>,
+    // Local address connections originate from, overriding the service and global ones
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub check_interval_fail: Option<u64>,
+    pub source_address: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub notify_failures: Option<u64>,
+    pub ip_version: Option<IpVersion>,
+    // SOCKS5 proxy such as Tor, e.g. "socks5h://127.0.0.1:9050", the host is resolved by the proxy
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub rereport: Option<u64>,
-    pub check: CheckType,
+    pub socks_proxy: Option<String>,
 }
 
-impl Service {
-    pub async fn run(&self, id: String, app_state: AppState) {
-        loop {
-            tracing::info!("Running health check for service: {}", self.name);
-
-            let state = match &self.check {
-                CheckType::Certificate(cert) => cert.check().await,
-                CheckType::Http(http) => http.check().await,
-                CheckType::TcpPing(tcp) => tcp.check().await,
-            };
+impl ConnectionOptions {
+    pub fn timeout_ms(&self, default: u64) -> u64 {
+        self.timeout_ms.unwrap_or(default)
+    }
 
-            // Log the result
-            match &state {
-                State::Success => tracing::info!("Service '{}' check succeeded", self.name),
-                State::Failure(reason) => tracing::warn!("Service '{}' check failed: {}", self.name, reason),
-                State::Unknown => tracing::info!("Service '{}' check returned unknown state", self.name),
-            }
+    // Address connections are bound to. Binding the unspecified address of a family
+    // restricts connections to it when `ip_version` is set without a matching source address.
+    pub fn local_address(&self, ctx: &CheckContext) -> Option<IpAddr> {
+        let source = self
+            .source_address
+            .as_deref()
+            .and_then(|a| a.parse().ok())
+            .or(ctx.source_address);
+        match self.ip_version {
+            Some(IpVersion::Ipv4) if !source.is_some_and(|s| s.is_ipv4()) => Some(Ipv4Addr::UNSPECIFIED.into()),
+            Some(IpVersion::Ipv6) if !source.is_some_and(|s| s.is_ipv6()) => Some(Ipv6Addr::UNSPECIFIED.into()),
+            _ => source,
+        }
+    }
 
-            // Update state in the global store
-            app_state.set_state(id.clone(), state.clone()).await;
+    // Open a TCP connection, through the proxy when one is configured
+    pub async fn connect(&self, ctx: &CheckContext, host: &str, port: u16) -> std::io::Result<tokio::net::TcpStream> {
+        socks::connect_via(
+            ctx.resolver.as_ref(),
+            self.socks_proxy.as_deref(),
+            host,
+            port,
+            self.local_address(ctx),
+        )
+        .await
+    }
 
-            // Get global config defaults
-            let config = app_state.get_config().await;
+    // HTTP client honoring the options, HTTP requests have no timeout unless one is set
+    pub fn http_client(&self, ctx: &CheckContext) -> reqwest::Result<reqwest::Client> {
+        self.http_client_builder(ctx)?.build()
+    }
 
-            // Determine sleep interval based on state, using service override or global default
-            let interval = match &state {
-                State::Success => self.check_interval_success.unwrap_or(config.check_interval_success),
-                State::Failure(_) => self.check_interval_fail.unwrap_or(config.check_interval_fail),
-                State::Unknown => self.check_interval_success.unwrap_or(config.check_interval_success),
-            };
+    // Client builder with the connection options applied, for check-specific settings
+    pub fn http_client_builder(&self, ctx: &CheckContext) -> reqwest::Result<reqwest::ClientBuilder> {
+        let mut builder = reqwest::Client::builder()
+            .local_address(self.local_address(ctx))
+            .dns_resolver(Arc::new(resolver::HttpResolver(ctx.resolver.clone())));
+        if let Some(timeout_ms) = self.timeout_ms {
+            builder = builder.timeout(Duration::from_millis(timeout_ms));
+        }
+        if let Some(proxy) = &self.socks_proxy {
+            builder = builder.proxy(reqwest::Proxy::all(socks::proxy_url(proxy))?);
+        }
+        Ok(builder)
+    }
 
-            tracing::debug!("Service '{}' next check in {}ms", self.name, interval);
-            tokio::time::sleep(Duration::from_millis(interval)).await;
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.timeout_ms == Some(0) {
+            anyhow::bail!("timeout_ms must be greater than zero");
         }
+        if let Some(address) = &self.source_address {
+            validate_source_address(address)?;
+            let ipv4 = address.parse::<IpAddr>().is_ok_and(|a| a.is_ipv4());
+            if self.ip_version.is_some_and(|version| (version == IpVersion::Ipv4) != ipv4) {
+                anyhow::bail!("source_address '{}' doesn't match ip_version", address);
+            }
+        }
+        if let Some(proxy) = &self.socks_proxy {
+            socks::parse_proxy(proxy)?;
+        }
+        Ok(())
     }
 }
 
-// ServiceState represents the current runtime state of a service for API responses
-#[derive(Deserialize, Serialize, Debug, Clone)]
-pub struct ServiceState {
-    pub name: String,
-    pub description: String,
-    pub state: State,
-    pub last_check: DateTime<Utc>,
-    pub consecutive_failures: u64,
-    pub total_checks: u64,
-    pub successful_checks: u64,
-    pub failed_checks: u64,
-    pub uptime_start: Option<DateTime<Utc>>,
+// UrlMode decides whether all or any of an HTTP check's URLs must pass
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Hash, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UrlMode {
+    #[default]
+    All,
+    Any,
 }
 
-// Config represents the application configuration loaded from file
-#[derive(Deserialize, Serialize, Debug, Clone)]
-pub struct Config {
-    pub telegram_token: String,
-    pub telegram_chat_id: i64,
-    pub check_interval_success: u64,
-    pub check_interval_fail: u64,
-    pub notify_failures: u64,
-    pub rereport: u64,
-    pub services: HashMap<String, Service>,
-    pub web_port: Option<u16>,
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct ServiceHttp {
+    // May be left out when `urls` is given
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub url: String,
+    // Further URLs checked concurrently, e.g. the instances behind a load balancer
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub api_bearer_token: Option<String>,
+    pub urls: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<UrlMode>,
+    // Defaults to GET
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    // Request body, sent as application/json when it parses as JSON
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    // Request headers, e.g. Authorization or X-Api-Key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headers: Option<BTreeMap<String, String>>,
+    pub expected_status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_format: Option<BodyFormat>,
+    // Charset the Content-Type must declare and the body must decode in without errors, e.g. "utf-8"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_charset: Option<String>,
+    // Text the body must contain, e.g. a marker rendered only by a healthy page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_contains: Option<String>,
+    // Regular expression the body must match
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_regex: Option<String>,
+    // JSON document the body must equal, to catch schema drift
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_json: Option<JsonDocument>,
+    // Paths left out of the `expected_json` comparison, e.g. "meta.generated_at" or "items[*].id"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignore_json_fields: Option<Vec<String>>,
+    // Numeric header that must stay within bounds, e.g. X-Queue-Depth
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header_threshold: Option<HeaderThreshold>,
+    // Expected Cache-Control, ETag and Age headers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_headers: Option<CacheHeaders>,
+    // Defer the check on 429 with Retry-After/X-RateLimit-Reset instead of failing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub respect_rate_limits: Option<bool>,
+    // Requests per URL and check, for noisy endpoints (default: 1)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub samples: Option<u32>,
+    // Samples that must pass for the URL to be healthy (default: more than half)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub majority_threshold: Option<u32>,
+    // Keep cookies between requests of the check, e.g. a CSRF or session cookie
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cookie_store: Option<bool>,
+    // Requested before the check so its cookies are sent along, implies `cookie_store`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warmup_url: Option<String>,
+    // Bound on establishing the connection, reported separately from slow responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_ms: Option<u64>,
+    // Bound on the whole request, overrides `timeout_ms`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_timeout_ms: Option<u64>,
+    #[serde(flatten)]
+    pub connection: ConnectionOptions,
 }
 
-impl Config {
-    pub fn load(path: &std::path::Path) -> anyhow::Result<Self> {
-        let contents = std::fs::read_to_string(path)?;
-        let config: Config = serde_yaml::from_str(&contents)?;
-        Ok(config)
+// Bound on a request when neither `timeout_ms` nor `read_timeout_ms` is set, so a server
+// that accepts the connection but never responds can't stall the check
+const DEFAULT_HTTP_TIMEOUT_MS: u64 = 10000;
+
+impl ServiceHttp {
+    // Every URL the check requests, `url` first
+    pub fn targets(&self) -> Vec<&str> {
+        std::iter::once(self.url.as_str())
+            .filter(|url| !url.is_empty())
+            .chain(self.urls.iter().flatten().map(String::as_str))
+            .collect()
     }
-}
 
-// AppState manages the runtime state of all services
-#[derive(Clone)]
-pub struct AppState {
-    services: Arc<RwLock<HashMap<String, ServiceState>>>,
-    config: Arc<RwLock<Config>>,
-    task_handles: Arc<RwLock<HashMap<String, tokio::task::JoinHandle<()>>>>,
-    telegram: Arc<TelegramClient>,
-    config_path: Arc<String>,
-}
+    pub async fn check(&self, ctx: &CheckContext) -> State {
+        let cookie_store = self.cookie_store.unwrap_or(false) || self.warmup_url.is_some();
+        let client = self.connection.http_client_builder(ctx).and_then(|mut builder| {
+            builder = builder.cookie_store(cookie_store).timeout(Duration::from_millis(self.timeout_ms()));
+            if let Some(timeout_ms) = self.connect_timeout_ms {
+                builder = builder.connect_timeout(Duration::from_millis(timeout_ms));
+            }
+            builder.build()
+        });
+        let client = match client {
+            Ok(client) => client,
+            Err(e) => return State::failure(FailureCategory::Configuration, format!("Failed to create HTTP client: {}", e)),
+        };
+        // Compiled once for every URL and sample of the check
+        let body_regex = match self.body_regex.as_deref().map(regex::Regex::new).transpose() {
+            Ok(body_regex) => body_regex,
+            Err(e) => return State::failure(FailureCategory::Configuration, format!("Invalid body_regex: {}", e)),
+        };
+        let body_regex = body_regex.as_ref();
 
-impl AppState {
-    pub fn new(config: Config, config_path: String) -> Self {
-        let now = Utc::now();
-        let services = config
-            .services
+        if let Some(url) = &self.warmup_url {
+            tracing::debug!("Warm-up request to {}", url);
+            match client.get(url).send().await {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => {
+                    let status = response.status().as_u16();
+                    return State::Failure(
+                        FailureReason::new(FailureCategory::Status, format!("Warm-up request returned status {}", status))
+                            .with_code(status),
+                    );
+                }
+                Err(e) => return State::failure(FailureCategory::Connection, format!("Warm-up request failed: {}", e)),
+            }
+        }
+
+        let targets = self.targets();
+        if let [url] = targets.as_slice() {
+            return self.sample_url(&client, url, body_regex, ctx).await;
+        }
+
+        let results =
+            futures::future::join_all(targets.iter().map(|url| self.sample_url(&client, url, body_regex, ctx))).await;
+        if ctx.rate_limit_delay.lock().unwrap().is_some() {
+            return State::Unknown;
+        }
+        let failed = results.iter().filter(|state| matches!(state, State::Failure(_))).count();
+        let mode = self.mode.unwrap_or_default();
+        let healthy = match mode {
+            UrlMode::All => failed == 0,
+            UrlMode::Any => failed < results.len(),
+        };
+        if healthy {
+            return State::Success;
+        }
+
+        // The first failed URL's failure is the check's
+        let Some(first) = results.iter().find_map(|state| match state {
+            State::Failure(reason) => Some(reason.clone()),
+            _ => None,
+        }) else {
+            return State::Success;
+        };
+        let lines: Vec<String> = targets
             .iter()
-            .filter(|(_, service)| service.enabled)
-            .map(|(id, service)| {
-                (
-                    id.clone(),
-                    ServiceState {
-                        name: service.name.clone(),
-                        description: service.description.clone(),
-                        state: State::Unknown,
-                        last_check: now,
-                        consecutive_failures: 0,
-                        total_checks: 0,
-                        successful_checks: 0,
-                        failed_checks: 0,
-                        uptime_start: None,
-                    },
-                )
+            .zip(&results)
+            .map(|(url, state)| match state {
+                State::Failure(reason) => format!("{}: {}", url, reason),
+                _ => format!("{}: OK", url),
             })
             .collect();
+        let summary = match mode {
+            UrlMode::All => format!("{} of {} URLs failed", failed, results.len()),
+            UrlMode::Any => format!("All {} URLs failed", results.len()),
+        };
+        State::Failure(first.map_detail(|_| format!("{}\n{}", summary, lines.join("\n"))))
+    }
 
-        // Create Telegram client
-        let telegram = Arc::new(TelegramClient::new(
-            config.telegram_token.clone(),
-            config.telegram_chat_id,
-        ));
+    // Request to `url` with the configured method, headers and body
+    fn request(&self, client: &reqwest::Client, url: &str) -> Result<reqwest::RequestBuilder, FailureReason> {
+        use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
 
-        Self {
-            services: Arc::new(RwLock::new(services)),
-            config: Arc::new(RwLock::new(config)),
-            task_handles: Arc::new(RwLock::new(HashMap::new())),
-            telegram,
-            config_path: Arc::new(config_path),
+        let mut headers = HeaderMap::new();
+        for (name, value) in self.headers.iter().flatten() {
+            let name = HeaderName::from_bytes(name.trim().as_bytes())
+                .map_err(|_| FailureReason::new(FailureCategory::Configuration, format!("Invalid header name: {}", name)))?;
+            let value = HeaderValue::from_str(value).map_err(|_| {
+                FailureReason::new(FailureCategory::Configuration, format!("Invalid value for header {}", name))
+            })?;
+            headers.insert(name, value);
+        }
+        let mut request = client.request(parse_method(self.method.as_deref())?, url);
+        if let Some(body) = &self.body {
+            if !headers.contains_key(CONTENT_TYPE) && serde_json::from_str::<serde::de::IgnoredAny>(body).is_ok() {
+                headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            }
+            request = request.body(body.clone());
         }
+        Ok(request.headers(headers))
     }
 
-    pub async fn set_state(&self, id: String, state: State) {
-        // Determine notification action before modifying state
-        let notification = {
-            let mut services = self.services.write().await;
-            if let Some(service_state) = services.get_mut(&id) {
-                let now = Utc::now();
-                let previous_failures = service_state.consecutive_failures;
-                let was_failing = previous_failures > 0;
+    // Bound on the whole request
+    fn timeout_ms(&self) -> u64 {
+        self.read_timeout_ms
+            .unwrap_or_else(|| self.connection.timeout_ms(DEFAULT_HTTP_TIMEOUT_MS))
+    }
 
-                service_state.state = state.clone();
-                service_state.last_check = now;
-                service_state.total_checks += 1;
+    // Failure reason of a request, naming the phase that timed out when separate timeouts are set
+    fn request_error(&self, e: &reqwest::Error) -> FailureReason {
+        let timeout = |detail| FailureReason::new(FailureCategory::Timeout, detail);
+        match (e.is_timeout(), e.is_connect(), self.connect_timeout_ms, self.read_timeout_ms) {
+            (true, true, Some(timeout_ms), _) => timeout(format!("Connect timed out after {}ms", timeout_ms)),
+            (true, false, _, Some(timeout_ms)) => timeout(format!("Response timed out after {}ms", timeout_ms)),
+            (true, ..) => timeout(format!("Timeout after {}ms", self.timeout_ms())),
+            _ if is_dns_error(e) => FailureReason::new(FailureCategory::Dns, format!("Request failed: {}", e)),
+            _ => FailureReason::new(FailureCategory::Connection, format!("Request failed: {}", e)),
+        }
+    }
+
+    // Check the URL `samples` times in a row, healthy when `majority_threshold` of them pass
+    async fn sample_url(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        body_regex: Option<&regex::Regex>,
+        ctx: &CheckContext,
+    ) -> State {
+        let samples = self.samples.unwrap_or(1);
+        if samples <= 1 {
+            return self.check_url(client, url, body_regex, ctx).await;
+        }
+
+        // A rate limited sample stops the sampling and counts neither as passed nor as taken
+        let mut taken = 0;
+        let mut passed = 0;
+        let mut healthy = State::Success;
+        let mut failure = None;
+        while taken < samples && ctx.rate_limit_delay.lock().unwrap().is_none() {
+            let state = self.check_url(client, url, body_regex, ctx).await;
+            if ctx.rate_limit_delay.lock().unwrap().is_some() {
+                break;
+            }
+            taken += 1;
+            match state {
+                State::Failure(reason) => failure = Some(reason),
+                state => {
+                    passed += 1;
+                    healthy = state;
+                }
+            }
+        }
+        if taken == 0 {
+            return State::Unknown;
+        }
+        let samples = taken;
+        let required = self.majority_threshold.unwrap_or(samples / 2 + 1).min(samples);
+
+        tracing::debug!("{} of {} samples of {} passed", passed, samples, url);
+        // The last failed sample's failure is the URL's
+        match failure {
+            Some(failure) if passed < required => State::Failure(failure.map_detail(|detail| {
+                format!("{} of {} samples passed ({} required): {}", passed, samples, required, detail)
+            })),
+            _ => healthy,
+        }
+    }
+
+    async fn check_url(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        body_regex: Option<&regex::Regex>,
+        ctx: &CheckContext,
+    ) -> State {
+        tracing::debug!("Starting HTTP check for url: {}", url);
+
+        let mut request = match self.request(client, url) {
+            Ok(request) => request,
+            Err(reason) => return State::Failure(reason),
+        };
+        if let Some(request_id) = &ctx.request_id {
+            request = request.header("X-Request-ID", request_id);
+        }
+        // Failed lookups are retried by the client's resolver
+        let response = request.send().await;
+
+        // Waiting within the check would count against its timeout, the next check is deferred instead
+        if let Ok(response) = &response {
+            if let Some(delay) = rate_limit_delay(response).filter(|_| self.respect_rate_limits.unwrap_or(false)) {
+                tracing::debug!("Rate limited by {}, deferring for {:?}", url, delay);
+                let mut deferred = ctx.rate_limit_delay.lock().unwrap();
+                *deferred = Some(deferred.map_or(delay, |deferred| deferred.max(delay)));
+                return State::Unknown;
+            }
+        }
+
+        let result = match response {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                let expected = self.expected_status.unwrap_or(200);
+                if status == expected {
+                    self.check_response(response, body_regex).await
+                } else {
+                    State::Failure(unexpected_status(status))
+                }
+            }
+            Err(e) => State::Failure(self.request_error(&e)),
+        };
+
+        tracing::debug!(
+            "HTTP check for url: {} completed with state: {:?}",
+            url,
+            result
+        );
+        result
+    }
+
+    // Validate the content type, charset, header threshold, cache headers, body content, body format and JSON document
+    // of a response with the expected status
+    async fn check_response(&self, response: reqwest::Response, body_regex: Option<&regex::Regex>) -> State {
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+        if let Some(expected) = &self.expected_content_type {
+            // Compare the media type only, ignoring parameters such as charset
+            let media_type = content_type.split(';').next().unwrap_or("").trim();
+            if !media_type.eq_ignore_ascii_case(expected.trim()) {
+                return State::failure(FailureCategory::Content, format!("Unexpected content type: {}", content_type));
+            }
+        }
+        let charset = match self.expected_charset.as_deref().map(|expected| declared_charset(expected, &content_type)) {
+            Some(Ok(encoding)) => Some(encoding),
+            Some(Err(reason)) => return State::Failure(reason),
+            None => None,
+        };
+        if let Some(threshold) = &self.header_threshold {
+            if let Err(reason) = threshold.check(response.headers()) {
+                return State::failure(FailureCategory::Content, reason);
+            }
+        }
+        if let Some(cache_headers) = &self.cache_headers {
+            if let Err(reason) = cache_headers.check(response.headers()) {
+                return State::failure(FailureCategory::Content, reason);
+            }
+        }
+
+        if charset.is_none()
+            && self.body_format.is_none()
+            && self.expected_json.is_none()
+            && self.body_contains.is_none()
+            && body_regex.is_none()
+        {
+            return State::Success;
+        }
+        let body = match charset {
+            Some(encoding) => match response.bytes().await {
+                Ok(bytes) => match decode_strict(encoding, &bytes) {
+                    Ok(body) => body,
+                    Err(reason) => return State::failure(FailureCategory::Content, reason),
+                },
+                Err(e) => return State::failure(FailureCategory::Connection, format!("Failed to read body: {}", e)),
+            },
+            None => match response.text().await {
+                Ok(body) => body,
+                Err(e) => return State::failure(FailureCategory::Connection, format!("Failed to read body: {}", e)),
+            },
+        };
+
+        if let Some(text) = self.body_contains.as_ref().filter(|text| !body.contains(text.as_str())) {
+            return State::failure(
+                FailureCategory::Content,
+                format!("Body does not contain \"{}\": {}", text, body_snippet(&body)),
+            );
+        }
+        if let Some(regex) = body_regex.filter(|regex| !regex.is_match(&body)) {
+            return State::failure(
+                FailureCategory::Content,
+                format!("Body does not match /{}/: {}", regex, body_snippet(&body)),
+            );
+        }
+
+        if let Some(JsonDocument(expected)) = &self.expected_json {
+            let actual = match serde_json::from_str::<serde_json::Value>(&body) {
+                Ok(actual) => actual,
+                Err(e) => return State::failure(FailureCategory::Content, format!("Body is not valid JSON: {}", e)),
+            };
+            let ignored = self.ignore_json_fields.as_deref().unwrap_or_default();
+            if let Some(difference) = json_diff::first_difference(&actual, expected, ignored) {
+                return State::failure(FailureCategory::Content, format!("JSON differs at {}", difference));
+            }
+        }
+
+        let Some(format) = self.body_format else {
+            return State::Success;
+        };
+        let parsed = match format {
+            BodyFormat::Json => serde_json::from_str::<serde_json::Value>(&body)
+                .map(|_| ())
+                .map_err(|e| format!("Body is not valid JSON: {}", e)),
+            BodyFormat::Xml => roxmltree::Document::parse(&body)
+                .map(|_| ())
+                .map_err(|e| format!("Body is not valid XML: {}", e)),
+        };
+        match parsed {
+            Ok(()) => State::Success,
+            Err(reason) => State::failure(FailureCategory::Content, reason),
+        }
+    }
+}
+
+// Encoding declared by the charset parameter of a Content-Type, which must be the `expected` one.
+// Labels naming the same encoding, e.g. "utf8" and "UTF-8", are equal.
+fn declared_charset(expected: &str, content_type: &str) -> Result<&'static Encoding, FailureReason> {
+    let expected = Encoding::for_label(expected.trim().as_bytes()).ok_or_else(|| {
+        FailureReason::new(FailureCategory::Configuration, format!("Invalid expected_charset: {}", expected))
+    })?;
+    let content = |detail| FailureReason::new(FailureCategory::Content, detail);
+    let declared = content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .map(|(_, value)| value.trim().trim_matches('"'))
+        .ok_or_else(|| content(format!("Content type declares no charset: {}", content_type)))?;
+    match Encoding::for_label(declared.as_bytes()) {
+        Some(encoding) if encoding == expected => Ok(encoding),
+        _ => Err(content(format!("Unexpected charset: {}, expected {}", declared, expected.name()))),
+    }
+}
+
+// Decode a body, failing on byte sequences invalid in the encoding and on replacement characters
+// left behind by an earlier lossy conversion
+fn decode_strict(encoding: &'static Encoding, bytes: &[u8]) -> Result<String, String> {
+    let Some(body) = encoding.decode_without_bom_handling_and_without_replacement(bytes) else {
+        let lossy = encoding.decode_without_bom_handling(bytes).0;
+        return Err(format!("Body is not valid {}: {}", encoding.name(), body_snippet(&lossy)));
+    };
+    if body.contains('\u{FFFD}') {
+        return Err(format!("Body contains replacement characters: {}", body_snippet(&body)));
+    }
+    Ok(body.into_owned())
+}
+
+// Start of a body for failure reasons, on a single line
+fn body_snippet(body: &str) -> String {
+    const MAX_SNIPPET_LENGTH: usize = 200;
+    let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+    match body.char_indices().nth(MAX_SNIPPET_LENGTH) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None if body.is_empty() => "empty body".to_string(),
+        None => body,
+    }
+}
+
+// Whether the request failed resolving the host, the resolver's error is kept in the error chain
+fn is_dns_error(error: &reqwest::Error) -> bool {
+    let mut source = std::error::Error::source(error);
+    while let Some(error) = source {
+        if error.is::<resolver::LookupError>() {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+// How long a 429 response asks to wait, from Retry-After (seconds or HTTP date)
+// or X-RateLimit-Reset (seconds or Unix timestamp)
+fn rate_limit_delay(response: &reqwest::Response) -> Option<Duration> {
+    if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let header = |name: &str| response.headers().get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+    let now = Utc::now();
+
+    // An unparseable Retry-After falls through to X-RateLimit-Reset
+    if let Some(value) = header("retry-after") {
+        if let Ok(seconds) = value.parse::<u64>() {
+            return Some(Duration::from_secs(seconds));
+        }
+        if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+            return Some((date.with_timezone(&Utc) - now).to_std().unwrap_or_default());
+        }
+    }
+
+    let reset = header("x-ratelimit-reset")?.parse::<i64>().ok()?;
+    // Values larger than a day are Unix timestamps rather than a number of seconds
+    let seconds = if reset > 86400 { reset - now.timestamp() } else { reset };
+    Some(Duration::from_secs(seconds.max(0) as u64))
+}
+
+// HTTP method given in the configuration, GET when not set
+fn parse_method(method: Option<&str>) -> Result<reqwest::Method, FailureReason> {
+    match method {
+        Some(method) => reqwest::Method::from_bytes(method.trim().to_uppercase().as_bytes())
+            .map_err(|_| FailureReason::new(FailureCategory::Configuration, format!("Invalid method: {}", method))),
+        None => Ok(reqwest::Method::GET),
+    }
+}
+
+// Failure of a response with an unexpected HTTP status
+fn unexpected_status(status: u16) -> FailureReason {
+    FailureReason::new(FailureCategory::Status, format!("Unexpected status: {}", status)).with_code(status)
+}
+
+// HttpStep is a single request of an HTTP flow
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct HttpStep {
+    pub url: String,
+    // Defaults to GET
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    // Sent URL-encoded, e.g. login credentials
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub form: Option<BTreeMap<String, String>>,
+    pub expected_status: Option<u16>,
+}
+
+// ServiceHttpFlow runs requests in order sharing cookies, e.g. log in then fetch a protected page
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct ServiceHttpFlow {
+    pub steps: Vec<HttpStep>,
+}
+
+impl ServiceHttpFlow {
+    pub async fn check(&self, ctx: &CheckContext) -> State {
+        let client = match reqwest::Client::builder()
+            .cookie_store(true)
+            .local_address(ctx.source_address)
+            .build()
+        {
+            Ok(client) => client,
+            Err(e) => return State::failure(FailureCategory::Configuration, format!("Failed to create HTTP client: {}", e)),
+        };
+
+        for (index, step) in self.steps.iter().enumerate() {
+            tracing::debug!("HTTP flow step {}: {}", index + 1, step.url);
+            if let Err(reason) = step.run(&client, ctx).await {
+                return State::Failure(reason.map_detail(|detail| format!("Step {} ({}): {}", index + 1, step.url, detail)));
+            }
+        }
+        State::Success
+    }
+}
+
+// ServiceGrpcWeb calls grpc.health.v1.Health/Check over gRPC-Web
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct ServiceGrpcWeb {
+    // Base URL of the server, the health method path is appended
+    pub url: String,
+    // Service name sent in the request, empty checks the server as a whole
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+}
+
+impl ServiceGrpcWeb {
+    pub async fn check(&self, ctx: &CheckContext) -> State {
+        let url = format!("{}{}", self.url.trim_end_matches('/'), grpc_web::HEALTH_CHECK_PATH);
+        tracing::debug!("Starting gRPC-Web health check for url: {}", url);
+
+        let client = match reqwest::Client::builder().local_address(ctx.source_address).build() {
+            Ok(client) => client,
+            Err(e) => return State::failure(FailureCategory::Configuration, format!("Failed to create HTTP client: {}", e)),
+        };
+        let mut request = client
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, grpc_web::CONTENT_TYPE)
+            .header("x-grpc-web", "1")
+            .body(grpc_web::encode_request(self.service.as_deref().unwrap_or("")));
+        if let Some(request_id) = &ctx.request_id {
+            request = request.header("X-Request-ID", request_id);
+        }
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(e) => return State::failure(FailureCategory::Connection, format!("Request failed: {}", e)),
+        };
+        if !response.status().is_success() {
+            return State::Failure(unexpected_status(response.status().as_u16()));
+        }
+
+        // Trailers-only responses carry the status in the HTTP headers
+        let mut header_trailers = grpc_web::HealthResponse::default();
+        for name in ["grpc-status", "grpc-message"] {
+            if let Some(value) = response.headers().get(name).and_then(|v| v.to_str().ok()) {
+                grpc_web::apply_trailer(&mut header_trailers, &format!("{}: {}", name, value));
+            }
+        }
+
+        let body = match response.bytes().await {
+            Ok(body) => body,
+            Err(e) => return State::failure(FailureCategory::Connection, format!("Failed to read body: {}", e)),
+        };
+        let decoded = match grpc_web::decode_response(&body) {
+            Ok(decoded) => decoded,
+            Err(reason) => return State::failure(FailureCategory::Content, reason),
+        };
+
+        let grpc_status = decoded.grpc_status.or(header_trailers.grpc_status);
+        let grpc_message = decoded.grpc_message.or(header_trailers.grpc_message).unwrap_or_default();
+        match (grpc_status, decoded.status) {
+            (None, _) => State::failure(FailureCategory::Content, "Response has no grpc-status trailer"),
+            (Some(0), Some(grpc_web::ServingStatus::Serving)) => State::Success,
+            (Some(0), Some(status)) => State::failure(FailureCategory::Content, format!("Health status: {}", status.name())),
+            (Some(0), None) => State::failure(FailureCategory::Content, "Response has no health check message"),
+            (Some(code), _) => State::Failure(
+                FailureReason::new(FailureCategory::Status, format!("gRPC status {}: {}", code, grpc_message)).with_code(code),
+            ),
+        }
+    }
+}
+
+// ServicePromQL evaluates a PromQL expression and compares its value to a threshold
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ServicePromQL {
+    // Base URL of the Prometheus server, e.g. "http://prometheus:9090"
+    pub endpoint: String,
+    pub query: String,
+    // One of >, >=, <, <=, ==, != - the check passes while `value <comparator> threshold` holds
+    pub comparator: String,
+    pub threshold: f64,
+}
+
+// f64 has no Hash, the threshold is hashed by its bits
+impl std::hash::Hash for ServicePromQL {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.endpoint.hash(state);
+        self.query.hash(state);
+        self.comparator.hash(state);
+        self.threshold.to_bits().hash(state);
+    }
+}
+
+impl ServicePromQL {
+    pub async fn check(&self, ctx: &CheckContext) -> State {
+        let url = format!("{}{}", self.endpoint.trim_end_matches('/'), promql::QUERY_PATH);
+        let mut url = match reqwest::Url::parse(&url) {
+            Ok(url) => url,
+            Err(e) => return State::failure(FailureCategory::Configuration, format!("Invalid endpoint: {}", e)),
+        };
+        url.query_pairs_mut().append_pair("query", &self.query);
+        tracing::debug!("Starting PromQL check for query: {}", self.query);
+
+        let client = match reqwest::Client::builder().local_address(ctx.source_address).build() {
+            Ok(client) => client,
+            Err(e) => return State::failure(FailureCategory::Configuration, format!("Failed to create HTTP client: {}", e)),
+        };
+        let mut request = client.get(url);
+        if let Some(request_id) = &ctx.request_id {
+            request = request.header("X-Request-ID", request_id);
+        }
+
+        // Prometheus answers bad queries with 400 and an error body, so the status isn't checked first
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(e) => return State::failure(FailureCategory::Connection, format!("Request failed: {}", e)),
+        };
+        let status = response.status();
+        let response: promql::QueryResponse = match response.json().await {
+            Ok(response) => response,
+            Err(_) => return State::Failure(unexpected_status(status.as_u16())),
+        };
+
+        let value = match promql::scalar_value(response) {
+            Ok(value) => value,
+            Err(reason) => return State::failure(FailureCategory::Content, reason),
+        };
+        match promql::compare(value, &self.comparator, self.threshold) {
+            Ok(true) => State::Success,
+            Ok(false) => State::failure(
+                FailureCategory::Content,
+                format!("Value {} violates {} {}", value, self.comparator, self.threshold),
+            ),
+            Err(reason) => State::failure(FailureCategory::Configuration, reason),
+        }
+    }
+}
+
+impl HttpStep {
+    async fn run(&self, client: &reqwest::Client, ctx: &CheckContext) -> Result<(), FailureReason> {
+        let mut request = client.request(parse_method(self.method.as_deref())?, &self.url);
+        if let Some(form) = &self.form {
+            request = request.form(form);
+        }
+        if let Some(request_id) = &ctx.request_id {
+            request = request.header("X-Request-ID", request_id);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| FailureReason::new(FailureCategory::Connection, format!("Request failed: {}", e)))?;
+        let status = response.status().as_u16();
+        if status != self.expected_status.unwrap_or(200) {
+            return Err(unexpected_status(status));
+        }
+        Ok(())
+    }
+}
+// ServiceExternalCheck runs a command: exit code 0 is success, anything else a failure
+// with the command's output as the reason
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct ServiceExternalCheck {
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    // Default: 10000, the command is killed when exceeded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+impl ServiceExternalCheck {
+    pub async fn check(&self, ctx: &CheckContext) -> State {
+        if !ctx.allow_command_checks {
+            return State::failure(FailureCategory::Configuration, "Command checks are disabled, see allow_command_checks");
+        }
+        tracing::debug!("Starting external check: {} {:?}", self.command, self.args);
+
+        let timeout_ms = self.timeout_ms.unwrap_or(10000);
+        let output = tokio::process::Command::new(&self.command)
+            .args(&self.args)
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let output = match tokio::time::timeout(Duration::from_millis(timeout_ms), output).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => return State::failure(FailureCategory::Command, format!("Failed to run {}: {}", self.command, e)),
+            Err(_) => return State::failure(FailureCategory::Timeout, format!("Command timed out after {}ms", timeout_ms)),
+        };
+        if output.status.success() {
+            return State::Success;
+        }
+
+        // stdout carries the reason, stderr and the exit status are the fallbacks
+        let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        let detail = match (stdout.is_empty(), stderr.is_empty(), output.status.code()) {
+            (false, _, _) => stdout,
+            (true, false, _) => stderr,
+            (true, true, Some(code)) => format!("Command exited with status {}", code),
+            (true, true, None) => "Command was terminated by a signal".to_string(),
+        };
+        let reason = FailureReason::new(FailureCategory::Command, detail);
+        State::Failure(match output.status.code() {
+            Some(code) => reason.with_code(code),
+            None => reason,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct ServiceCertificate {
+    pub host: String,
+    pub port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days_before_expiry: Option<u64>,
+    // PEM file with an additional root certificate to trust (e.g. an internal CA)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_cert: Option<String>,
+    // Skip chain, hostname and validity verification during the TLS handshake;
+    // the expiry threshold is still checked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accept_invalid_certs: Option<bool>,
+    // Require embedded signed certificate timestamps (certificate transparency)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_sct: Option<bool>,
+    // CA domain the host's CAA records must authorize, e.g. "letsencrypt.org"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caa_issuer: Option<String>,
+    // Warn whenever the leaf certificate changes, e.g. an unannounced renewal or interception
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_fingerprint_change: Option<bool>,
+    // `timeout_ms` bounds TCP connect and TLS handshake separately (default: 10000)
+    #[serde(flatten)]
+    pub connection: ConnectionOptions,
+}
+
+impl ServiceCertificate {
+    pub async fn check(&self, ctx: &CheckContext) -> State {
+        tracing::debug!(
+            "Starting certificate check for host: {}:{}",
+            self.host,
+            self.port
+        );
+
+        let result = self.check_certificate(ctx).await;
+
+        tracing::debug!(
+            "Certificate check for host: {}:{} completed with state: {:?}",
+            self.host,
+            self.port,
+            result
+        );
+        result
+    }
+
+    async fn check_certificate(&self, ctx: &CheckContext) -> State {
+        use native_tls::TlsConnector;
+
+        let timeout_ms = self.connection.timeout_ms(10000);
+        let timeout = Duration::from_millis(timeout_ms);
+
+        // Connect to the server
+        let connect = self.connection.connect(ctx, &self.host, self.port);
+        let tcp_stream = match tokio::time::timeout(timeout, connect).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(e)) => return State::failure(FailureCategory::Connection, format!("TCP connection failed: {}", e)),
+            Err(_) => {
+                return State::failure(FailureCategory::Timeout, format!("TCP connection timed out after {}ms", timeout_ms))
+            }
+        };
+
+        // Create TLS connector
+        let mut builder = TlsConnector::builder();
+        if let Some(ca_cert) = &self.ca_cert {
+            let pem = match std::fs::read(ca_cert) {
+                Ok(pem) => pem,
+                Err(e) => {
+                    return State::failure(
+                        FailureCategory::Configuration,
+                        format!("Failed to read CA certificate {}: {}", ca_cert, e),
+                    )
+                }
+            };
+            match native_tls::Certificate::from_pem(&pem) {
+                Ok(cert) => builder.add_root_certificate(cert),
+                Err(e) => {
+                    return State::failure(
+                        FailureCategory::Configuration,
+                        format!("Failed to parse CA certificate {}: {}", ca_cert, e),
+                    )
+                }
+            };
+        }
+        if self.accept_invalid_certs.unwrap_or(false) {
+            builder.danger_accept_invalid_certs(true);
+            builder.danger_accept_invalid_hostnames(true);
+        }
+        let connector = match builder.build() {
+            Ok(c) => c,
+            Err(e) => return State::failure(FailureCategory::Configuration, format!("Failed to create TLS connector: {}", e)),
+        };
+
+        let connector = tokio_native_tls::TlsConnector::from(connector);
+
+        // Perform TLS handshake
+        let tls_stream = match tokio::time::timeout(timeout, connector.connect(&self.host, tcp_stream)).await {
+            Ok(Ok(stream)) => stream,
+            Ok(Err(e)) => return State::failure(FailureCategory::Tls, format!("TLS handshake failed: {}", e)),
+            Err(_) => return State::failure(FailureCategory::Timeout, format!("TLS handshake timed out after {}ms", timeout_ms)),
+        };
+
+        // Get the peer certificate
+        let cert = match tls_stream.get_ref().peer_certificate() {
+            Ok(Some(cert)) => cert,
+            Ok(None) => return State::failure(FailureCategory::Tls, "No peer certificate found"),
+            Err(e) => return State::failure(FailureCategory::Tls, format!("Failed to get peer certificate: {}", e)),
+        };
+
+        let der = cert.to_der().unwrap();
+        if self.notify_fingerprint_change.unwrap_or(false) {
+            *ctx.certificate_fingerprint.lock().unwrap() = Some(certificate_fingerprint(&der));
+        }
+        let state = match x509_parser::parse_x509_certificate(&der) {
+            Ok((_, parsed_cert)) => self.check_parsed(&parsed_cert),
+            Err(e) => return State::failure(FailureCategory::Certificate, format!("Failed to parse certificate: {}", e)),
+        };
+        if matches!(state, State::Failure(_)) {
+            return state;
+        }
+
+        if let Some(issuer) = &self.caa_issuer {
+            if let Err(reason) = self.check_caa(issuer).await {
+                return State::Failure(reason);
+            }
+        }
+        state
+    }
+
+    // Check the expiry and certificate transparency of the peer certificate
+    fn check_parsed(&self, parsed_cert: &x509_parser::certificate::X509Certificate) -> State {
+        use x509_parser::extensions::ParsedExtension;
+
+        let expiry = expiry_state(parsed_cert, self.days_before_expiry);
+        if matches!(expiry, State::Failure(_)) {
+            return expiry;
+        }
+
+        if self.require_sct.unwrap_or(false) {
+            let has_sct = parsed_cert
+                .extensions()
+                .iter()
+                .any(|ext| matches!(ext.parsed_extension(), ParsedExtension::SCT(scts) if !scts.is_empty()));
+            if !has_sct {
+                return State::failure(
+                    FailureCategory::Certificate,
+                    "Certificate has no embedded SCTs (certificate transparency)",
+                );
+            }
+        }
+        expiry
+    }
+
+    // Verify the CAA records relevant for the host authorize `issuer`
+    async fn check_caa(&self, issuer: &str) -> Result<(), FailureReason> {
+        let resolver = dns::system_resolver().map_err(|e| {
+            FailureReason::new(FailureCategory::Configuration, format!("Failed to create DNS resolver: {}", e))
+        })?;
+        let (domain, issuers) = dns::caa_issuers(&resolver, &self.host)
+            .await
+            .map_err(|e| FailureReason::new(FailureCategory::Dns, e.to_string()))?
+            .ok_or_else(|| FailureReason::new(FailureCategory::Certificate, format!("No CAA records found for {}", self.host)))?;
+
+        let issuer = issuer.trim_end_matches('.').to_lowercase();
+        if issuers.contains(&issuer) {
+            Ok(())
+        } else {
+            Err(FailureReason::new(
+                FailureCategory::Certificate,
+                format!(
+                    "CAA records of {} don't authorize {} (authorized: {})",
+                    domain,
+                    issuer,
+                    if issuers.is_empty() { "none".to_string() } else { issuers.join(", ") }
+                ),
+            ))
+        }
+    }
+}
+
+// Expired certificates fail, ones expiring within `days_before_expiry` (default: 30) warn
+fn expiry_state(parsed_cert: &x509_parser::certificate::X509Certificate, days_before_expiry: Option<u64>) -> State {
+    // Get the not_after timestamp
+    let not_after = parsed_cert.validity().not_after;
+    let expiry_timestamp = not_after.timestamp();
+
+    // Calculate days until expiration
+    let now = chrono::Utc::now().timestamp();
+    let seconds_until_expiry = expiry_timestamp - now;
+    let days_until_expiry = seconds_until_expiry / 86400; // 86400 seconds in a day
+
+    let threshold = days_before_expiry.unwrap_or(30);
+
+    if days_until_expiry < 0 {
+        State::failure(FailureCategory::Certificate, format!("Certificate expired {} days ago", -days_until_expiry))
+    } else if days_until_expiry < threshold as i64 {
+        // Still valid, so this is a heads-up rather than an outage
+        State::Warning(format!(
+            "Certificate expires in {} days (threshold: {} days)",
+            days_until_expiry, threshold
+        ))
+    } else {
+        State::Success
+    }
+}
+
+// ServiceCertificateFile checks the expiry of a certificate stored in a PEM file,
+// e.g. a client certificate that is never served over TLS
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct ServiceCertificateFile {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days_before_expiry: Option<u64>,
+}
+
+impl ServiceCertificateFile {
+    pub async fn check(&self) -> State {
+        tracing::debug!("Starting certificate file check for: {}", self.path);
+
+        let pem = match tokio::fs::read(&self.path).await {
+            Ok(pem) => pem,
+            Err(e) => {
+                return State::failure(FailureCategory::Certificate, format!("Failed to read certificate {}: {}", self.path, e))
+            }
+        };
+        // The first certificate of a bundle is the one it's issued for
+        let pem = match x509_parser::pem::parse_x509_pem(&pem) {
+            Ok((_, pem)) => pem,
+            Err(e) => return State::failure(FailureCategory::Certificate, format!("Failed to parse PEM {}: {}", self.path, e)),
+        };
+        match pem.parse_x509() {
+            Ok(cert) => expiry_state(&cert, self.days_before_expiry),
+            Err(e) => State::failure(FailureCategory::Certificate, format!("Failed to parse certificate {}: {}", self.path, e)),
+        }
+    }
+}
+
+// ServiceDns resolves a record and optionally compares the returned values
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct ServiceDns {
+    pub hostname: String,
+    // A, AAAA, CNAME, MX or TXT
+    pub record_type: String,
+    // Values the records must match, in any order; MX values are "preference exchange"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_values: Option<Vec<String>>,
+    // Query this nameserver ("ip" or "ip:port") instead of the system resolver
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nameserver: Option<String>,
+    // Fail while the record still resolves, e.g. an old CNAME that should be gone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expect_absent: Option<bool>,
+}
+
+impl ServiceDns {
+    pub async fn check(&self) -> State {
+        tracing::debug!("Starting DNS check for: {} {}", self.record_type, self.hostname);
+
+        let resolver = match &self.nameserver {
+            Some(nameserver) => dns::nameserver_resolver(nameserver),
+            None => dns::system_resolver(),
+        };
+        let resolver = match resolver {
+            Ok(resolver) => resolver,
+            Err(e) => return State::failure(FailureCategory::Configuration, format!("Failed to create DNS resolver: {}", e)),
+        };
+        let values = match dns::lookup_values(&resolver, &self.hostname, &self.record_type).await {
+            Ok(values) => values,
+            Err(e) => return State::failure(FailureCategory::Dns, e.to_string()),
+        };
+        if self.expect_absent.unwrap_or(false) {
+            return match values.is_empty() {
+                true => State::Success,
+                false => State::failure(
+                    FailureCategory::Dns,
+                    format!("{} records for {} still resolve: {}", self.record_type, self.hostname, values.join(", ")),
+                ),
+            };
+        }
+        if values.is_empty() {
+            return State::failure(
+                FailureCategory::Dns,
+                format!("No {} records found for {}", self.record_type, self.hostname),
+            );
+        }
+        let Some(expected) = &self.expected_values else {
+            return State::Success;
+        };
+
+        // Names are case-insensitive and may be written with the trailing dot
+        let normalize = |values: &[String]| {
+            let mut values: Vec<String> = values
+                .iter()
+                .map(|value| match self.record_type.as_str() {
+                    "TXT" => value.clone(),
+                    _ => value.trim_end_matches('.').to_lowercase(),
+                })
+                .collect();
+            values.sort();
+            values.dedup();
+            values
+        };
+        let (values, expected) = (normalize(&values), normalize(expected));
+        if values != expected {
+            return State::failure(
+                FailureCategory::Dns,
+                format!(
+                    "Unexpected {} records for {}: got {}, expected {}",
+                    self.record_type,
+                    self.hostname,
+                    values.join(", "),
+                    expected.join(", ")
+                ),
+            );
+        }
+        State::Success
+    }
+}
+
+// SHA-256 fingerprint of a DER certificate, formatted like OpenSSL ("AB:CD:...")
+fn certificate_fingerprint(der: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(der)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct ServiceTcpPing {
+    pub host: String,
+    pub port: u16,
+    // `timeout_ms` defaults to 1000
+    #[serde(flatten)]
+    pub connection: ConnectionOptions,
+}
+
+impl ServiceTcpPing {
+    pub async fn check(&self, ctx: &CheckContext) -> State {
+        tracing::debug!("Starting TCP ping for host: {}:{}", self.host, self.port);
+
+        let connect = self.connection.connect(ctx, &self.host, self.port);
+        let timeout_ms = self.connection.timeout_ms(1000);
+        let timeout = Duration::from_millis(timeout_ms);
+
+        let result =
+            match tokio::time::timeout(timeout, connect).await {
+                Ok(Ok(_)) => State::Success,
+                Ok(Err(e)) => State::failure(FailureCategory::Connection, format!("Connection failed: {}", e)),
+                Err(_) => State::failure(FailureCategory::Timeout, format!("Timeout after {}ms", timeout_ms)),
+            };
+
+        tracing::debug!(
+            "TCP ping for host: {}:{} completed with state: {:?}",
+            self.host,
+            self.port,
+            result
+        );
+        result
+    }
+}
+
+// ServicePortScan connects to each listed port of a host, e.g. to audit firewall rules
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct ServicePortScan {
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expected_open: Vec<u16>,
+    // Ports refusing or not answering connections count as closed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expected_closed: Vec<u16>,
+    // `timeout_ms` bounds connecting to each port and defaults to 1000
+    #[serde(flatten)]
+    pub connection: ConnectionOptions,
+}
+
+impl ServicePortScan {
+    pub async fn check(&self, ctx: &CheckContext) -> State {
+        tracing::debug!("Starting port scan for host: {}", self.host);
+
+        let timeout_ms = self.connection.timeout_ms(1000);
+        let timeout = Duration::from_millis(timeout_ms);
+        let source = self.connection.local_address(ctx);
+        // The host is resolved once for all ports, a proxy resolves it on its own
+        let address = match &self.connection.socks_proxy {
+            Some(_) => None,
+            None => match resolver::resolve_with_retry(ctx.resolver.as_ref(), &self.host, 0).await {
+                Ok(addresses) => match addresses.iter().find(|a| source.is_none_or(|s| s.is_ipv4() == a.is_ipv4())) {
+                    Some(address) => Some(address.ip()),
+                    None => return State::failure(FailureCategory::Dns, format!("No addresses found for {}", self.host)),
+                },
+                Err(e) => return State::failure(FailureCategory::Dns, format!("Failed to resolve {}: {}", self.host, e)),
+            },
+        };
+
+        // Ok(None) when the port is open, Ok(Some(reason)) when it's closed and Err when it can't be told
+        let probe = |port: u16| async move {
+            let connect = async {
+                match address {
+                    Some(ip) => resolver::connect_addr(SocketAddr::new(ip, port), source).await,
+                    None => self.connection.connect(ctx, &self.host, port).await,
+                }
+            };
+            match tokio::time::timeout(timeout, connect).await {
+                Ok(Ok(_)) => Ok(None),
+                Ok(Err(e)) if matches!(e.kind(), std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::TimedOut) => {
+                    Ok(Some(e.to_string()))
+                }
+                Ok(Err(e)) => Err(format!("Failed to probe port {}: {}", port, e)),
+                Err(_) => Ok(Some(format!("timeout after {}ms", timeout_ms))),
+            }
+        };
+
+        // Ports are probed at once, so the check takes about one timeout
+        let (open, closed) = tokio::join!(
+            futures::future::join_all(self.expected_open.iter().map(|&port| probe(port))),
+            futures::future::join_all(self.expected_closed.iter().map(|&port| probe(port))),
+        );
+        let probed = (open.into_iter().collect::<Result<Vec<_>, _>>(), closed.into_iter().collect::<Result<Vec<_>, _>>());
+        let (open, closed) = match probed {
+            (Ok(open), Ok(closed)) => (open, closed),
+            (Err(e), _) | (_, Err(e)) => return State::failure(FailureCategory::Connection, e),
+        };
+        let mut mismatches: Vec<String> = self
+            .expected_open
+            .iter()
+            .zip(open)
+            .filter_map(|(port, closed)| closed.map(|reason| format!("{} closed ({})", port, reason)))
+            .collect();
+        mismatches.extend(
+            self.expected_closed
+                .iter()
+                .zip(closed)
+                .filter(|(_, closed)| closed.is_none())
+                .map(|(port, _)| format!("{} open", port)),
+        );
+
+        let result = if mismatches.is_empty() {
+            State::Success
+        } else {
+            State::failure(FailureCategory::Content, format!("Unexpected port states: {}", mismatches.join(", ")))
+        };
+
+        tracing::debug!("Port scan for host: {} completed with state: {:?}", self.host, result);
+        result
+    }
+}
+
+// ServiceIcmpPing sends ICMP echo requests, for hosts that don't accept TCP connections.
+// Needs unprivileged ping sockets (net.ipv4.ping_group_range) or CAP_NET_RAW.
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct ServiceIcmpPing {
+    pub host: String,
+    // Echo requests per check, default: 3
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u32>,
+    // Wait for each reply, default: 1000
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+impl ServiceIcmpPing {
+    pub async fn check(&self, ctx: &CheckContext) -> State {
+        tracing::debug!("Starting ICMP ping for host: {}", self.host);
+
+        let address = match resolver::resolve_with_retry(ctx.resolver.as_ref(), &self.host, 0).await {
+            Ok(addresses) if !addresses.is_empty() => addresses[0].ip(),
+            Ok(_) => return State::failure(FailureCategory::Dns, format!("No addresses found for {}", self.host)),
+            Err(e) => return State::failure(FailureCategory::Dns, format!("Failed to resolve {}: {}", self.host, e)),
+        };
+        let client = match icmp_client(address, ctx.source_address) {
+            Ok(client) => client,
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                return State::failure(
+                    FailureCategory::Connection,
+                    "ICMP sockets are not permitted, allow ping sockets via net.ipv4.ping_group_range or grant CAP_NET_RAW",
+                )
+            }
+            Err(e) => return State::failure(FailureCategory::Connection, format!("Failed to open ICMP socket: {}", e)),
+        };
+
+        let count = self.count.unwrap_or(3);
+        let identifier = surge_ping::PingIdentifier(uuid::Uuid::new_v4().as_u128() as u16);
+        let mut pinger = client.pinger(address, identifier).await;
+        pinger.timeout(Duration::from_millis(self.timeout_ms.unwrap_or(1000)));
+
+        let mut received = 0;
+        let mut last_error = None;
+        for sequence in 0..count {
+            match pinger.ping(surge_ping::PingSequence(sequence as u16), &[0; 56]).await {
+                Ok(_) => received += 1,
+                Err(surge_ping::SurgeError::Timeout { .. }) => {}
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        let result = match (received, last_error) {
+            (0, None) => State::failure(
+                FailureCategory::Connection,
+                format!("{} packets transmitted, 0 received, 100% packet loss", count),
+            ),
+            (0, Some(e)) => State::failure(
+                FailureCategory::Connection,
+                format!("{} packets transmitted, 0 received, 100% packet loss ({})", count, e),
+            ),
+            _ => State::Success,
+        };
+        tracing::debug!("ICMP ping for host: {} received {}/{} replies", self.host, received, count);
+        result
+    }
+}
+
+// ICMP client for the address family of `address`, preferring unprivileged ping sockets
+// and falling back to raw sockets
+fn icmp_client(address: IpAddr, source_address: Option<IpAddr>) -> std::io::Result<surge_ping::Client> {
+    let config = |socket_type| {
+        let mut builder = surge_ping::Config::builder().sock_type_hint(socket_type).kind(match address {
+            IpAddr::V4(_) => surge_ping::ICMP::V4,
+            IpAddr::V6(_) => surge_ping::ICMP::V6,
+        });
+        if let Some(source) = source_address.filter(|source| source.is_ipv4() == address.is_ipv4()) {
+            builder = builder.bind(SocketAddr::new(source, 0));
+        }
+        builder.build()
+    };
+    match surge_ping::Client::new(&config(socket2::Type::DGRAM)) {
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => surge_ping::Client::new(&config(socket2::Type::RAW)),
+        result => result,
+    }
+}
+
+// Serde helper for byte strings written either as text or as a list of bytes
+mod bytes_or_string {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize, Serialize)]
+    #[serde(untagged)]
+    enum Bytes {
+        Text(String),
+        Raw(Vec<u8>),
+    }
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => text.serialize(serializer),
+            Err(_) => bytes.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        Ok(match Bytes::deserialize(deserializer)? {
+            Bytes::Text(text) => text.into_bytes(),
+            Bytes::Raw(bytes) => bytes,
+        })
+    }
+
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+            match bytes {
+                Some(bytes) => super::serialize(bytes, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+            Ok(Option::<super::Bytes>::deserialize(deserializer)?.map(|bytes| match bytes {
+                super::Bytes::Text(text) => text.into_bytes(),
+                super::Bytes::Raw(bytes) => bytes,
+            }))
+        }
+    }
+}
+
+// Upper bound for data read while waiting for an expected banner
+const MAX_BANNER_BYTES: usize = 64 * 1024;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct ServiceTcpBanner {
+    pub host: String,
+    pub port: u16,
+    // Bytes sent after connecting, before reading the response
+    #[serde(default, with = "bytes_or_string::option", skip_serializing_if = "Option::is_none")]
+    pub send: Option<Vec<u8>>,
+    // Bytes the response must contain
+    #[serde(with = "bytes_or_string")]
+    pub expect: Vec<u8>,
+    // `timeout_ms` bounds the whole exchange (default: 5000)
+    #[serde(flatten)]
+    pub connection: ConnectionOptions,
+}
+
+impl ServiceTcpBanner {
+    pub async fn check(&self, ctx: &CheckContext) -> State {
+        tracing::debug!("Starting TCP banner check for host: {}:{}", self.host, self.port);
+
+        let timeout_ms = self.connection.timeout_ms(5000);
+        let timeout = Duration::from_millis(timeout_ms);
+
+        let mut received = Vec::new();
+        let result = match tokio::time::timeout(timeout, self.exchange(ctx, &mut received)).await {
+            Ok(Ok(())) => State::Success,
+            Ok(Err(reason)) => State::Failure(reason),
+            Err(_) if received.is_empty() => State::failure(FailureCategory::Timeout, format!("Timeout after {}ms", timeout_ms)),
+            Err(_) => State::failure(
+                FailureCategory::Content,
+                format!("Unexpected response: {}", String::from_utf8_lossy(&received).trim_end()),
+            ),
+        };
+
+        tracing::debug!(
+            "TCP banner check for host: {}:{} completed with state: {:?}",
+            self.host,
+            self.port,
+            result
+        );
+        result
+    }
+
+    // Connect, send the optional payload and read until the expected bytes show up
+    async fn exchange(&self, ctx: &CheckContext, received: &mut Vec<u8>) -> Result<(), FailureReason> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let mut stream = self
+            .connection
+            .connect(ctx, &self.host, self.port)
+            .await
+            .map_err(|e| FailureReason::new(FailureCategory::Connection, format!("Connection failed: {}", e)))?;
+
+        if let Some(send) = &self.send {
+            stream
+                .write_all(send)
+                .await
+                .map_err(|e| FailureReason::new(FailureCategory::Connection, format!("Failed to send data: {}", e)))?;
+        }
+
+        let mut buf = [0u8; 1024];
+        while !contains_bytes(received, &self.expect) {
+            let read = stream
+                .read(&mut buf)
+                .await
+                .map_err(|e| FailureReason::new(FailureCategory::Connection, format!("Failed to read response: {}", e)))?;
+            if read == 0 || received.len() >= MAX_BANNER_BYTES {
+                return Err(FailureReason::new(
+                    FailureCategory::Content,
+                    format!("Unexpected response: {}", String::from_utf8_lossy(received).trim_end()),
+                ));
+            }
+            received.extend_from_slice(&buf[..read]);
+        }
+        Ok(())
+    }
+}
+
+fn contains_bytes(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty() || haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum CheckType {
+    // Boxed, HTTP checks have by far the most settings
+    Http(Box<ServiceHttp>),
+    #[serde(rename = "httpFlow")]
+    HttpFlow(ServiceHttpFlow),
+    #[serde(rename = "grpcWeb")]
+    GrpcWeb(ServiceGrpcWeb),
+    #[serde(rename = "promql")]
+    PromQL(ServicePromQL),
+    Certificate(ServiceCertificate),
+    #[serde(rename = "certificateFile")]
+    CertificateFile(ServiceCertificateFile),
+    #[serde(rename = "tcpPing")]
+    TcpPing(ServiceTcpPing),
+    #[serde(rename = "tcpBanner")]
+    TcpBanner(ServiceTcpBanner),
+    #[serde(rename = "portScan")]
+    PortScan(ServicePortScan),
+    #[serde(rename = "icmpPing")]
+    IcmpPing(ServiceIcmpPing),
+    External(ServiceExternalCheck),
+    Dns(ServiceDns),
+}
+
+impl CheckType {
+    // Host the check connects to, used to limit concurrent checks per host
+    pub fn host(&self) -> Option<String> {
+        let url_host = |url: &str| reqwest::Url::parse(url).ok()?.host_str().map(str::to_lowercase);
+        match self {
+            CheckType::Http(http) => url_host(http.targets().first()?),
+            CheckType::HttpFlow(flow) => url_host(&flow.steps.first()?.url),
+            CheckType::GrpcWeb(grpc) => url_host(&grpc.url),
+            CheckType::PromQL(promql) => url_host(&promql.endpoint),
+            CheckType::Certificate(cert) => Some(cert.host.to_lowercase()),
+            CheckType::CertificateFile(_) | CheckType::External(_) => None,
+            CheckType::TcpPing(tcp) => Some(tcp.host.to_lowercase()),
+            CheckType::TcpBanner(banner) => Some(banner.host.to_lowercase()),
+            CheckType::PortScan(scan) => Some(scan.host.to_lowercase()),
+            CheckType::IcmpPing(icmp) => Some(icmp.host.to_lowercase()),
+            CheckType::Dns(dns) => Some(dns.hostname.trim_end_matches('.').to_lowercase()),
+        }
+    }
+
+    // Connection settings of check types that make their own connections
+    pub fn connection(&self) -> Option<&ConnectionOptions> {
+        match self {
+            CheckType::Http(http) => Some(&http.connection),
+            CheckType::Certificate(cert) => Some(&cert.connection),
+            CheckType::TcpPing(tcp) => Some(&tcp.connection),
+            CheckType::TcpBanner(banner) => Some(&banner.connection),
+            CheckType::PortScan(scan) => Some(&scan.connection),
+            CheckType::HttpFlow(_)
+            | CheckType::GrpcWeb(_)
+            | CheckType::PromQL(_)
+            | CheckType::CertificateFile(_)
+            | CheckType::IcmpPing(_)
+            | CheckType::External(_)
+            | CheckType::Dns(_) => None,
+        }
+    }
+
+    pub async fn check(&self, ctx: &CheckContext) -> State {
+        let check = async {
+            match self {
+                CheckType::Certificate(cert) => cert.check(ctx).await,
+                CheckType::CertificateFile(file) => file.check().await,
+                CheckType::Http(http) => http.check(ctx).await,
+                CheckType::HttpFlow(flow) => flow.check(ctx).await,
+                CheckType::GrpcWeb(grpc) => grpc.check(ctx).await,
+                CheckType::PromQL(promql) => promql.check(ctx).await,
+                CheckType::TcpPing(tcp) => tcp.check(ctx).await,
+                CheckType::TcpBanner(banner) => banner.check(ctx).await,
+                CheckType::PortScan(scan) => scan.check(ctx).await,
+                CheckType::IcmpPing(icmp) => icmp.check(ctx).await,
+                CheckType::External(external) => external.check(ctx).await,
+                CheckType::Dns(dns) => dns.check().await,
+            }
+        };
+
+        // The global timeout is a hard ceiling regardless of per-check settings
+        match ctx.global_timeout_ms {
+            Some(timeout_ms) => match tokio::time::timeout(Duration::from_millis(timeout_ms), check).await {
+                Ok(state) => state,
+                Err(_) => State::failure(FailureCategory::Timeout, format!("Check exceeded global timeout of {}ms", timeout_ms)),
+            },
+            None => check.await,
+        }
+    }
+}
+
+// CheckContext carries shared resources used while running checks
+#[derive(Clone)]
+pub struct CheckContext {
+    pub resolver: Arc<dyn Resolver>,
+    // Unique id of the current check run, sent to HTTP targets as X-Request-ID
+    pub request_id: Option<String>,
+    pub global_timeout_ms: Option<u64>,
+    // Local address checks originate from
+    pub source_address: Option<IpAddr>,
+    // Set by certificate checks with `notify_fingerprint_change` to the fingerprint they saw
+    pub certificate_fingerprint: Arc<std::sync::Mutex<Option<String>>>,
+    // Set by HTTP checks with `respect_rate_limits` to how long a 429 asked to wait
+    pub rate_limit_delay: Arc<std::sync::Mutex<Option<Duration>>>,
+    // External checks only run when the configuration file allows them
+    pub allow_command_checks: bool,
+}
+
+impl Default for CheckContext {
+    fn default() -> Self {
+        Self {
+            resolver: Arc::new(SystemResolver),
+            request_id: None,
+            global_timeout_ms: None,
+            source_address: None,
+            certificate_fingerprint: Arc::default(),
+            rate_limit_delay: Arc::default(),
+            allow_command_checks: false,
+        }
+    }
+}
+
+// Format a duration compactly for messages, e.g. "2h 5m" or "14m"
+fn format_duration(duration: chrono::Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let (days, hours, minutes) = (seconds / 86400, seconds % 86400 / 3600, seconds % 3600 / 60);
+
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+// Extract a readable message from a panic payload
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+// Run a monitoring task, restarting it with exponential backoff whenever it panics
+async fn supervise<F, Fut>(name: &str, mut task: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut backoff = RESPAWN_BACKOFF_MIN;
+    loop {
+        let started = tokio::time::Instant::now();
+        match AssertUnwindSafe(task()).catch_unwind().await {
+            Ok(()) => break,
+            Err(panic) => {
+                // A task that ran for a while before panicking starts over with a short backoff
+                if started.elapsed() > RESPAWN_BACKOFF_MAX {
+                    backoff = RESPAWN_BACKOFF_MIN;
+                }
+                tracing::error!(
+                    "Monitoring task for service '{}' panicked: {}, restarting in {:?}",
+                    name,
+                    panic_message(panic.as_ref()),
+                    backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(RESPAWN_BACKOFF_MAX);
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct Service {
+    // Name of the template in `Config::templates` this service was merged with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    pub enabled: bool,
+    pub name: String,
+    pub description: String,
+    // Human-friendly alternative to the id in API paths, e.g. /api/services/website/report
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slug: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check_interval_success: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check_interval_fail: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_failures: Option<u64>,
+    // Alert on failures accumulated over recent checks, even when not consecutive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure_window: Option<FailureWindow>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debounce_checks: Option<u64>,
+    // Warm-up after the service was added, failures are recorded but not alerted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initial_grace_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rereport: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_reports_per_incident: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_request_id: Option<bool>,
+    // Check latency counted as within the SLO
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_latency_ms: Option<u64>,
+    // Weight of the service in the overall health score (default: 1)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health_weight: Option<u32>,
+    // Shell command run once per incident when the failure threshold is crossed, e.g. restarting
+    // a container; requires allow_command_checks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_failure_command: Option<String>,
+    // Remediation steps, linked from every alert
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runbook_url: Option<String>,
+    // Names of `notifiers` sent this service's notifications instead of the default channels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_targets: Option<Vec<String>>,
+    // Planned downtime excluded from uptime reports
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<Vec<MaintenanceWindow>>,
+    // Local address checks originate from, overriding the global one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_address: Option<String>,
+    pub check: CheckType,
+}
+
+// FailureWindow alerts once `failures` of the last `checks` checks failed
+#[derive(Deserialize, Serialize, Debug, Clone, Hash)]
+pub struct FailureWindow {
+    pub failures: u64,
+    pub checks: u64,
+}
+
+impl Service {
+    // Validate the service settings, `id` is used in error messages
+    pub fn validate(&self, id: &str) -> anyhow::Result<()> {
+        if self.name.trim().is_empty() {
+            anyhow::bail!("Service '{}' has an empty name", id);
+        }
+        if self.maintenance.iter().flatten().any(|w| w.end <= w.start) {
+            anyhow::bail!("Service '{}' has a maintenance window that doesn't end after it starts", id);
+        }
+        if let Some(slug) = &self.slug {
+            if slug.is_empty() || !slug.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                anyhow::bail!("Service '{}' has an invalid slug '{}', use letters, digits, '-' and '_'", id, slug);
+            }
+        }
+        if self.check_interval_success == Some(0) || self.check_interval_fail == Some(0) {
+            anyhow::bail!("Service '{}' has a zero check interval", id);
+        }
+        if self.rereport == Some(0) {
+            anyhow::bail!("Service '{}' has rereport set to zero", id);
+        }
+        if self.check_timeout_ms == Some(0) {
+            anyhow::bail!("Service '{}' has a zero check timeout", id);
+        }
+        if self.debounce_checks == Some(0) {
+            anyhow::bail!("Service '{}' has debounce_checks set to zero", id);
+        }
+        if let Some(window) = &self.failure_window {
+            if window.failures == 0 || window.failures > window.checks {
+                anyhow::bail!(
+                    "Service '{}' failure_window needs 1 to {} failures, got {}",
+                    id,
+                    window.checks,
+                    window.failures
+                );
+            }
+        }
+        if let Some(address) = &self.source_address {
+            validate_source_address(address).map_err(|e| anyhow::anyhow!("Service '{}': {}", id, e))?;
+        }
+        if let Some(connection) = self.check.connection() {
+            connection.validate().map_err(|e| anyhow::anyhow!("Service '{}': {}", id, e))?;
+        }
+        match &self.check {
+            CheckType::Http(http) if http.targets().is_empty() => {
+                anyhow::bail!("Service '{}' has no URL to check", id);
+            }
+            CheckType::Http(http) if http.samples == Some(0) => {
+                anyhow::bail!("Service '{}' has samples set to zero", id);
+            }
+            CheckType::Http(http)
+                if http.majority_threshold.is_some_and(|t| t == 0 || t > http.samples.unwrap_or(1)) =>
+            {
+                anyhow::bail!("Service '{}' has a majority_threshold outside 1 to samples", id);
+            }
+            CheckType::Http(http)
+                if http.header_threshold.as_ref().is_some_and(|t| t.min.is_none() && t.max.is_none()) =>
+            {
+                anyhow::bail!("Service '{}' has a header_threshold without min or max", id);
+            }
+            CheckType::Http(http) if http.cache_headers.as_ref().is_some_and(CacheHeaders::is_empty) => {
+                anyhow::bail!("Service '{}' has cache_headers without expectations", id);
+            }
+            CheckType::Http(http)
+                if http.expected_charset.as_ref().is_some_and(|c| Encoding::for_label(c.trim().as_bytes()).is_none()) =>
+            {
+                anyhow::bail!("Service '{}' has an unknown expected_charset", id);
+            }
+            CheckType::PromQL(check) if !promql::COMPARATORS.contains(&check.comparator.as_str()) => {
+                anyhow::bail!(
+                    "Service '{}' has an unknown comparator '{}', expected one of {}",
+                    id,
+                    check.comparator,
+                    promql::COMPARATORS.join(" ")
+                );
+            }
+            CheckType::PortScan(check) if check.expected_open.is_empty() && check.expected_closed.is_empty() => {
+                anyhow::bail!("Service '{}' has a port scan without expected ports", id);
+            }
+            CheckType::PortScan(check) if check.expected_open.iter().any(|port| check.expected_closed.contains(port)) => {
+                anyhow::bail!("Service '{}' expects a port to be both open and closed", id);
+            }
+            CheckType::IcmpPing(check) if check.count == Some(0) || check.timeout_ms == Some(0) => {
+                anyhow::bail!("Service '{}' has an ICMP count or timeout of zero", id);
+            }
+            CheckType::Dns(check) if !dns::RECORD_TYPES.contains(&check.record_type.as_str()) => {
+                anyhow::bail!(
+                    "Service '{}' has an unsupported record type '{}', expected one of {}",
+                    id,
+                    check.record_type,
+                    dns::RECORD_TYPES.join(" ")
+                );
+            }
+            CheckType::Dns(check) if check.expect_absent == Some(true) && check.expected_values.is_some() => {
+                anyhow::bail!("Service '{}' has both expected_values and expect_absent", id);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // Check interval for the given state, using the service override or the global default
+    fn configured_interval(&self, state: &State, config: &Config) -> u64 {
+        match state {
+            State::Failure(_) => self.check_interval_fail.unwrap_or(config.check_interval_fail),
+            _ => self.check_interval_success.unwrap_or(config.check_interval_success),
+        }
+    }
+
+    // Effective check interval, raised to `min_check_interval_ms` when configured below it
+    pub fn interval(&self, state: &State, config: &Config) -> u64 {
+        self.configured_interval(state, config)
+            .max(config.min_check_interval_ms.unwrap_or(0))
+    }
+
+    // Run the check bounded by the service timeout, which also covers DNS resolution
+    pub async fn check(&self, ctx: &CheckContext) -> State {
+        let timeout_ms = self.check_timeout_ms.unwrap_or(DEFAULT_CHECK_TIMEOUT_MS);
+
+        // Tag every check run with a unique id so it can be correlated with the target's logs
+        let request_id = uuid::Uuid::new_v4().to_string();
+        let ctx = CheckContext {
+            request_id: Some(request_id.clone()),
+            source_address: self
+                .source_address
+                .as_deref()
+                .and_then(|a| a.parse().ok())
+                .or(ctx.source_address),
+            ..ctx.clone()
+        };
+        let span = tracing::info_span!("check", service = %self.name, request_id = %request_id);
+
+        // A panicking check is reported as a failure instead of killing the task
+        let check = AssertUnwindSafe(self.check.check(&ctx)).catch_unwind();
+
+        let state = match tokio::time::timeout(Duration::from_millis(timeout_ms), check)
+            .instrument(span)
+            .await
+        {
+            Ok(Ok(state)) => state,
+            Ok(Err(panic)) => {
+                State::failure(FailureCategory::Internal, format!("Check panicked: {}", panic_message(panic.as_ref())))
+            }
+            Err(_) => State::failure(FailureCategory::Timeout, format!("Check timed out after {}ms", timeout_ms)),
+        };
+
+        match state {
+            State::Failure(reason) if self.include_request_id.unwrap_or(false) => {
+                State::Failure(reason.map_detail(|detail| format!("{} (request id: {})", detail, request_id)))
+            }
+            state => state,
+        }
+    }
+
+    // Run a single check and record its result, sending any notifications
+    pub async fn run_once(&self, id: &str, app_state: &AppState) -> State {
+        self.run_check(id, app_state).await.0
+    }
+
+    // Run a single check like `run_once`. A rate limited check records nothing, it returns the
+    // previous state and how long to wait before checking again.
+    async fn run_check(&self, id: &str, app_state: &AppState) -> (State, Option<Duration>) {
+        tracing::info!("Running health check for service: {}", self.name);
+
+        // Waiting for another check of the same host doesn't count towards latency
+        let _permit = app_state.host_permit(&self.check).await;
+        let ctx = app_state.check_context().await;
+        let started = std::time::Instant::now();
+        let state = self.check(&ctx).await;
+        let latency = started.elapsed();
+        let deferred = ctx.rate_limit_delay.lock().unwrap().take();
+        if let Some(delay) = deferred {
+            tracing::info!("Service '{}' is rate limited, deferring the check for {:?}", self.name, delay);
+            let previous = app_state.services.read().await.get(id).map(|s| s.state.clone());
+            return (previous.unwrap_or(State::Unknown), Some(delay));
+        }
+        let fingerprint = ctx.certificate_fingerprint.lock().unwrap().take();
+        if let Some(fingerprint) = fingerprint {
+            app_state.record_fingerprint(id, fingerprint).await;
+        }
+        app_state.record_latency(id, latency).await;
+        app_state.publish_result(id, &self.name, &state, latency).await;
+
+        // Log the result
+        match &state {
+            State::Success => tracing::info!("Service '{}' check succeeded", self.name),
+            State::Failure(reason) => tracing::warn!("Service '{}' check failed: {}", self.name, reason),
+            State::Warning(reason) => tracing::warn!("Service '{}' check warning: {}", self.name, reason),
+            State::Unknown => tracing::info!("Service '{}' check returned unknown state", self.name),
+        }
+
+        // Update state in the global store, the database follows what was recorded
+        let recorded = app_state.set_state(id.to_string(), state.clone()).await;
+        app_state.persist_result(id, &self.name, &state, latency, !recorded);
+        (state, None)
+    }
+
+    pub async fn run(&self, id: String, app_state: AppState) {
+        let mut clamp_logged = false;
+        // Delay of the current check past its slot, see `AppState::check_offset`
+        let mut offset = 0;
+        loop {
+            app_state.record_loop_iteration(&id).await;
+            let (state, deferred) = self.run_check(&id, &app_state).await;
+
+            // Get global config defaults
+            let config = app_state.get_config().await;
+
+            // Determine sleep interval based on state, using service override or global default
+            let interval = self.interval(&state, &config);
+            let configured = self.configured_interval(&state, &config);
+            if interval > configured && !clamp_logged {
+                tracing::warn!(
+                    "Service '{}' check interval {}ms is below min_check_interval_ms, using {}ms",
+                    self.name,
+                    configured,
+                    interval
+                );
+                clamp_logged = true;
+            }
+
+            // A rate limited check waits at least as long as the target asked
+            let interval = deferred.map_or(interval, |delay| interval.max(delay.as_millis() as u64));
+            let next_offset = app_state.check_offset(&config, interval);
+            let interval = (interval + next_offset).saturating_sub(offset);
+            offset = next_offset;
+            tracing::debug!("Service '{}' next check in {}ms", self.name, interval);
+            tokio::time::sleep(Duration::from_millis(interval)).await;
+        }
+    }
+}
+
+// ServiceState represents the current runtime state of a service for API responses
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ServiceState {
+    pub name: String,
+    pub description: String,
+    pub state: State,
+    pub status: Status,
+    // Category and code of the current failure, for clients filtering failures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<FailureReason>,
+    pub last_check: DateTime<Utc>,
+    pub consecutive_failures: u64,
+    pub total_checks: u64,
+    pub successful_checks: u64,
+    pub failed_checks: u64,
+    pub uptime_start: Option<DateTime<Utc>>,
+    pub down_since: Option<DateTime<Utc>>,
+    // When this run started monitoring the service
+    pub monitored_since: DateTime<Utc>,
+    // When the service was added, starts its `initial_grace_ms`. Known for services added by a
+    // config reload and, with `database_path`, for every service.
+    #[serde(skip)]
+    pub added_at: Option<DateTime<Utc>>,
+    pub last_loop_iteration: DateTime<Utc>,
+    // Telegram message of the current incident's alert, edited on rereports
+    #[serde(skip)]
+    pub alert_message_id: Option<i64>,
+    // Rereports sent during the current incident
+    #[serde(default)]
+    pub rereports_sent: u64,
+    // Someone acknowledged the current incident, rereports are suppressed until recovery
+    #[serde(default)]
+    pub acknowledged: bool,
+    // Weight of the service in the overall health score
+    #[serde(default = "default_health_weight")]
+    pub health_weight: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runbook_url: Option<String>,
+    // Results of the most recent checks (true = failed) for `failure_window`
+    #[serde(skip)]
+    pub recent_failures: VecDeque<bool>,
+    // A windowed alert was sent, cleared once the window has no failures left
+    #[serde(skip)]
+    pub window_alerted: bool,
+    // Status differing from the recorded one and how many checks in a row reported it
+    #[serde(skip)]
+    pub pending_status: Option<(Status, u64)>,
+    // SHA-256 fingerprint of the certificate seen by the last check, with `notify_fingerprint_change`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate_fingerprint: Option<String>,
+    // Number of the last notification raised and the highest one sent, for `order_notifications`
+    #[serde(skip)]
+    pub notification_sequence: u64,
+    #[serde(skip)]
+    pub sent_sequence: u64,
+    // The first result was announced, with `notify_first_check`
+    #[serde(skip)]
+    pub first_check_notified: bool,
+    // Most recent check results, oldest first, capped at `history_size`
+    #[serde(skip)]
+    pub history: VecDeque<(DateTime<Utc>, State)>,
+}
+
+fn default_health_weight() -> u32 {
+    DEFAULT_HEALTH_WEIGHT
+}
+
+impl ServiceState {
+    fn new(service: &Service, now: DateTime<Utc>) -> Self {
+        Self {
+            name: service.name.clone(),
+            description: service.description.clone(),
+            state: State::Unknown,
+            status: Status::Unknown,
+            failure: None,
+            last_check: now,
+            consecutive_failures: 0,
+            total_checks: 0,
+            successful_checks: 0,
+            failed_checks: 0,
+            uptime_start: None,
+            down_since: None,
+            monitored_since: now,
+            added_at: None,
+            last_loop_iteration: now,
+            alert_message_id: None,
+            rereports_sent: 0,
+            acknowledged: false,
+            health_weight: service.health_weight.unwrap_or(DEFAULT_HEALTH_WEIGHT),
+            runbook_url: service.runbook_url.clone(),
+            recent_failures: VecDeque::new(),
+            window_alerted: false,
+            pending_status: None,
+            certificate_fingerprint: None,
+            notification_sequence: 0,
+            sent_sequence: 0,
+            first_check_notified: false,
+            history: VecDeque::new(),
+        }
+    }
+
+    // Hold back a change of status until `debounce` checks in a row reported it. Returns the
+    // number of checks the committed result was held back for, or None while it's held back.
+    fn debounce(&mut self, state: &State, debounce: u64) -> Option<u64> {
+        let status = Status::from(state);
+        // Nothing to protect before the first result
+        if debounce <= 1 || status == self.status || self.status == Status::Unknown {
+            self.pending_status = None;
+            return Some(0);
+        }
+
+        let seen = match self.pending_status {
+            Some((pending, seen)) if pending == status => seen + 1,
+            _ => 1,
+        };
+        if seen >= debounce {
+            self.pending_status = None;
+            Some(seen - 1)
+        } else {
+            self.pending_status = Some((status, seen));
+            None
+        }
+    }
+}
+
+// CheckResult is posted to the result webhook after every check
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CheckResult {
+    pub service: String,
+    pub state: State,
+    pub latency_ms: u64,
+    pub timestamp: DateTime<Utc>,
+}
+
+// StalledService describes a monitoring task that stopped iterating its check loop
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StalledService {
+    pub id: String,
+    pub name: String,
+    pub last_loop_iteration: DateTime<Utc>,
+}
+
+// Settings only read when sending notifications
+const NOTIFICATION_FIELDS: [&str; 21] = [
+    "telegram_token",
+    "telegram_chat_id",
+    "telegram_api_url",
+    "slack_webhook_url",
+    "smtp_host",
+    "smtp_port",
+    "smtp_username",
+    "smtp_password",
+    "email_to",
+    "email_from",
+    "notification_failover",
+    "notifiers",
+    "environment",
+    "notification_emoji",
+    "notification_retries",
+    "dead_letter_path",
+    "notification_coalesce_ms",
+    "order_notifications",
+    "notify_on_startup",
+    "notify_first_check",
+    "result_webhook_url",
+];
+
+// NotificationChannel is a channel notifications can be sent through
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationChannel {
+    Telegram,
+    Slack,
+    Email,
+}
+
+// Notifier is a named notification target, services are routed to it with `notify_targets`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Notifier {
+    // Another chat of the configured Telegram bot
+    Telegram { chat_id: i64 },
+    Slack { webhook_url: String },
+    // Recipients reached through the configured SMTP server
+    Email { to: Vec<String> },
+}
+
+// NotifierClient sends the notifications of a named notifier
+#[derive(Debug)]
+pub enum NotifierClient {
+    Telegram(TelegramClient),
+    Slack(SlackClient),
+    Email(EmailClient),
+}
+
+// Config represents the application configuration loaded from file
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Config {
+    pub telegram_token: String,
+    // Numeric id or "@handle", resolved with `resolve_telegram_chat_id`
+    pub telegram_chat_id: ChatId,
+    pub check_interval_success: u64,
+    pub check_interval_fail: u64,
+    pub notify_failures: u64,
+    // Checks in a row a different status must be seen for before the recorded state changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debounce_checks: Option<u64>,
+    pub rereport: u64,
+    // Rereports sent per incident before further ones are suppressed until recovery
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_reports_per_incident: Option<u64>,
+    // Shared service fields, merged into services referencing them with `template`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub templates: Option<HashMap<String, serde_yaml::Value>>,
+    pub services: HashMap<String, Service>,
+    pub web_port: Option<u16>,
+    // Token the configuration endpoints (/api/config, its export and import, /api/test) require as
+    // `Authorization: Bearer <token>`. Unset disables these endpoints, they reject every request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_bearer_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watchdog_intervals: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale_intervals: Option<u64>,
+    // Check results kept per service for /api/services/:id/history
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub history_size: Option<usize>,
+    // Upper bounds of the buckets of /api/services/:id/latency
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_buckets_ms: Option<Vec<u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telegram_api_url: Option<String>,
+    // Incoming webhook alerts, warnings and recoveries are also posted to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slack_webhook_url: Option<String>,
+    // SMTP server alerts, warnings and recoveries are also emailed through to `email_to`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_host: Option<String>,
+    // 465 uses implicit TLS, 587 requires STARTTLS, other ports use it when offered (default: 587)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_to: Option<Vec<String>>,
+    // Defaults to `smtp_username` when it is an address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_from: Option<String>,
+    // Channels in priority order, each only used when sending to the previous one failed.
+    // Unset, notifications go to every configured channel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification_failover: Option<Vec<NotificationChannel>>,
+    // Named notification targets services can be routed to with `notify_targets`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notifiers: Option<BTreeMap<String, Notifier>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification_coalesce_ms: Option<u64>,
+    // Drop notifications overtaken by a newer one of the same service, e.g. an alert delayed by
+    // retries or coalescing that would otherwise arrive after the recovery
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_notifications: Option<bool>,
+    // Floor for every check interval, protecting targets from too aggressive checking
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_check_interval_ms: Option<u64>,
+    // Checks allowed to run against the same host at once (default: unlimited)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_checks_per_host: Option<usize>,
+    // Shuffle the order checks are started in, so no service is always first in line
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub randomize_check_order: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub global_check_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification_emoji: Option<NotificationEmoji>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification_retries: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dead_letter_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_request_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_webhook_url: Option<String>,
+    // External table every check result is written to, for dashboards
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postgres_results: Option<PostgresResults>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_cache_ttl_ms: Option<u64>,
+    // SQLite database every check result is written to, restoring the counters on startup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database_retention_days: Option<u64>,
+    // Minimum time between warnings while writing to the database keeps failing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database_alert_interval_ms: Option<u64>,
+    // Re-check/acknowledge buttons on alerts, handled by polling the Bot API for updates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telegram_actions: Option<bool>,
+    // Announce each start of the monitor, so restarts and crash loops get noticed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_on_startup: Option<bool>,
+    // Notify the first result of every service, confirming monitoring is wired up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_first_check: Option<bool>,
+    // Allow `!external` checks, which run commands on the monitoring host
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_command_checks: Option<bool>,
+    // Prefix of metric names on /metrics, avoids collisions in shared scrapes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_prefix: Option<String>,
+    // Attempts to bind the web server port while it's still in use, e.g. during a restart
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web_bind_attempts: Option<u32>,
+    // Delay before the first bind retry, doubled after each attempt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web_bind_backoff_ms: Option<u64>,
+    // Environment overrides applied on top of the files, see `with_overrides`
+    #[serde(skip)]
+    env_overrides: Vec<EnvOverride>,
+}
+
+// EnvOverride is an environment variable applied to the configuration, with the file's value it
+// replaced (None when the override added it) at `keys`
+#[derive(Debug, Clone)]
+struct EnvOverride {
+    variable: String,
+    value: String,
+    keys: Vec<serde_yaml::Value>,
+    original: Option<serde_yaml::Value>,
+}
+
+impl Config {
+    // Check context with the settings from this configuration
+    pub fn check_context(&self) -> CheckContext {
+        CheckContext {
+            global_timeout_ms: self.global_check_timeout_ms,
+            // Validated when the configuration is loaded
+            source_address: self.source_address.as_deref().and_then(|a| a.parse().ok()),
+            allow_command_checks: self.allow_command_checks.unwrap_or(false),
+            ..Default::default()
+        }
+    }
+
+    // Resolve an @handle chat id to its numeric id, keeping an id resolved before
+    pub async fn resolve_telegram_chat_id(&mut self) -> anyhow::Result<()> {
+        let ChatId::Handle { handle, id: None } = &self.telegram_chat_id else {
+            return Ok(());
+        };
+        let handle = handle.clone();
+        let id = self
+            .telegram_client()
+            .resolve_handle(&handle)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to resolve telegram_chat_id '{}': {}", handle, e))?;
+        tracing::info!("Resolved telegram_chat_id '{}' to {}", handle, id);
+        self.telegram_chat_id = ChatId::Handle { handle, id: Some(id) };
+        Ok(())
+    }
+
+    // Whether the configurations differ in nothing but notification settings, which are applied
+    // without restarting the monitoring tasks
+    pub fn differs_only_in_notifications(&self, other: &Config) -> bool {
+        let without_notifications = |config: &Config| {
+            let mut value = serde_yaml::to_value(config).ok()?;
+            let mapping = value.as_mapping_mut()?;
+            for field in NOTIFICATION_FIELDS {
+                mapping.remove(field);
+            }
+            Some(value)
+        };
+        match (without_notifications(self), without_notifications(other)) {
+            (Some(current), Some(other)) => current == other,
+            _ => false,
+        }
+    }
+
+    pub fn telegram_client(&self) -> TelegramClient {
+        // An unresolved handle has no chat to send to yet
+        self.telegram_chat_client(self.telegram_chat_id.id().unwrap_or_default())
+    }
+
+    // Telegram client of the configured bot sending to the given chat
+    fn telegram_chat_client(&self, chat_id: i64) -> TelegramClient {
+        let client = TelegramClient::new(self.telegram_token.clone(), chat_id)
+            .with_environment(self.environment.clone())
+            .with_emoji(self.notification_emoji.clone().unwrap_or_default())
+            .with_actions(self.telegram_actions.unwrap_or(false));
+        match &self.telegram_api_url {
+            Some(api_url) => client.with_api_url(api_url.clone()),
+            None => client,
+        }
+    }
+
+    pub fn slack_client(&self) -> Option<SlackClient> {
+        let webhook_url = self.slack_webhook_url.clone()?;
+        Some(SlackClient::new(webhook_url).with_environment(self.environment.clone()))
+    }
+
+    pub fn email_client(&self) -> Option<EmailClient> {
+        self.smtp_client(self.email_to.clone().unwrap_or_default())
+    }
+
+    // Email client of the configured SMTP server sending to the given recipients
+    fn smtp_client(&self, to: Vec<String>) -> Option<EmailClient> {
+        let host = self.smtp_host.clone()?;
+        let port = self.smtp_port.unwrap_or(email::DEFAULT_SMTP_PORT);
+        let mut client = EmailClient::new(host, port, to);
+        if let (Some(username), Some(password)) = (&self.smtp_username, &self.smtp_password) {
+            client = client.with_credentials(username.clone(), password.clone());
+        }
+        Some(
+            client
+                .with_from(self.email_from.clone())
+                .with_environment(self.environment.clone()),
+        )
+    }
+
+    // Clients of the named notifiers
+    pub fn notifier_clients(&self) -> HashMap<String, NotifierClient> {
+        self.notifiers
+            .iter()
+            .flatten()
+            .filter_map(|(name, notifier)| {
+                let client = match notifier {
+                    Notifier::Telegram { chat_id } => NotifierClient::Telegram(self.telegram_chat_client(*chat_id)),
+                    Notifier::Slack { webhook_url } => NotifierClient::Slack(
+                        SlackClient::new(webhook_url.clone()).with_environment(self.environment.clone()),
+                    ),
+                    Notifier::Email { to } => NotifierClient::Email(self.smtp_client(to.clone())?),
+                };
+                Some((name.clone(), client))
+            })
+            .collect()
+    }
+}
+
+// Set the value at `path`, matching existing keys case-insensitively and creating missing mappings.
+// Returns the keys of the outermost value that was replaced or added, and the replaced value.
+fn set_override(
+    node: &mut serde_yaml::Value,
+    path: &[&str],
+    value: serde_yaml::Value,
+) -> anyhow::Result<(Vec<serde_yaml::Value>, Option<serde_yaml::Value>)> {
+    use serde_yaml::Value;
+
+    let Some((segment, rest)) = path.split_first() else {
+        return Ok((Vec::new(), Some(std::mem::replace(node, value))));
+    };
+    // Checks are tagged with their type, overrides apply to the fields inside
+    let node = match node {
+        Value::Tagged(tagged) => &mut tagged.value,
+        node => node,
+    };
+    if node.is_null() {
+        *node = Value::Mapping(Default::default());
+    }
+    let Value::Mapping(mapping) = node else {
+        anyhow::bail!("'{}' is not inside a mapping", segment.to_lowercase());
+    };
+
+    let existing = mapping
+        .keys()
+        .find(|key| key.as_str().is_some_and(|key| key.eq_ignore_ascii_case(segment)))
+        .cloned();
+    let key = existing.clone().unwrap_or_else(|| Value::String(segment.to_lowercase()));
+    let child = mapping.entry(key.clone()).or_insert(Value::Null);
+    // An added key, or a null that becomes a mapping, is restored as a whole
+    let restore = match existing {
+        None => Some(None),
+        Some(_) if child.is_null() && !rest.is_empty() => Some(Some(Value::Null)),
+        Some(_) => None,
+    };
+    let (mut keys, original) = set_override(child, rest, value)?;
+    if let Some(original) = restore {
+        return Ok((vec![key], original));
+    }
+    keys.insert(0, key);
+    Ok((keys, original))
+}
+
+// Undo `set_override`: put back the replaced value at `keys`, or remove the value it added
+fn revert_override(node: &mut serde_yaml::Value, keys: &[serde_yaml::Value], original: Option<serde_yaml::Value>) {
+    use serde_yaml::Value;
+
+    let Some((key, rest)) = keys.split_first() else {
+        if let Some(original) = original {
+            *node = original;
+        }
+        return;
+    };
+    let node = match node {
+        Value::Tagged(tagged) => &mut tagged.value,
+        node => node,
+    };
+    let Value::Mapping(mapping) = node else {
+        return;
+    };
+    match (rest.is_empty(), original) {
+        (true, Some(original)) => {
+            mapping.insert(key.clone(), original);
+        }
+        (true, None) => {
+            mapping.remove(key);
+        }
+        (false, original) => {
+            if let Some(child) = mapping.get_mut(key) {
+                revert_override(child, rest, original);
+            }
+        }
+    }
+}
+
+// Merge the referenced template under every service of a document's `services` mapping
+fn apply_templates(document: &mut serde_yaml::Value, templates: &HashMap<String, serde_yaml::Value>) -> anyhow::Result<()> {
+    let Some(services) = document.get_mut("services").and_then(|s| s.as_mapping_mut()) else {
+        return Ok(());
+    };
+    for (id, service) in services.iter_mut() {
+        let Some(name) = service.get("template").and_then(|t| t.as_str()) else {
+            continue;
+        };
+        let template = templates.get(name).ok_or_else(|| {
+            anyhow::anyhow!("Service '{}' references unknown template '{}'", id.as_str().unwrap_or_default(), name)
+        })?;
+        *service = merge_yaml(template, service.clone());
+    }
+    Ok(())
+}
+
+// Deep-merge `overlay` onto `base`; mappings and checks of the same type merge field by field
+fn merge_yaml(base: &serde_yaml::Value, overlay: serde_yaml::Value) -> serde_yaml::Value {
+    use serde_yaml::Value;
+
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            let mut merged = base.clone();
+            for (key, value) in overlay {
+                let value = match base.get(&key) {
+                    Some(base_value) => merge_yaml(base_value, value),
+                    None => value,
+                };
+                merged.insert(key, value);
+            }
+            Value::Mapping(merged)
+        }
+        (Value::Tagged(base), Value::Tagged(mut overlay)) if base.tag == overlay.tag => {
+            overlay.value = merge_yaml(&base.value, overlay.value);
+            Value::Tagged(overlay)
+        }
+        (_, overlay) => overlay,
+    }
+}
+
+// ServicesFile is an additional configuration file that only contributes services
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct ServicesFile {
+    #[serde(default)]
+    services: HashMap<String, Service>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        Self::load_all(&[path])
+    }
+
+    // Parse a YAML configuration, merging templates into the services referencing them
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        let mut value: serde_yaml::Value = serde_yaml::from_str(contents)?;
+        let templates: HashMap<String, serde_yaml::Value> = match value.get("templates") {
+            Some(templates) => serde_yaml::from_value(templates.clone())?,
+            None => HashMap::new(),
+        };
+        apply_templates(&mut value, &templates)?;
+        Ok(serde_yaml::from_value(value)?)
+    }
+
+    // Load the first file as the main configuration and merge services from the others
+    pub fn load_all<P: AsRef<Path>>(paths: &[P]) -> anyhow::Result<Self> {
+        let (main, others) = paths
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("No configuration file given"))?;
+
+        let contents = std::fs::read_to_string(main)?;
+        let mut config = Config::parse(&contents)?;
+
+        for path in others {
+            let path = path.as_ref();
+            let contents = std::fs::read_to_string(path)?;
+            let mut value: serde_yaml::Value = serde_yaml::from_str(&contents)?;
+            apply_templates(&mut value, config.templates.as_ref().unwrap_or(&HashMap::new()))?;
+            let file: ServicesFile = serde_yaml::from_value(value)?;
+            for (id, service) in file.services {
+                if config.services.contains_key(&id) {
+                    anyhow::bail!("Duplicate service id '{}' in {}", id, path.display());
+                }
+                config.services.insert(id, service);
+            }
+        }
+
+        let config = config.with_overrides(std::env::vars())?;
+        config.validate()?;
+        for warning in config.warnings() {
+            tracing::warn!("Configuration: {}", warning);
+        }
+        Ok(config)
+    }
+
+    // Apply `HEALTHCHECK__<KEY>[__<KEY>...]` variables on top of the loaded configuration,
+    // e.g. HEALTHCHECK__WEB_PORT=9000 or HEALTHCHECK__SERVICES__API__ENABLED=false
+    pub fn with_overrides(self, vars: impl IntoIterator<Item = (String, String)>) -> anyhow::Result<Self> {
+        use serde_yaml::Value;
+
+        let mut overrides: Vec<(String, String)> = vars
+            .into_iter()
+            .filter(|(variable, _)| variable.starts_with(ENV_OVERRIDE_PREFIX))
+            .collect();
+        if overrides.is_empty() {
+            return Ok(self);
+        }
+        overrides.sort();
+
+        let mut document = serde_yaml::to_value(&self)?;
+        let mut applied = Vec::new();
+        for (variable, value) in overrides {
+            let name = &variable[ENV_OVERRIDE_PREFIX.len()..];
+            let path: Vec<&str> = name.split("__").collect();
+            let invalid = |e: anyhow::Error| anyhow::anyhow!("Invalid override {}: {}", variable, e);
+            // Values are parsed as YAML scalars so numbers and booleans keep their type, and taken
+            // as a string when that doesn't fit the setting, e.g. a numeric token
+            let scalar = serde_yaml::from_str(&value).unwrap_or_else(|_| Value::String(value.clone()));
+            let mut overridden = document.clone();
+            let (mut keys, mut original) = set_override(&mut overridden, &path, scalar.clone()).map_err(invalid)?;
+            if !scalar.is_string() && serde_yaml::from_value::<Config>(overridden.clone()).is_err() {
+                overridden = document.clone();
+                (keys, original) = set_override(&mut overridden, &path, Value::String(value.clone())).map_err(invalid)?;
+            }
+            document = overridden;
+            tracing::info!("Configuration value {} overridden from environment", path.join(".").to_lowercase());
+            applied.push(EnvOverride { variable, value, keys, original });
+        }
+        let mut config: Config = serde_yaml::from_value(document)
+            .map_err(|e| anyhow::anyhow!("Invalid configuration after environment overrides: {}", e))?;
+        config.env_overrides = applied;
+        Ok(config)
+    }
+
+    // The configuration as written in the files, for showing and saving it without the
+    // environment overrides
+    pub fn without_overrides(&self) -> anyhow::Result<Self> {
+        if self.env_overrides.is_empty() {
+            return Ok(self.clone());
+        }
+        let mut document = serde_yaml::to_value(self)?;
+        for applied in self.env_overrides.iter().rev() {
+            revert_override(&mut document, &applied.keys, applied.original.clone());
+        }
+        Ok(serde_yaml::from_value(document)?)
+    }
+
+    // Apply the environment overrides of this configuration on top of another one
+    pub fn reapply_overrides(&self, config: Config) -> anyhow::Result<Self> {
+        config.with_overrides(
+            self.env_overrides
+                .iter()
+                .map(|applied| (applied.variable.clone(), applied.value.clone())),
+        )
+    }
+
+    // Settings for a single `--once` cycle. No state is kept between runs, so a failure has to reach
+    // the alert threshold by itself, and alerts can't wait for a coalescing window.
+    pub fn for_single_run(mut self) -> Self {
+        self.notify_failures = 1;
+        self.debounce_checks = None;
+        self.notification_coalesce_ms = None;
+        for service in self.services.values_mut() {
+            service.notify_failures = None;
+            service.debounce_checks = None;
+            service.initial_grace_ms = None;
+        }
+        self
+    }
+
+    // Write the configuration back, keeping services in the file they were loaded from;
+    // new services go to the main file
+    pub fn save_all<P: AsRef<Path>>(&self, paths: &[P]) -> anyhow::Result<()> {
+        let (main, others) = paths
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("No configuration file given"))?;
+
+        let mut main_config = self.without_overrides()?;
+        for path in others {
+            let contents = std::fs::read_to_string(path)?;
+            let existing: ServicesFile = serde_yaml::from_str(&contents)?;
+            let file = ServicesFile {
+                services: existing
+                    .services
+                    .keys()
+                    .filter_map(|id| main_config.services.remove_entry(id))
+                    .collect(),
+            };
+            std::fs::write(path, serde_yaml::to_string(&file)?)?;
+        }
+
+        std::fs::write(main, serde_yaml::to_string(&main_config)?)?;
+        Ok(())
+    }
+
+    // Reject configurations that would misbehave at runtime (zero intervals, empty names, ...)
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.check_interval_success == 0 || self.check_interval_fail == 0 {
+            anyhow::bail!("Check intervals must be greater than zero");
+        }
+        if self.rereport == 0 {
+            anyhow::bail!("rereport must be greater than zero");
+        }
+        if self.watchdog_intervals == Some(0) {
+            anyhow::bail!("watchdog_intervals must be greater than zero");
+        }
+        if self.stale_intervals == Some(0) {
+            anyhow::bail!("stale_intervals must be greater than zero");
+        }
+        if self.history_size == Some(0) {
+            anyhow::bail!("history_size must be greater than zero");
+        }
+        if let Some(bounds) = &self.latency_buckets_ms {
+            if bounds.is_empty() || bounds.windows(2).any(|pair| pair[0] >= pair[1]) {
+                anyhow::bail!("latency_buckets_ms must list at least one bound in ascending order");
+            }
+        }
+        if self.global_check_timeout_ms == Some(0) {
+            anyhow::bail!("global_check_timeout_ms must be greater than zero");
+        }
+        if self.request_timeout_ms == Some(0) || self.config_request_timeout_ms == Some(0) {
+            anyhow::bail!("Request timeouts must be greater than zero");
+        }
+        if self.debounce_checks == Some(0) {
+            anyhow::bail!("debounce_checks must be greater than zero");
+        }
+        if self.max_checks_per_host == Some(0) {
+            anyhow::bail!("max_checks_per_host must be greater than zero");
+        }
+        if let Some(address) = &self.source_address {
+            validate_source_address(address)?;
+        }
+        if let Some(postgres) = &self.postgres_results {
+            postgres.validate()?;
+        }
+        if let Some(url) = &self.slack_webhook_url {
+            reqwest::Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid slack_webhook_url: {}", e))?;
+        }
+        if let Some(channels) = &self.notification_failover {
+            if channels.is_empty() {
+                anyhow::bail!("notification_failover must list at least one channel");
+            }
+            if channels.contains(&NotificationChannel::Slack) && self.slack_webhook_url.is_none() {
+                anyhow::bail!("notification_failover lists slack, but slack_webhook_url is not set");
+            }
+            if channels.contains(&NotificationChannel::Email) && self.smtp_host.is_none() {
+                anyhow::bail!("notification_failover lists email, but smtp_host is not set");
+            }
+        }
+        for (name, notifier) in self.notifiers.iter().flatten() {
+            match notifier {
+                Notifier::Telegram { .. } => {}
+                Notifier::Slack { webhook_url } => {
+                    reqwest::Url::parse(webhook_url)
+                        .map_err(|e| anyhow::anyhow!("Invalid webhook_url of notifier '{}': {}", name, e))?;
+                }
+                Notifier::Email { to } => match self.smtp_client(to.clone()) {
+                    Some(client) => client.validate().map_err(|e| anyhow::anyhow!("Notifier '{}': {}", name, e))?,
+                    None => anyhow::bail!("Notifier '{}' sends email, which requires smtp_host", name),
+                },
+            }
+        }
+        if self.smtp_username.is_some() != self.smtp_password.is_some() {
+            anyhow::bail!("smtp_username and smtp_password must be set together");
+        }
+        match self.email_client() {
+            Some(client) => client.validate()?,
+            None if self.email_to.is_some() => anyhow::bail!("email_to requires smtp_host"),
+            None => {}
+        }
+
+        let mut slugs = HashMap::new();
+        for (id, service) in &self.services {
+            if id.trim().is_empty() {
+                anyhow::bail!("Service identifiers must not be empty");
+            }
+            service.validate(id)?;
+            if matches!(service.check, CheckType::External(_)) && !self.allow_command_checks.unwrap_or(false) {
+                anyhow::bail!("Service '{}' runs a command, which requires allow_command_checks: true", id);
+            }
+            if service.on_failure_command.is_some() && !self.allow_command_checks.unwrap_or(false) {
+                anyhow::bail!("Service '{}' has an on_failure_command, which requires allow_command_checks: true", id);
+            }
+            if let Some(targets) = &service.notify_targets {
+                if targets.is_empty() {
+                    anyhow::bail!("notify_targets of service '{}' must list at least one notifier", id);
+                }
+                for target in targets {
+                    if !self.notifiers.as_ref().is_some_and(|notifiers| notifiers.contains_key(target)) {
+                        anyhow::bail!("Service '{}' notifies '{}', which is not in notifiers", id, target);
+                    }
+                }
+            }
+            if let Some(slug) = &service.slug {
+                if let Some(other) = slugs.insert(slug.as_str(), id) {
+                    anyhow::bail!("Services '{}' and '{}' share the slug '{}'", other, id, slug);
+                }
+                // A slug naming another service's id would make lookups ambiguous
+                if slug != id && self.services.contains_key(slug) {
+                    anyhow::bail!("Slug '{}' of service '{}' is the id of another service", slug, id);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Id of the service with the given id or slug
+    pub fn service_id(&self, id_or_slug: &str) -> Option<String> {
+        if self.services.contains_key(id_or_slug) {
+            return Some(id_or_slug.to_string());
+        }
+        self.services
+            .iter()
+            .find(|(_, service)| service.slug.as_deref() == Some(id_or_slug))
+            .map(|(id, _)| id.clone())
+    }
+}
+
+impl Config {
+    // Suspicious but valid settings, such as services running the exact same check
+    pub fn warnings(&self) -> Vec<String> {
+        let mut by_check: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for (id, service) in &self.services {
+            if let Ok(check) = serde_yaml::to_string(&service.check) {
+                by_check.entry(check).or_default().push(id);
+            }
+        }
+
+        let mut warnings: Vec<String> = by_check
+            .into_values()
+            .filter(|ids| ids.len() > 1)
+            .map(|mut ids| {
+                ids.sort_unstable();
+                format!("Services '{}' have identical checks, possibly a copy-paste mistake", ids.join("', '"))
+            })
+            .collect();
+        warnings.sort();
+        warnings
+    }
+}
+
+fn validate_source_address(address: &str) -> anyhow::Result<()> {
+    address
+        .parse::<IpAddr>()
+        .map(|_| ())
+        .map_err(|_| anyhow::anyhow!("Invalid source_address '{}', expected an IP address", address))
+}
+
+// NotificationKind tells which kind of message a state change produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NotificationKind {
+    Alert,
+    // Rereport edited into the alert message with the given id
+    AlertUpdate(i64),
+    Recovery,
+    Warning,
+    // Neither good nor bad news, like the announcement of a service's first successful check
+    Info,
+}
+
+impl NotificationKind {
+    fn label(&self) -> &'static str {
+        match self {
+            NotificationKind::Alert | NotificationKind::AlertUpdate(_) => "alert",
+            NotificationKind::Recovery => "recovery",
+            NotificationKind::Warning => "warning",
+            NotificationKind::Info => "info",
+        }
+    }
+}
+
+// A result older than `stale_intervals` check intervals no longer reflects reality, it's reported as unknown
+fn reported_state(
+    config: &Config,
+    id: &str,
+    service_state: &ServiceState,
+    stale_intervals: u64,
+    now: DateTime<Utc>,
+) -> ServiceState {
+    let mut service_state = service_state.clone();
+    if let Some(service) = config.services.get(id) {
+        let interval = service.interval(&service_state.state, config);
+        let allowed = chrono::Duration::milliseconds((interval * stale_intervals) as i64);
+        if now - service_state.last_check > allowed {
+            service_state.state = State::Unknown;
+            service_state.status = Status::Unknown;
+            service_state.failure = None;
+        }
+    }
+    service_state
+}
+
+// Restore the counters of results recorded by previous runs. Uptime continues from the
+// stored start when the last result wasn't a failure, the state is checked anew.
+fn restore_counters(store: &StateStore, services: &mut HashMap<String, ServiceState>) {
+    let counters = match store.counters() {
+        Ok(counters) => counters,
+        Err(e) => {
+            tracing::error!("Failed to read counters from database {}: {}", store.path(), e);
+            return;
+        }
+    };
+    for (id, service_state) in services.iter_mut() {
+        if let Some(stored) = counters.get(id) {
+            service_state.total_checks = stored.total_checks;
+            service_state.successful_checks = stored.successful_checks;
+            service_state.failed_checks = stored.failed_checks;
+            service_state.uptime_start = stored.uptime_start;
+        }
+    }
+}
+
+// Restore when the services were first added, services the database hasn't seen yet are added now
+fn restore_added_at(store: &StateStore, services: &mut HashMap<String, ServiceState>, now: DateTime<Utc>) {
+    let ids: Vec<&str> = services.keys().map(String::as_str).collect();
+    let added = match store.added_at(&ids, now) {
+        Ok(added) => added,
+        Err(e) => {
+            tracing::error!("Failed to read service add times from database {}: {}", store.path(), e);
+            return;
+        }
+    };
+    for (id, service_state) in services.iter_mut() {
+        service_state.added_at = added.get(id).copied();
+    }
+}
+
+// Result waiting to be written to the database as (service id, result, held back by debouncing)
+type StoreWrite = (String, CheckResult, bool);
+
+// Alert waiting to be coalesced as (service id, service name, message, sequence), with the sender
+// of whether it was delivered
+type PendingAlert = (String, String, String, u64, tokio::sync::oneshot::Sender<bool>);
+
+// AppState manages the runtime state of all services
+#[derive(Clone)]
+pub struct AppState {
+    services: Arc<RwLock<HashMap<String, ServiceState>>>,
+    config: Arc<RwLock<Config>>,
+    task_handles: Arc<RwLock<HashMap<String, tokio::task::JoinHandle<()>>>>,
+    // Replaced when only notification settings change, see `update_config`
+    telegram: Arc<std::sync::RwLock<Arc<TelegramClient>>>,
+    // Set with `slack_webhook_url`, replaced like the Telegram client
+    slack: Arc<std::sync::RwLock<Option<Arc<SlackClient>>>>,
+    // Set with `smtp_host`, replaced like the Telegram client
+    email: Arc<std::sync::RwLock<Option<Arc<EmailClient>>>>,
+    // Clients of the named notifiers
+    notifiers: Arc<std::sync::RwLock<Arc<HashMap<String, NotifierClient>>>>,
+    config_paths: Arc<Vec<String>>,
+    resolver: Arc<dyn Resolver>,
+    incidents: Arc<RwLock<HashMap<String, Vec<Incident>>>>,
+    // Rolling window of check latencies in milliseconds
+    latencies: Arc<RwLock<HashMap<String, VecDeque<u64>>>>,
+    // Alerts waiting to be coalesced
+    pending_alerts: Arc<std::sync::Mutex<Vec<PendingAlert>>>,
+    // Semaphores limiting concurrent checks per host, rebuilt on config updates
+    host_limits: Arc<std::sync::Mutex<HashMap<String, Arc<Semaphore>>>>,
+    // Recorded check results for /api/events
+    events: Arc<EventLog>,
+    // Writer of `postgres_results`, replaced when its settings change
+    postgres: Arc<std::sync::Mutex<Option<ResultWriter>>>,
+    // Source of the shuffled check order of `randomize_check_order`
+    check_order_rng: Arc<std::sync::Mutex<StdRng>>,
+    // Database of `database_path`, opened at startup
+    store: Option<Arc<StateStore>>,
+    // Time of the last warning about failing database writes, which are reported at most once per interval
+    store_alerted_at: Arc<std::sync::Mutex<Option<DateTime<Utc>>>>,
+    // Queue of the task writing results to the database, started with the first result
+    store_writer: Arc<std::sync::Mutex<Option<tokio::sync::mpsc::UnboundedSender<StoreWrite>>>>,
+    // Latest background delivery per service, each waits for the one before to keep the order
+    deliveries: Arc<std::sync::Mutex<HashMap<String, tokio::task::JoinHandle<()>>>>,
+}
+
+impl AppState {
+    pub fn new(config: Config, config_paths: Vec<String>) -> Self {
+        let now = Utc::now();
+        let mut services = config
+            .services
+            .iter()
+            .filter(|(_, service)| service.enabled)
+            .map(|(id, service)| (id.clone(), ServiceState::new(service, now)))
+            .collect();
+        let retention_days = config.database_retention_days.unwrap_or(DEFAULT_DATABASE_RETENTION_DAYS);
+        let store = config.database_path.as_deref().and_then(|path| match StateStore::open(path, retention_days) {
+            Ok(store) => Some(Arc::new(store)),
+            Err(e) => {
+                tracing::error!("Failed to open database {}, results won't be persisted: {}", path, e);
+                None
+            }
+        });
+        if let Some(store) = &store {
+            restore_counters(store, &mut services);
+            restore_added_at(store, &mut services, now);
+        }
+
+        // Create Telegram client
+        let telegram = Arc::new(std::sync::RwLock::new(Arc::new(config.telegram_client())));
+        let slack = Arc::new(std::sync::RwLock::new(config.slack_client().map(Arc::new)));
+        let email = Arc::new(std::sync::RwLock::new(config.email_client().map(Arc::new)));
+        let notifiers = Arc::new(std::sync::RwLock::new(Arc::new(config.notifier_clients())));
+
+        // HTTP checks resolve through reqwest, the cache serves TCP-based checks
+        let resolver: Arc<dyn Resolver> = match config.dns_cache_ttl_ms {
+            Some(ttl_ms) => Arc::new(CachingResolver::new(Arc::new(SystemResolver), Duration::from_millis(ttl_ms))),
+            None => Arc::new(SystemResolver),
+        };
+
+        Self {
+            services: Arc::new(RwLock::new(services)),
+            config: Arc::new(RwLock::new(config)),
+            task_handles: Arc::new(RwLock::new(HashMap::new())),
+            telegram,
+            slack,
+            email,
+            notifiers,
+            config_paths: Arc::new(config_paths),
+            resolver,
+            incidents: Arc::default(),
+            latencies: Arc::default(),
+            pending_alerts: Arc::default(),
+            host_limits: Arc::default(),
+            events: Arc::default(),
+            postgres: Arc::default(),
+            check_order_rng: Arc::new(std::sync::Mutex::new(StdRng::from_os_rng())),
+            store,
+            store_alerted_at: Arc::default(),
+            store_writer: Arc::default(),
+            deliveries: Arc::default(),
+        }
+    }
+
+    // Seed the shuffled check order, making it reproducible
+    pub fn with_check_order_seed(self, seed: u64) -> Self {
+        *self.check_order_rng.lock().unwrap() = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    // Enabled services in the order a cycle checks them, shuffled anew each cycle
+    // when `randomize_check_order` is set
+    fn check_order<'a>(&self, config: &'a Config) -> Vec<(&'a String, &'a Service)> {
+        let mut services: Vec<_> = config.services.iter().filter(|(_, service)| service.enabled).collect();
+        if config.randomize_check_order.unwrap_or(false) {
+            // Sorted first, the map's own order would make seeded shuffles differ between runs
+            services.sort_by_key(|(id, _)| *id);
+            services.shuffle(&mut *self.check_order_rng.lock().unwrap());
+        }
+        services
+    }
+
+    // With `randomize_check_order`, a random delay of up to a tenth of the interval for the next check,
+    // so services on the same schedule are checked in a different order every cycle
+    fn check_offset(&self, config: &Config, interval: u64) -> u64 {
+        if !config.randomize_check_order.unwrap_or(false) {
+            return 0;
+        }
+        self.check_order_rng.lock().unwrap().random_range(0..=interval / 10)
+    }
+
+    // Current Telegram client
+    fn telegram(&self) -> Arc<TelegramClient> {
+        self.telegram.read().unwrap().clone()
+    }
+
+    // Current Slack client, if configured
+    fn slack(&self) -> Option<Arc<SlackClient>> {
+        self.slack.read().unwrap().clone()
+    }
+
+    // Current email client, if configured
+    fn email(&self) -> Option<Arc<EmailClient>> {
+        self.email.read().unwrap().clone()
+    }
+
+    // Current clients of the named notifiers
+    fn notifiers(&self) -> Arc<HashMap<String, NotifierClient>> {
+        self.notifiers.read().unwrap().clone()
+    }
+
+    // Subscribe to recorded check results, replaying the buffered ones after `last_event_id`
+    pub fn subscribe_events(&self, last_event_id: Option<u64>) -> (Vec<CheckEvent>, tokio::sync::broadcast::Receiver<CheckEvent>) {
+        self.events.subscribe(last_event_id)
+    }
+
+    // Record a check result, false when `debounce_checks` held it back
+    pub async fn set_state(&self, id: String, state: State) -> bool {
+        // Set when the failure threshold is crossed and the service has an on_failure_command,
+        // along with the start of the incident it's run for
+        let mut remediation = None;
+        // Determine notification actions before modifying state
+        let notifications = {
+            let mut services = self.services.write().await;
+            if let Some(service_state) = services.get_mut(&id) {
+                let now = Utc::now();
+                let previous_failures = service_state.consecutive_failures;
+                let was_failing = previous_failures > 0;
+
+                let config = self.config.read().await;
+                let service = config.services.get(&id);
+                let debounce = service
+                    .and_then(|s| s.debounce_checks)
+                    .or(config.debounce_checks)
+                    .unwrap_or(1);
+
+                // Every result is kept, including held back ones
+                service_state.history.push_back((now, state.clone()));
+                let history_size = config.history_size.unwrap_or(DEFAULT_HISTORY_SIZE);
+                while service_state.history.len() > history_size {
+                    service_state.history.pop_front();
+                }
+
+                // A held back result only counts in the statistics
+                let Some(held_back) = service_state.debounce(&state, debounce) else {
+                    service_state.last_check = now;
+                    service_state.total_checks += 1;
+                    match &state {
+                        State::Success | State::Warning(_) => service_state.successful_checks += 1,
+                        State::Failure(_) => service_state.failed_checks += 1,
+                        State::Unknown => {}
+                    }
+                    return false;
+                };
+
+                let previous_state = std::mem::replace(&mut service_state.state, state.clone());
+                service_state.status = Status::from(&state);
+                service_state.failure = match &state {
+                    State::Failure(reason) => Some(reason.clone()),
+                    _ => None,
+                };
+                service_state.last_check = now;
+                service_state.total_checks += 1;
+                self.events.publish(&id, &service_state.name, &state, now);
 
-                let config = self.config.read().await;
-                let service = config.services.get(&id);
                 let notify_failures = service
                     .and_then(|s| s.notify_failures)
                     .unwrap_or(config.notify_failures);
                 let rereport = service
                     .and_then(|s| s.rereport)
                     .unwrap_or(config.rereport);
+                let max_reports = service
+                    .and_then(|s| s.max_reports_per_incident)
+                    .or(config.max_reports_per_incident);
+                let failure_window = service.and_then(|s| s.failure_window.as_ref());
+                let in_grace = service
+                    .and_then(|s| s.initial_grace_ms)
+                    .zip(service_state.added_at)
+                    .is_some_and(|(grace, added_at)| now - added_at < chrono::Duration::milliseconds(grace as i64));
+
+                let mut notifications = Vec::new();
+                match &state {
+                    // A warning means the service is reachable, so it counts as healthy
+                    State::Success | State::Warning(_) => {
+                        service_state.consecutive_failures = 0;
+                        service_state.successful_checks += 1;
+
+                        // Set uptime_start only on first successful check
+                        if service_state.uptime_start.is_none() {
+                            service_state.uptime_start = Some(now);
+                        }
+
+                        // Send recovery notification if was previously failing
+                        if was_failing {
+                            let message = match service_state.down_since {
+                                Some(down_since) => format!("Recovered after {}", format_duration(now - down_since)),
+                                None => "recovered".to_string(),
+                            };
+                            notifications.push((NotificationKind::Recovery, service_state.name.clone(), message));
+                        }
+                        service_state.alert_message_id = None;
+                        service_state.rereports_sent = 0;
+                        service_state.acknowledged = false;
+                        if service_state.down_since.take().is_some() {
+                            self.end_incident(&id, now).await;
+                        }
+
+                        // Warn once when the service enters the warning state
+                        if let State::Warning(reason) = &state {
+                            if !matches!(previous_state, State::Warning(_)) {
+                                notifications.push((NotificationKind::Warning, service_state.name.clone(), reason.clone()));
+                            }
+                        }
+                    }
+                    State::Failure(reason) => {
+                        // Failures while a new service warms up don't count towards alerts
+                        if !in_grace {
+                            // Failures held back by the debounce were consecutive too
+                            service_state.consecutive_failures += held_back + 1;
+                        }
+                        service_state.failed_checks += 1;
+                        // Clear uptime when service fails
+                        service_state.uptime_start = None;
+                        if service_state.down_since.is_none() {
+                            service_state.down_since = Some(now);
+                            self.start_incident(&id, now, &reason.detail).await;
+                        }
+
+                        // Send alert if consecutive failures reached threshold
+                        if service_state.consecutive_failures == notify_failures {
+                            notifications.push((NotificationKind::Alert, service_state.name.clone(), reason.to_string()));
+                            remediation = service
+                                .and_then(|s| s.on_failure_command.clone())
+                                .filter(|_| config.allow_command_checks.unwrap_or(false))
+                                .zip(service_state.down_since);
+                        }
+                        // Resend alert at rereport intervals, up to the per-incident cap
+                        else if service_state.consecutive_failures > notify_failures
+                            && (service_state.consecutive_failures - notify_failures) % rereport == 0
+                            && max_reports.is_none_or(|max| service_state.rereports_sent < max)
+                            && !service_state.acknowledged {
+                            service_state.rereports_sent += 1;
+                            match service_state.alert_message_id {
+                                Some(message_id) => {
+                                    let message = format!(
+                                        "{} (still failing)\n\nConsecutive failures: {}\nLast checked: {}",
+                                        reason,
+                                        service_state.consecutive_failures,
+                                        now.format("%Y-%m-%d %H:%M:%S UTC")
+                                    );
+                                    notifications.push((NotificationKind::AlertUpdate(message_id), service_state.name.clone(), message));
+                                }
+                                None => {
+                                    notifications.push((NotificationKind::Alert, service_state.name.clone(), format!("{} (still failing)", reason)));
+                                }
+                            }
+                        }
+                    }
+                    State::Unknown => {}
+                };
+
+                // Cumulative failures over the recent checks, counted alongside the consecutive ones
+                if let Some(window) = failure_window.filter(|_| state != State::Unknown && !in_grace) {
+                    service_state.recent_failures.push_back(matches!(state, State::Failure(_)));
+                    while service_state.recent_failures.len() as u64 > window.checks {
+                        service_state.recent_failures.pop_front();
+                    }
+                    let failures = service_state.recent_failures.iter().filter(|&&f| f).count() as u64;
+                    if failures == 0 {
+                        service_state.window_alerted = false;
+                    } else if let State::Failure(reason) = &state {
+                        if failures >= window.failures && !service_state.window_alerted {
+                            service_state.window_alerted = true;
+                            // The consecutive threshold may have alerted for this very check
+                            if !notifications.iter().any(|(kind, _, _)| matches!(kind, NotificationKind::Alert)) {
+                                let message = format!(
+                                    "{}\n\n{} of the last {} checks failed",
+                                    reason,
+                                    failures,
+                                    service_state.recent_failures.len()
+                                );
+                                notifications.push((NotificationKind::Alert, service_state.name.clone(), message));
+                            }
+                        }
+                    }
+                }
+
+                // The first result is announced even when it raises nothing, later ones follow the rules above
+                if config.notify_first_check.unwrap_or(false) && !service_state.first_check_notified && state != State::Unknown {
+                    service_state.first_check_notified = true;
+                    if notifications.is_empty() {
+                        let name = service_state.name.clone();
+                        notifications.push(match &state {
+                            State::Failure(reason) => (NotificationKind::Alert, name, format!("First check failed: {}", reason)),
+                            State::Warning(reason) => (NotificationKind::Warning, name, format!("First check: {}", reason)),
+                            _ => (NotificationKind::Info, name, "First check succeeded, monitoring is active".to_string()),
+                        });
+                    }
+                }
+
+                // Number the notifications, so ones overtaken by a newer notification can be dropped
+                notifications
+                    .into_iter()
+                    .map(|(kind, service_name, message)| {
+                        service_state.notification_sequence += 1;
+                        (kind, service_name, message, service_state.notification_sequence)
+                    })
+                    .collect::<Vec<_>>()
+            } else {
+                Vec::new()
+            }
+        }; // Release locks before sending notifications
+
+        // Send notifications in the background (outside of locks), so retries don't hold up the next check
+        if !notifications.is_empty() {
+            self.dispatch(id.clone(), notifications);
+        }
+
+        // The command may take a while, its outcome is added to the incident once it's done
+        if let Some((command, incident_start)) = remediation {
+            let app_state = self.clone();
+            tokio::spawn(async move { app_state.remediate(&id, &command, incident_start).await });
+        }
+        true
+    }
+
+    // Deliver a service's notifications in the background, after the ones dispatched before them
+    fn dispatch(&self, id: String, notifications: Vec<(NotificationKind, String, String, u64)>) {
+        let app_state = self.clone();
+        let mut deliveries = self.deliveries.lock().unwrap();
+        let previous = deliveries.remove(&id);
+        let key = id.clone();
+        let delivery = tokio::spawn(async move {
+            if let Some(previous) = previous {
+                let _ = previous.await;
+            }
+            app_state.notify(&id, notifications).await;
+        });
+        deliveries.insert(key, delivery);
+    }
+
+    // Wait until the notifications dispatched so far are delivered or dead-lettered
+    pub async fn flush_notifications(&self) {
+        let deliveries: Vec<_> = self.deliveries.lock().unwrap().drain().map(|(_, delivery)| delivery).collect();
+        futures::future::join_all(deliveries).await;
+    }
+
+    // Send notifications to the service's notify_targets, or to the default channels
+    async fn notify(&self, id: &str, notifications: Vec<(NotificationKind, String, String, u64)>) {
+        let (runbook_url, targets) = match self.config.read().await.services.get(id) {
+            Some(service) => (service.runbook_url.clone(), service.notify_targets.clone()),
+            None => (None, None),
+        };
+        for (kind, service_name, message, sequence) in notifications {
+            let runbook_url = runbook_url.as_deref();
+            let Some(targets) = &targets else {
+                self.notify_default(kind, Some((id, sequence)), service_name, message, runbook_url).await;
+                continue;
+            };
+
+            // Every named notifier of the service gets the notification
+            let notifiers = self.notifiers();
+            for client in targets.iter().filter_map(|target| notifiers.get(target)) {
+                let sequence = (id, sequence);
+                match client {
+                    NotifierClient::Telegram(telegram) => {
+                        self.notify_telegram_chat(telegram, kind, sequence, &service_name, &message, runbook_url).await;
+                    }
+                    NotifierClient::Slack(slack) => {
+                        self.notify_slack(slack, kind, Some(sequence), &service_name, &message, runbook_url).await;
+                    }
+                    NotifierClient::Email(email) => {
+                        self.notify_email(email, kind, Some(sequence), &service_name, &message, runbook_url).await;
+                    }
+                }
+            }
+        }
+    }
+
+    // Send a notification to the default channels, all of them or the first that works with `notification_failover`
+    async fn notify_default(
+        &self,
+        kind: NotificationKind,
+        sequence: Option<(&str, u64)>,
+        service_name: String,
+        message: String,
+        runbook_url: Option<&str>,
+    ) {
+        let failover = self.config.read().await.notification_failover.clone();
+        let Some(channels) = failover else {
+            if let Some(slack) = self.slack() {
+                self.notify_slack(&slack, kind, sequence, &service_name, &message, runbook_url).await;
+            }
+            if let Some(email) = self.email() {
+                self.notify_email(&email, kind, sequence, &service_name, &message, runbook_url).await;
+            }
+            self.notify_telegram(kind, sequence, service_name, message, runbook_url).await;
+            return;
+        };
+
+        // Channels in priority order, the next one is only used when sending to the previous failed
+        for channel in &channels {
+            let delivered = match channel {
+                NotificationChannel::Telegram => {
+                    self.notify_telegram(kind, sequence, service_name.clone(), message.clone(), runbook_url)
+                        .await
+                }
+                NotificationChannel::Slack => match self.slack() {
+                    Some(slack) => {
+                        self.notify_slack(&slack, kind, sequence, &service_name, &message, runbook_url).await
+                    }
+                    None => false,
+                },
+                NotificationChannel::Email => match self.email() {
+                    Some(email) => {
+                        self.notify_email(&email, kind, sequence, &service_name, &message, runbook_url).await
+                    }
+                    None => false,
+                },
+            };
+            if delivered {
+                break;
+            }
+            tracing::warn!("Failed to notify '{}' via {:?}, trying the next channel", service_name, channel);
+        }
+    }
+
+    // Run a service's on_failure_command and record its outcome in the incident started at `incident_start`,
+    // which may have ended by then
+    async fn remediate(&self, id: &str, command: &str, incident_start: DateTime<Utc>) {
+        tracing::info!("Running on_failure_command of service '{}': {}", id, command);
+        let output = tokio::process::Command::new("sh")
+            .args(["-c", command])
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let outcome = match tokio::time::timeout(ON_FAILURE_COMMAND_TIMEOUT, output).await {
+            Ok(Ok(output)) => {
+                let text = [output.stdout, output.stderr]
+                    .iter()
+                    .map(|stream| String::from_utf8_lossy(stream).trim().to_string())
+                    .filter(|stream| !stream.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let status = match output.status.code() {
+                    Some(code) => format!("Exited with status {}", code),
+                    None => "Terminated by a signal".to_string(),
+                };
+                match text.char_indices().nth(MAX_REMEDIATION_OUTPUT) {
+                    _ if text.is_empty() => status,
+                    Some((end, _)) => format!("{}: {}...", status, &text[..end]),
+                    None => format!("{}: {}", status, text),
+                }
+            }
+            Ok(Err(e)) => format!("Failed to run: {}", e),
+            Err(_) => format!("Timed out after {}s", ON_FAILURE_COMMAND_TIMEOUT.as_secs()),
+        };
+        tracing::info!("on_failure_command of service '{}' finished: {}", id, outcome);
+
+        let mut incidents = self.incidents.write().await;
+        let incident = incidents.get_mut(id).and_then(|i| i.iter_mut().rev().find(|i| i.started_at == incident_start));
+        if let Some(incident) = incident {
+            incident.remediation = Some(outcome);
+        }
+    }
+
+    // Send a notification to Telegram, returning whether it was delivered
+    async fn notify_telegram(
+        &self,
+        kind: NotificationKind,
+        sequence: Option<(&str, u64)>,
+        service_name: String,
+        message: String,
+        runbook_url: Option<&str>,
+    ) -> bool {
+        let message = match (&kind, runbook_url) {
+            (NotificationKind::Alert | NotificationKind::AlertUpdate(_), Some(url)) => {
+                format!("{}\n\n{}", message, telegram::runbook_link(url))
+            }
+            _ => message,
+        };
+        // Notifications about the monitor itself belong to no service, so they're never coalesced or edited
+        let Some((id, sequence)) = sequence else {
+            let telegram = self.telegram();
+            return self
+                .deliver(kind, None, &service_name, &message, || async {
+                    match kind {
+                        NotificationKind::Alert | NotificationKind::AlertUpdate(_) => {
+                            telegram.send_alert(&service_name, &message).await
+                        }
+                        NotificationKind::Recovery => telegram.send_recovery(&service_name, &message).await,
+                        NotificationKind::Warning => telegram.send_warning(&service_name, &message).await,
+                        NotificationKind::Info => telegram.send_info(&service_name, &message).await,
+                    }
+                })
+                .await
+                .is_some();
+        };
+        match kind {
+            NotificationKind::Alert => self.send_alert(id, sequence, service_name, message).await,
+            NotificationKind::AlertUpdate(message_id) => {
+                if self.is_overtaken(id, sequence).await {
+                    tracing::info!("Dropping rereport of '{}' overtaken by a newer notification", service_name);
+                    return false;
+                }
+                if let Err(e) = self.telegram().edit_alert(message_id, id, &service_name, &message).await {
+                    tracing::warn!("Failed to edit alert for '{}', sending a new one: {}", service_name, e);
+                    return self.send_alert(id, sequence, service_name, message).await;
+                }
+                self.mark_sent(id, sequence).await;
+                true
+            }
+            NotificationKind::Recovery => {
+                let telegram = self.telegram();
+                self.deliver(kind, Some((id, sequence)), &service_name, &message, || {
+                    telegram.send_recovery(&service_name, &message)
+                })
+                .await
+                .is_some()
+            }
+            NotificationKind::Warning => {
+                let telegram = self.telegram();
+                self.deliver(kind, Some((id, sequence)), &service_name, &message, || {
+                    telegram.send_warning(&service_name, &message)
+                })
+                .await
+                    .is_some()
+            }
+            NotificationKind::Info => {
+                let telegram = self.telegram();
+                self.deliver(kind, Some((id, sequence)), &service_name, &message, || {
+                    telegram.send_info(&service_name, &message)
+                })
+                .await
+                .is_some()
+            }
+        }
+    }
+
+    // Send a notification to a notifier's Telegram chat; only alerts in the default chat are edited
+    // on rereports and coalesced, so rereports arrive here as new alerts
+    async fn notify_telegram_chat(
+        &self,
+        telegram: &TelegramClient,
+        kind: NotificationKind,
+        sequence: (&str, u64),
+        service_name: &str,
+        message: &str,
+        runbook_url: Option<&str>,
+    ) -> bool {
+        let message = match (&kind, runbook_url) {
+            (NotificationKind::Alert | NotificationKind::AlertUpdate(_), Some(url)) => {
+                format!("{}\n\n{}", message, telegram::runbook_link(url))
+            }
+            _ => message.to_string(),
+        };
+        let (id, _) = sequence;
+        self.deliver(kind, Some(sequence), service_name, &message, || async {
+            match kind {
+                NotificationKind::Alert | NotificationKind::AlertUpdate(_) => {
+                    telegram.send_service_alert(id, service_name, &message).await
+                }
+                NotificationKind::Recovery => telegram.send_recovery(service_name, &message).await,
+                NotificationKind::Warning => telegram.send_warning(service_name, &message).await,
+                NotificationKind::Info => telegram.send_info(service_name, &message).await,
+            }
+        })
+        .await
+        .is_some()
+    }
+
+    // Post a notification to Slack, whose webhook messages can't be edited so rereports are sent anew
+    async fn notify_slack(
+        &self,
+        slack: &SlackClient,
+        kind: NotificationKind,
+        sequence: Option<(&str, u64)>,
+        service_name: &str,
+        message: &str,
+        runbook_url: Option<&str>,
+    ) -> bool {
+        let runbook = runbook_url.map(slack::runbook_link);
+        self.deliver_via("slack", kind, sequence, service_name, message, || async {
+            match kind {
+                NotificationKind::Alert | NotificationKind::AlertUpdate(_) => {
+                    slack.send_alert(service_name, message, runbook.as_deref()).await
+                }
+                NotificationKind::Recovery => slack.send_recovery(service_name, message).await,
+                NotificationKind::Warning => slack.send_warning(service_name, message).await,
+                NotificationKind::Info => slack.send_info(service_name, message).await,
+            }
+        })
+        .await
+        .is_some()
+    }
+
+    // Email a notification, rereports are sent as new alerts as with Slack
+    async fn notify_email(
+        &self,
+        email: &EmailClient,
+        kind: NotificationKind,
+        sequence: Option<(&str, u64)>,
+        service_name: &str,
+        message: &str,
+        runbook_url: Option<&str>,
+    ) -> bool {
+        let runbook = runbook_url.map(email::runbook_link);
+        self.deliver_via("email", kind, sequence, service_name, message, || async {
+            match kind {
+                NotificationKind::Alert | NotificationKind::AlertUpdate(_) => {
+                    email.send_alert(service_name, message, runbook.as_deref()).await
+                }
+                NotificationKind::Recovery => email.send_recovery(service_name, message).await,
+                NotificationKind::Warning => email.send_warning(service_name, message).await,
+                NotificationKind::Info => email.send_info(service_name, message).await,
+            }
+        })
+        .await
+        .is_some()
+    }
+
+    // Send a Telegram notification, see `deliver_via`
+    async fn deliver<F, Fut>(
+        &self,
+        kind: NotificationKind,
+        sequence: Option<(&str, u64)>,
+        service_name: &str,
+        message: &str,
+        send: F,
+    ) -> Option<i64>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = anyhow::Result<i64>>,
+    {
+        self.deliver_via("telegram", kind, sequence, service_name, message, send).await
+    }
+
+    // Send a notification, retrying with backoff; a notification that still fails is dead-lettered.
+    // With a sequence (service id, number) it's dropped once a newer notification of the service was sent.
+    async fn deliver_via<F, Fut, T>(
+        &self,
+        channel: &str,
+        kind: NotificationKind,
+        sequence: Option<(&str, u64)>,
+        service_name: &str,
+        message: &str,
+        send: F,
+    ) -> Option<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let (retries, dead_letter_path) = {
+            let config = self.config.read().await;
+            (
+                config.notification_retries.unwrap_or(DEFAULT_NOTIFICATION_RETRIES),
+                config.dead_letter_path.clone(),
+            )
+        };
+
+        let mut backoff = NOTIFICATION_RETRY_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            if let Some((id, number)) = sequence {
+                if self.is_overtaken(id, number).await {
+                    tracing::info!(
+                        "Dropping {} {} notification of '{}' overtaken by a newer notification",
+                        channel,
+                        kind.label(),
+                        service_name
+                    );
+                    return None;
+                }
+            }
+            match send().await {
+                Ok(message_id) => {
+                    if let Some((id, number)) = sequence {
+                        self.mark_sent(id, number).await;
+                    }
+                    return Some(message_id);
+                }
+                Err(e) if attempt < retries => {
+                    attempt += 1;
+                    tracing::warn!(
+                        "Failed to send {} notification (attempt {}/{}), retrying in {:?}: {}",
+                        channel,
+                        attempt,
+                        retries + 1,
+                        backoff,
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(e) => {
+                    let letter = DeadLetter {
+                        timestamp: Utc::now(),
+                        channel: channel.to_string(),
+                        service: service_name.to_string(),
+                        state: kind.label().to_string(),
+                        message: message.to_string(),
+                        error: e.to_string(),
+                    };
+                    dead_letter::record(dead_letter_path.as_deref(), &letter).await;
+                    return None;
+                }
+            }
+        }
+    }
+
+    // Whether a newer notification of the service was already sent, with `order_notifications`
+    async fn is_overtaken(&self, id: &str, sequence: u64) -> bool {
+        if !self.config.read().await.order_notifications.unwrap_or(false) {
+            return false;
+        }
+        self.services.read().await.get(id).is_some_and(|s| sequence < s.sent_sequence)
+    }
+
+    // Whether the service is no longer failing, with `order_notifications`
+    async fn has_recovered(&self, id: &str) -> bool {
+        if !self.config.read().await.order_notifications.unwrap_or(false) {
+            return false;
+        }
+        self.services.read().await.get(id).is_some_and(|s| s.consecutive_failures == 0)
+    }
+
+    async fn mark_sent(&self, id: &str, sequence: u64) {
+        if let Some(service_state) = self.services.write().await.get_mut(id) {
+            service_state.sent_sequence = service_state.sent_sequence.max(sequence);
+        }
+    }
+
+    async fn start_incident(&self, id: &str, now: DateTime<Utc>, reason: &str) {
+        let mut incidents = self.incidents.write().await;
+        let service_incidents = incidents.entry(id.to_string()).or_default();
+        service_incidents.push(Incident {
+            started_at: now,
+            ended_at: None,
+            reason: reason.to_string(),
+            remediation: None,
+        });
+        if service_incidents.len() > MAX_INCIDENTS {
+            service_incidents.remove(0);
+        }
+    }
+
+    async fn end_incident(&self, id: &str, now: DateTime<Utc>) {
+        let mut incidents = self.incidents.write().await;
+        if let Some(incident) = incidents.get_mut(id).and_then(|i| i.last_mut()) {
+            incident.ended_at.get_or_insert(now);
+        }
+    }
+
+    // Uptime over a date range computed from recorded incidents, None for unknown services
+    pub async fn uptime_report(
+        &self,
+        id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Option<UptimeReport> {
+        let monitored_since = self.services.read().await.get(id)?.monitored_since;
+        let maintenance = self
+            .config
+            .read()
+            .await
+            .services
+            .get(id)
+            .and_then(|s| s.maintenance.clone())
+            .unwrap_or_default();
+        let incidents = self.incidents.read().await;
+        let now = Utc::now();
+
+        Some(history::uptime_report(
+            incidents.get(id).map(Vec::as_slice).unwrap_or_default(),
+            &maintenance,
+            monitored_since,
+            from.unwrap_or(monitored_since),
+            to.unwrap_or(now),
+            now,
+        ))
+    }
+
+    // Remember the certificate fingerprint, warning when it differs from the previous one
+    pub async fn record_fingerprint(&self, id: &str, fingerprint: String) {
+        let notification = {
+            let mut services = self.services.write().await;
+            let Some(service_state) = services.get_mut(id) else {
+                return;
+            };
+            let previous = service_state.certificate_fingerprint.replace(fingerprint.clone());
+            let Some(previous) = previous.filter(|previous| *previous != fingerprint) else {
+                return;
+            };
+            tracing::warn!("Certificate of service '{}' changed to {}", service_state.name, fingerprint);
+            let message = format!(
+                "Certificate changed\nPrevious SHA-256: {}\nCurrent SHA-256: {}",
+                previous, fingerprint
+            );
+            service_state.notification_sequence += 1;
+            (NotificationKind::Warning, service_state.name.clone(), message, service_state.notification_sequence)
+        };
+
+        // Routed like the service's state changes
+        self.dispatch(id.to_string(), vec![notification]);
+    }
+
+    pub async fn record_latency(&self, id: &str, latency: Duration) {
+        let mut latencies = self.latencies.write().await;
+        let window = latencies.entry(id.to_string()).or_default();
+        window.push_back(latency.as_millis() as u64);
+        if window.len() > LATENCY_WINDOW {
+            window.pop_front();
+        }
+    }
+
+    // Post the check result to the result webhook in the background, failures are only logged
+    pub async fn publish_result(&self, id: &str, service_name: &str, state: &State, latency: Duration) {
+        let (url, postgres_results) = {
+            let config = self.config.read().await;
+            (config.result_webhook_url.clone(), config.postgres_results.clone())
+        };
+        let result = CheckResult {
+            service: service_name.to_string(),
+            state: state.clone(),
+            latency_ms: latency.as_millis() as u64,
+            timestamp: Utc::now(),
+        };
+
+        {
+            let mut postgres = self.postgres.lock().unwrap();
+            if postgres.as_ref().map(ResultWriter::settings) != postgres_results.as_ref() {
+                // Dropping the previous writer still writes its queued results
+                *postgres = postgres_results.map(ResultWriter::start);
+            }
+            if let Some(writer) = postgres.as_ref() {
+                writer.write(postgres::ResultRow {
+                    service_id: id.to_string(),
+                    service: result.service.clone(),
+                    state: result.state.clone(),
+                    latency_ms: result.latency_ms as i64,
+                    checked_at: result.timestamp,
+                });
+            }
+        }
+
+        let Some(url) = url else {
+            return;
+        };
+
+        tokio::spawn(async move {
+            let response = reqwest::Client::new().post(&url).json(&result).send().await;
+            match response.and_then(|r| r.error_for_status()) {
+                Ok(_) => tracing::debug!("Posted result of '{}' to webhook", result.service),
+                Err(e) => tracing::warn!("Failed to post result of '{}' to webhook: {}", result.service, e),
+            }
+        });
+    }
+
+    // Queue the result for the task writing to the database, which keeps the order of the results
+    fn persist_result(&self, id: &str, service_name: &str, state: &State, latency: Duration, held_back: bool) {
+        let Some(store) = self.store.clone() else {
+            return;
+        };
+        let result = CheckResult {
+            service: service_name.to_string(),
+            state: state.clone(),
+            latency_ms: latency.as_millis() as u64,
+            timestamp: Utc::now(),
+        };
+
+        let mut writer = self.store_writer.lock().unwrap();
+        let sender = writer.get_or_insert_with(|| {
+            let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<StoreWrite>();
+            let app_state = self.clone();
+            tokio::spawn(async move {
+                while let Some((id, result, held_back)) = receiver.recv().await {
+                    app_state.write_result(store.clone(), id, result, held_back).await;
+                }
+            });
+            sender
+        });
+        let _ = sender.send((id.to_string(), result, held_back));
+    }
+
+    // Write the result to the database, warning when it fails unless a warning was sent
+    // within `database_alert_interval_ms`
+    async fn write_result(&self, store: Arc<StateStore>, id: String, result: CheckResult, held_back: bool) {
+        let path = store.path().to_string();
+        let written = tokio::task::spawn_blocking(move || {
+            store.record(&id, &result.state, result.latency_ms, result.timestamp, held_back)
+        })
+        .await;
+        let e = match written {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) => e,
+            Err(e) => {
+                tracing::error!("Database write panicked: {}", e);
+                return;
+            }
+        };
+        tracing::error!("Failed to write result to database {}: {}", path, e);
+
+        let interval = self.config.read().await.database_alert_interval_ms.unwrap_or(DEFAULT_DATABASE_ALERT_INTERVAL_MS);
+        let now = Utc::now();
+        {
+            let mut alerted_at = self.store_alerted_at.lock().unwrap();
+            if alerted_at.is_some_and(|at| now - at < chrono::Duration::milliseconds(interval as i64)) {
+                return;
+            }
+            *alerted_at = Some(now);
+        }
+        let message = format!("Check results can't be written to {}: {}", path, e);
+        self.notify_default(NotificationKind::Warning, None, "healthcheck".to_string(), message, None).await;
+    }
+
+    // Distribution of check latencies over the rolling window, None for unknown services
+    pub async fn latency_histogram(&self, id: &str) -> Option<LatencyHistogram> {
+        if !self.services.read().await.contains_key(id) {
+            return None;
+        }
+        let bounds = self
+            .config
+            .read()
+            .await
+            .latency_buckets_ms
+            .clone()
+            .unwrap_or_else(|| DEFAULT_LATENCY_BUCKETS_MS.to_vec());
+        let latencies = self.latencies.read().await;
+        let window: Vec<u64> = latencies.get(id).map(|w| w.iter().copied().collect()).unwrap_or_default();
+
+        Some(slo::latency_histogram(&window, &bounds))
+    }
+
+    // Most recent check results, oldest first, None for unknown services
+    pub async fn check_history(&self, id: &str) -> Option<Vec<CheckRecord>> {
+        let services = self.services.read().await;
+        let service_state = services.get(id)?;
+        Some(
+            service_state
+                .history
+                .iter()
+                .map(|(timestamp, state)| CheckRecord {
+                    timestamp: *timestamp,
+                    status: Status::from(state),
+                    state: state.clone(),
+                })
+                .collect(),
+        )
+    }
+
+    // Latency percentiles and SLO compliance over the rolling window, None for unknown services
+    pub async fn slo_report(&self, id: &str) -> Option<SloReport> {
+        if !self.services.read().await.contains_key(id) {
+            return None;
+        }
+        let target_latency_ms = self.config.read().await.services.get(id)?.target_latency_ms;
+        let latencies = self.latencies.read().await;
+        let window: Vec<u64> = latencies.get(id).map(|w| w.iter().copied().collect()).unwrap_or_default();
+
+        Some(slo::slo_report(&window, target_latency_ms))
+    }
+
+    // Send an alert, collapsing alerts raised within `notification_coalesce_ms` into one message.
+    // Returns whether it was delivered, for coalesced alerts once the window's message was sent.
+    async fn send_alert(&self, id: &str, sequence: u64, service_name: String, message: String) -> bool {
+        let window = self.config.read().await.notification_coalesce_ms.unwrap_or(0);
+        if window == 0 {
+            let telegram = self.telegram();
+            let sent = self
+                .deliver(NotificationKind::Alert, Some((id, sequence)), &service_name, &message, || {
+                    telegram.send_service_alert(id, &service_name, &message)
+                })
+                .await;
+            if let Some(message_id) = sent {
+                self.store_alert_message(id, message_id).await;
+            }
+            return sent.is_some();
+        }
+
+        // The first alert of a window schedules the flush, later ones just join it
+        let (delivered, outcome) = tokio::sync::oneshot::channel();
+        let first = {
+            let mut pending = self.pending_alerts.lock().unwrap();
+            pending.push((id.to_string(), service_name, message, sequence, delivered));
+            pending.len() == 1
+        };
+        if first {
+            let app_state = self.clone();
+            tokio::spawn(async move { app_state.flush_alerts(window).await });
+        }
+        // Waiting for the outcome lets `notification_failover` move on when the combined message failed
+        outcome.await.unwrap_or(false)
+    }
+
+    // Send the alerts collected during a coalescing window, then tell each whether it was delivered
+    async fn flush_alerts(&self, window: u64) {
+        tokio::time::sleep(Duration::from_millis(window)).await;
+        let queued = std::mem::take(&mut *self.pending_alerts.lock().unwrap());
+        let telegram = self.telegram();
+
+        // Services may have recovered while their alert was waiting, its recovery waits behind it.
+        // A dropped alert counts as delivered, there's nothing left for `notification_failover` to send.
+        let mut alerts = Vec::new();
+        for alert in queued {
+            if self.is_overtaken(&alert.0, alert.3).await || self.has_recovered(&alert.0).await {
+                tracing::info!("Dropping alert of '{}' overtaken by a newer notification", alert.1);
+                let _ = alert.4.send(true);
+            } else {
+                alerts.push(alert);
+            }
+        }
+
+        // A combined message covers several services, so only a single alert can be edited later
+        let delivered = match alerts.as_slice() {
+            [] => false,
+            [(id, service_name, message, sequence, _)] => {
+                let sent = self
+                    .deliver(NotificationKind::Alert, Some((id, *sequence)), service_name, message, || {
+                        telegram.send_service_alert(id, service_name, message)
+                    })
+                    .await;
+                if let Some(message_id) = sent {
+                    self.store_alert_message(id, message_id).await;
+                }
+                sent.is_some()
+            }
+            _ => {
+                let lines: Vec<(String, String)> = alerts
+                    .iter()
+                    .map(|(_, service_name, message, _, _)| (service_name.clone(), message.clone()))
+                    .collect();
+                let service_names: Vec<&str> = lines.iter().map(|(name, _)| name.as_str()).collect();
+                let messages: Vec<&str> = lines.iter().map(|(_, message)| message.as_str()).collect();
+                // Retries continue with the part that failed
+                let sent_parts = std::sync::atomic::AtomicUsize::new(0);
+                let sent = self
+                    .deliver(NotificationKind::Alert, None, &service_names.join(", "), &messages.join("\n"), || {
+                        telegram.send_alerts(&lines, &sent_parts)
+                    })
+                    .await;
+                if sent.is_some() {
+                    for (id, _, _, sequence, _) in &alerts {
+                        self.mark_sent(id, *sequence).await;
+                    }
+                }
+                sent.is_some()
+            }
+        };
+        for (_, _, _, _, outcome) in alerts {
+            let _ = outcome.send(delivered);
+        }
+    }
+
+    // Remember the alert message so rereports edit it, unless the service already recovered
+    async fn store_alert_message(&self, id: &str, message_id: i64) {
+        let mut services = self.services.write().await;
+        if let Some(service_state) = services.get_mut(id) {
+            if service_state.consecutive_failures > 0 {
+                service_state.alert_message_id = Some(message_id);
+            }
+        }
+    }
+
+    // Check context built from the current configuration and shared resources
+    pub async fn check_context(&self) -> CheckContext {
+        CheckContext {
+            resolver: self.resolver.clone(),
+            ..self.config.read().await.check_context()
+        }
+    }
+
+    pub async fn record_loop_iteration(&self, id: &str) {
+        let mut services = self.services.write().await;
+        if let Some(service_state) = services.get_mut(id) {
+            service_state.last_loop_iteration = Utc::now();
+        }
+    }
+
+    // Services whose monitoring loop hasn't iterated within `watchdog_intervals` check intervals
+    pub async fn stalled_services(&self) -> Vec<StalledService> {
+        let services = self.services.read().await;
+        let config = self.config.read().await;
+        let intervals = config.watchdog_intervals.unwrap_or(DEFAULT_WATCHDOG_INTERVALS);
+        let now = Utc::now();
+
+        let mut stalled: Vec<StalledService> = services
+            .iter()
+            .filter_map(|(id, service_state)| {
+                let service = config.services.get(id)?;
+                let interval = service.interval(&service_state.state, &config);
+                let allowed = chrono::Duration::milliseconds((interval * intervals) as i64);
+
+                if now - service_state.last_loop_iteration > allowed {
+                    Some(StalledService {
+                        id: id.clone(),
+                        name: service_state.name.clone(),
+                        last_loop_iteration: service_state.last_loop_iteration,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect();
+        stalled.sort_by(|a, b| a.id.cmp(&b.id));
+        stalled
+    }
+
+    // Send the startup notification when `notify_on_startup` is enabled
+    pub async fn notify_startup(&self) {
+        let enabled = {
+            let config = self.config.read().await;
+            if !config.notify_on_startup.unwrap_or(false) {
+                return;
+            }
+            config.services.values().filter(|s| s.enabled).count()
+        };
+        if let Err(e) = self.telegram().send_startup(env!("CARGO_PKG_VERSION"), enabled).await {
+            tracing::warn!("Failed to send startup notification: {}", e);
+        }
+    }
+
+    // Periodically look for stalled monitoring tasks and alert once per stall
+    pub fn start_watchdog(&self) -> tokio::task::JoinHandle<()> {
+        let app_state = self.clone();
+        tokio::spawn(async move {
+            let mut alerted: HashSet<String> = HashSet::new();
+            loop {
+                tokio::time::sleep(WATCHDOG_PERIOD).await;
+
+                let stalled = app_state.stalled_services().await;
+                alerted.retain(|id| stalled.iter().any(|s| &s.id == id));
+
+                for service in stalled {
+                    if !alerted.insert(service.id.clone()) {
+                        continue;
+                    }
+                    tracing::error!(
+                        "Monitoring task for service '{}' stalled (last iteration: {})",
+                        service.name,
+                        service.last_loop_iteration
+                    );
+                    let message = format!(
+                        "Monitoring task stalled, last check loop iteration at {}",
+                        service.last_loop_iteration.format("%Y-%m-%d %H:%M:%S UTC")
+                    );
+                    let telegram = app_state.telegram();
+                    app_state
+                        .deliver(NotificationKind::Alert, None, &service.name, &message, || {
+                            telegram.send_alert(&service.name, &message)
+                        })
+                        .await;
+                }
+            }
+        })
+    }
+
+    pub async fn get_all_services(&self) -> Vec<ServiceState> {
+        let services = self.services.read().await;
+        let config = self.config.read().await;
+        let stale_intervals = config.stale_intervals.unwrap_or(DEFAULT_STALE_INTERVALS);
+        let now = Utc::now();
+
+        let mut result: Vec<ServiceState> = services
+            .iter()
+            .map(|(id, service_state)| reported_state(&config, id, service_state, stale_intervals, now))
+            .collect();
+        result.sort_by_key(|s| s.name.to_lowercase());
+        result
+    }
+
+    // State of a single service as reported by `get_all_services`, None for unknown or disabled services
+    pub async fn get_service(&self, id: &str) -> Option<ServiceState> {
+        let services = self.services.read().await;
+        let config = self.config.read().await;
+        let stale_intervals = config.stale_intervals.unwrap_or(DEFAULT_STALE_INTERVALS);
+        services
+            .get(id)
+            .map(|service_state| reported_state(&config, id, service_state, stale_intervals, Utc::now()))
+    }
+
+    pub async fn get_config(&self) -> Config {
+        self.config.read().await.clone()
+    }
+
+    // Wait until a check of the check's host may start, None when checks aren't limited
+    async fn host_permit(&self, check: &CheckType) -> Option<OwnedSemaphorePermit> {
+        let limit = self.config.read().await.max_checks_per_host?;
+        let host = check.host()?;
+        let semaphore = self
+            .host_limits
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(limit)))
+            .clone();
+        semaphore.acquire_owned().await.ok()
+    }
+
+    // Check every enabled service once, concurrently, and return the resulting states
+    pub async fn run_once(&self) -> Vec<ServiceState> {
+        let config = self.config.read().await.clone();
+        // join_all polls in order, so earlier checks are first to take host slots
+        let checks = self
+            .check_order(&config)
+            .into_iter()
+            .map(|(id, service)| service.run_once(id, self));
+        futures::future::join_all(checks).await;
+        self.flush_notifications().await;
+        self.get_all_services().await
+    }
+
+    pub async fn start_monitoring_tasks(&self) {
+        let config = self.config.read().await;
+        let mut handles = self.task_handles.write().await;
+
+        for service in config.services.values().filter(|service| !service.enabled) {
+            tracing::info!("Service '{}' is disabled, skipping", service.name);
+        }
+
+        for (uuid, service) in self.check_order(&config) {
+            tracing::info!("Starting monitor for service '{}'", service.name);
+            let service_clone = service.clone();
+            let state_clone = self.clone();
+            let id_clone = uuid.clone();
+
+            let handle = tokio::spawn(async move {
+                supervise(&service_clone.name, || {
+                    service_clone.run(id_clone.clone(), state_clone.clone())
+                })
+                .await;
+            });
+
+            handles.insert(uuid.clone(), handle);
+        }
+    }
+
+    // Suppress rereports of a failing service until it recovers
+    pub async fn acknowledge(&self, id: &str) -> Option<String> {
+        let mut services = self.services.write().await;
+        let service_state = services.get_mut(id)?;
+        if service_state.consecutive_failures == 0 {
+            return Some(format!("{} is not failing", service_state.name));
+        }
+        service_state.acknowledged = true;
+        Some(format!("Acknowledged {}", service_state.name))
+    }
+
+    // Run a service's check immediately and record the result
+    pub async fn recheck(&self, id: &str) -> Option<String> {
+        let service = self.config.read().await.services.get(id)?.clone();
+        let state = service.check(&self.check_context().await).await;
+        self.set_state(id.to_string(), state.clone()).await;
+
+        Some(match state {
+            State::Success => format!("{}: OK", service.name),
+            State::Warning(reason) => format!("{}: warning, {}", service.name, reason),
+            State::Failure(reason) => format!("{}: still failing, {}", service.name, reason),
+            State::Unknown => format!("{}: unknown", service.name),
+        })
+    }
+
+    // Perform the action of an alert button and return the text shown to the user
+    pub async fn handle_callback(&self, data: &str) -> String {
+        let result = match data.split_once(':') {
+            Some((telegram::RECHECK_ACTION, id)) => self.recheck(id).await,
+            Some((telegram::ACKNOWLEDGE_ACTION, id)) => self.acknowledge(id).await,
+            _ => return "Unknown action".to_string(),
+        };
+        result.unwrap_or_else(|| "Service not found".to_string())
+    }
+
+    // Poll Telegram for alert button presses from the configured chat
+    pub fn start_telegram_actions(&self) -> tokio::task::JoinHandle<()> {
+        let app_state = self.clone();
+        tokio::spawn(async move {
+            let mut offset = 0;
+            loop {
+                let updates = match app_state.telegram().get_updates(offset, TELEGRAM_POLL_TIMEOUT_SECS).await {
+                    Ok(updates) => updates,
+                    Err(e) => {
+                        tracing::warn!("Failed to poll Telegram updates: {}", e);
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        continue;
+                    }
+                };
+
+                for update in updates {
+                    offset = offset.max(update.update_id + 1);
+                    let Some(query) = update.callback_query else {
+                        continue;
+                    };
+                    let from_chat = query.message.as_ref().map(|m| m.chat.id) == Some(app_state.telegram().chat_id());
+                    let reply = match (&query.data, from_chat) {
+                        (Some(data), true) => app_state.handle_callback(data).await,
+                        _ => "Not allowed".to_string(),
+                    };
+                    if let Err(e) = app_state.telegram().answer_callback_query(&query.id, &reply).await {
+                        tracing::warn!("Failed to answer Telegram callback: {}", e);
+                    }
+                }
+            }
+        })
+    }
+
+    pub async fn stop_all_tasks(&self) {
+        tracing::info!("Stopping all monitoring tasks");
+        let mut handles = self.task_handles.write().await;
+
+        for (id, handle) in handles.drain() {
+            tracing::debug!("Aborting task for service ID: {}", id);
+            handle.abort();
+        }
+    }
+
+    pub async fn update_config(&self, new_config: Config) -> anyhow::Result<()> {
+        let current = self.config.read().await.clone();
+        // Environment overrides keep applying on top of the new configuration
+        let mut new_config = current.reapply_overrides(new_config)?;
+        new_config.validate()?;
+        // An unchanged @handle keeps its resolved id, a new one is resolved before anything changes
+        if let (ChatId::Handle { handle, id: None }, ChatId::Handle { handle: current_handle, id: Some(id) }) =
+            (&new_config.telegram_chat_id, &current.telegram_chat_id)
+        {
+            if handle == current_handle {
+                new_config.telegram_chat_id = ChatId::Handle { handle: handle.clone(), id: Some(*id) };
+            }
+        }
+        new_config.resolve_telegram_chat_id().await?;
+
+        if current.differs_only_in_notifications(&new_config) {
+            tracing::info!("Only notification settings changed, keeping monitoring tasks running");
+            new_config.save_all(&self.config_paths)?;
+            *self.telegram.write().unwrap() = Arc::new(new_config.telegram_client());
+            *self.slack.write().unwrap() = new_config.slack_client().map(Arc::new);
+            *self.email.write().unwrap() = new_config.email_client().map(Arc::new);
+            *self.notifiers.write().unwrap() = Arc::new(new_config.notifier_clients());
+            *self.config.write().await = new_config;
+            return Ok(());
+        }
+        tracing::info!("Updating configuration and restarting tasks");
+
+        // Stop all existing tasks
+        self.stop_all_tasks().await;
+
+        // Write configuration to file
+        tracing::info!("Writing configuration to {}", self.config_paths.join(", "));
+        new_config.save_all(&self.config_paths)?;
+        tracing::info!("Configuration file updated successfully");
+
+        // Update the configuration
+        {
+            let mut config = self.config.write().await;
+            *config = new_config.clone();
+        }
+        *self.telegram.write().unwrap() = Arc::new(new_config.telegram_client());
+        *self.slack.write().unwrap() = new_config.slack_client().map(Arc::new);
+        *self.email.write().unwrap() = new_config.email_client().map(Arc::new);
+        *self.notifiers.write().unwrap() = Arc::new(new_config.notifier_clients());
+        // The per-host limit may have changed
+        self.host_limits.lock().unwrap().clear();
+
+        // Update service states, preserving existing data where possible
+        {
+            let mut services = self.services.write().await;
+            let now = Utc::now();
+
+            // Remove services that no longer exist or are now disabled
+            services.retain(|id, _| {
+                new_config.services.get(id)
+                    .map(|s| s.enabled)
+                    .unwrap_or(false)
+            });
+            self.incidents
+                .write()
+                .await
+                .retain(|id, _| services.contains_key(id));
+            self.latencies
+                .write()
+                .await
+                .retain(|id, _| services.contains_key(id));
+
+            // Add or update enabled services only
+            for (id, service) in new_config.services.iter().filter(|(_, s)| s.enabled) {
+                match services.get_mut(id) {
+                    // Existing services keep their counters, uptime and open incident;
+                    // only the descriptive fields follow the new configuration
+                    Some(service_state) => {
+                        service_state.name = service.name.clone();
+                        service_state.description = service.description.clone();
+                        service_state.health_weight = service.health_weight.unwrap_or(DEFAULT_HEALTH_WEIGHT);
+                        service_state.runbook_url = service.runbook_url.clone();
+                        // The task is restarted below, don't report it as stalled meanwhile
+                        service_state.last_loop_iteration = now;
+                    }
+                    None => {
+                        let mut service_state = ServiceState::new(service, now);
+                        service_state.added_at = Some(now);
+                        if let Some(store) = &self.store {
+                            match store.added_at(&[id.as_str()], now) {
+                                Ok(added) => service_state.added_at = added.get(id).copied(),
+                                Err(e) => tracing::error!("Failed to record when {} was added: {}", id, e),
+                            }
+                        }
+                        services.insert(id.clone(), service_state);
+                    }
+                }
+            }
+        }
+
+        // Start new tasks
+        self.start_monitoring_tasks().await;
+
+        tracing::info!("Configuration updated and tasks restarted");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+telegram_token: "token"
+telegram_chat_id: 1
+check_interval_success: 1000
+check_interval_fail: 500
+notify_failures: 3
+rereport: 10
+services:
+  stalled:
+    enabled: true
+    name: "Stalled"
+    description: "Loop stopped iterating"
+    check: !tcpPing
+      host: "127.0.0.1"
+      port: 1
+  alive:
+    enabled: true
+    name: "Alive"
+    description: "Loop iterating normally"
+    check: !tcpPing
+      host: "127.0.0.1"
+      port: 1
+  backup:
+    enabled: true
+    name: "Backup"
+    description: "Backup server"
+    check: !tcpPing
+      host: "127.0.0.1"
+      port: 2
+"#;
+
+    fn app_state(yaml: &str) -> AppState {
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        AppState::new(config, vec!["unused.yaml".to_string()])
+    }
+
+    #[tokio::test]
+    async fn test_run_once_checks_every_service() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = app_state(&CONFIG.replace("port: 2\n", &format!("port: {}\n", port)));
+
+        let services = state.run_once().await;
+        assert_eq!(services.len(), 3);
+        assert!(services.iter().all(|s| s.total_checks == 1));
+        let status = |name: &str| services.iter().find(|s| s.name == name).unwrap().status;
+        assert_eq!(status("Backup"), Status::Up);
+        assert_eq!(status("Alive"), Status::Down);
+        assert_eq!(status("Stalled"), Status::Down);
+    }
+
+    #[test]
+    fn test_randomized_check_order() {
+        let mut yaml = CONFIG.replace("services:\n", "randomize_check_order: true\nservices:\n");
+        for i in 0..5 {
+            yaml.push_str(&format!("  extra{}:\n    enabled: true\n    name: \"Extra {}\"\n    description: \"\"\n    check: !tcpPing\n      host: \"127.0.0.1\"\n      port: 1\n", i, i));
+        }
+        let cycles = |seed: u64| {
+            let state = app_state(&yaml).with_check_order_seed(seed);
+            let config = state.config.try_read().unwrap().clone();
+            (0..5)
+                .map(|_| state.check_order(&config).into_iter().map(|(id, _)| id.clone()).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+
+        let first = cycles(1);
+        assert_eq!(first, cycles(1));
+        assert_ne!(first, cycles(2));
+        assert!(first.iter().any(|order| *order != first[0]));
+        assert!(first.iter().all(|order| order.len() == 8));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_randomized_check_order_varies_between_cycles() {
+        // Disabled command checks fail at once, without waiting on anything
+        let mut yaml = CONFIG
+            .replace("services:\n", "randomize_check_order: true\nservices:\n")
+            .replace("notify_failures: 3", "notify_failures: 1000");
+        yaml.truncate(yaml.find("  stalled:").unwrap());
+        for i in 0..5 {
+            yaml.push_str(&format!(
+                "  s{}:\n    enabled: true\n    name: \"S{}\"\n    description: \"\"\n    check: !external\n      command: \"true\"\n",
+                i, i
+            ));
+        }
+        let state = app_state(&yaml).with_check_order_seed(7);
+        let (_, mut events) = state.subscribe_events(None);
+        state.start_monitoring_tasks().await;
+
+        let mut cycles = Vec::new();
+        for _ in 0..6 {
+            let mut cycle = Vec::new();
+            for _ in 0..5 {
+                cycle.push(events.recv().await.unwrap().service_id);
+            }
+            cycles.push(cycle);
+        }
+        state.stop_all_tasks().await;
+
+        // Every service is checked once per cycle, in a changing order
+        for cycle in &cycles {
+            let mut ids = cycle.clone();
+            ids.sort();
+            assert_eq!(ids, ["s0", "s1", "s2", "s3", "s4"]);
+        }
+        assert!(cycles[1..].iter().any(|cycle| *cycle != cycles[0]), "{:?}", cycles);
+    }
+
+    #[tokio::test]
+    async fn test_counters_survive_config_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("healthcheck.yaml");
+        std::fs::write(&path, CONFIG).unwrap();
+        let config: Config = serde_yaml::from_str(CONFIG).unwrap();
+        let state = AppState::new(config.clone(), vec![path.to_string_lossy().to_string()]);
+
+        for _ in 0..3 {
+            state.set_state("backup".to_string(), State::Success).await;
+        }
+        state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        let before = state.services.read().await.clone();
+
+        // Reload leaving backup and alive unchanged, disabling stalled
+        let mut reloaded = config.clone();
+        reloaded.services.get_mut("stalled").unwrap().enabled = false;
+        state.update_config(reloaded).await.unwrap();
+
+        {
+            // Monitoring tasks haven't run yet on the single-threaded test runtime
+            let services = state.services.read().await;
+            for id in ["backup", "alive"] {
+                let (old, new) = (&before[id], &services[id]);
+                assert_eq!(new.uptime_start, old.uptime_start, "{}", id);
+                assert_eq!(new.total_checks, old.total_checks, "{}", id);
+                assert_eq!(new.successful_checks, old.successful_checks, "{}", id);
+                assert_eq!(new.failed_checks, old.failed_checks, "{}", id);
+                assert_eq!(new.consecutive_failures, old.consecutive_failures, "{}", id);
+                assert_eq!(new.down_since, old.down_since, "{}", id);
+                assert_eq!(new.monitored_since, old.monitored_since, "{}", id);
+            }
+            assert_eq!(services["backup"].total_checks, 3);
+            assert_eq!(services["alive"].consecutive_failures, 2);
+            assert!(!services.contains_key("stalled"));
+        }
+        state.stop_all_tasks().await;
+
+        // The open incident is kept as well
+        assert_eq!(state.incidents.read().await["alive"].len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_notification_change_keeps_check_tasks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("healthcheck.yaml");
+        std::fs::write(&path, CONFIG).unwrap();
+        let config: Config = serde_yaml::from_str(CONFIG).unwrap();
+        let state = AppState::new(config.clone(), vec![path.to_string_lossy().to_string()]);
+        state.start_monitoring_tasks().await;
+        let task_ids = |handles: &HashMap<String, tokio::task::JoinHandle<()>>| {
+            handles.iter().map(|(id, handle)| (id.clone(), handle.id())).collect::<HashMap<_, _>>()
+        };
+        let before = task_ids(&*state.task_handles.read().await);
+
+        // New token and Bot API server only rebuild the notifier
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let mut notifications = config.clone();
+        notifications.telegram_token = "rotated".to_string();
+        notifications.telegram_api_url = Some(telegram.url());
+        notifications.notify_on_startup = Some(true);
+        state.update_config(notifications.clone()).await.unwrap();
+        assert_eq!(task_ids(&*state.task_handles.read().await), before);
+        assert_eq!(state.get_config().await.telegram_token, "rotated");
+        state.notify_startup().await;
+        assert_eq!(telegram.messages().len(), 1);
+
+        // Any other change restarts the tasks
+        let mut intervals = notifications;
+        intervals.check_interval_success = 2000;
+        state.update_config(intervals).await.unwrap();
+        let after = task_ids(&*state.task_handles.read().await);
+        assert!(before.iter().all(|(id, task)| after[id] != *task));
+        state.stop_all_tasks().await;
+    }
+
+    #[test]
+    fn test_intervals_clamped_to_floor() {
+        let yaml = CONFIG.replace("name: \"Backup\"", "name: \"Backup\"\n    check_interval_fail: 100");
+        let mut config: Config = serde_yaml::from_str(&yaml).unwrap();
+        let backup = config.services["backup"].clone();
+        let failure = State::failure(FailureCategory::Connection, "Connection refused");
+        assert_eq!(backup.interval(&failure, &config), 100);
+        assert_eq!(backup.interval(&State::Success, &config), 1000);
+
+        // Only intervals below the floor are raised
+        config.min_check_interval_ms = Some(600);
+        assert_eq!(backup.interval(&failure, &config), 600);
+        assert_eq!(backup.interval(&State::Success, &config), 1000);
+    }
+
+    #[test]
+    fn test_duplicate_checks_are_reported() {
+        // Stalled and alive both ping 127.0.0.1:1
+        let config: Config = serde_yaml::from_str(CONFIG).unwrap();
+        assert_eq!(
+            config.warnings(),
+            vec!["Services 'alive', 'stalled' have identical checks, possibly a copy-paste mistake"]
+        );
+
+        // Any differing option makes the checks distinct
+        let config: Config = serde_yaml::from_str(&CONFIG.replacen("port: 1\n", "port: 3\n", 1)).unwrap();
+        assert!(config.warnings().is_empty());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_services_inherit_templates() {
+        let config = Config::parse(
+            r#"
+telegram_token: "token"
+telegram_chat_id: 1
+check_interval_success: 60000
+check_interval_fail: 10000
+notify_failures: 3
+rereport: 10
+templates:
+  api:
+    enabled: true
+    description: "Public API"
+    check_interval_success: 30000
+    check: !http
+      url: "https://example.com/health"
+      expected_status: 200
+      expected_content_type: "application/json"
+services:
+  orders:
+    template: api
+    name: "Orders"
+    check: !http
+      url: "https://orders.example.com/health"
+  billing:
+    template: api
+    name: "Billing"
+    check_interval_success: 5000
+    check: !http
+      url: "https://billing.example.com/health"
+      expected_status: 204
+"#,
+        )
+        .unwrap();
+
+        let orders = &config.services["orders"];
+        assert!(orders.enabled);
+        assert_eq!(orders.description, "Public API");
+        assert_eq!(orders.check_interval_success, Some(30000));
+        let CheckType::Http(http) = &orders.check else { panic!("expected an HTTP check") };
+        assert_eq!(http.url, "https://orders.example.com/health");
+        assert_eq!(http.expected_status, Some(200));
+        assert_eq!(http.expected_content_type.as_deref(), Some("application/json"));
+
+        // Service fields override the template
+        let billing = &config.services["billing"];
+        assert_eq!(billing.check_interval_success, Some(5000));
+        let CheckType::Http(http) = &billing.check else { panic!("expected an HTTP check") };
+        assert_eq!(http.expected_status, Some(204));
+        assert_eq!(http.expected_content_type.as_deref(), Some("application/json"));
+
+        let error = Config::parse(&CONFIG.replace("name: \"Alive\"", "name: \"Alive\"\n    template: missing"))
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Service 'alive' references unknown template 'missing'");
+    }
+
+    #[test]
+    fn test_environment_overrides_config_values() {
+        let config: Config = serde_yaml::from_str(CONFIG).unwrap();
+        let vars = [
+            ("HEALTHCHECK__WEB_PORT", "9000"),
+            ("HEALTHCHECK__TELEGRAM_TOKEN", "123456"),
+            ("HEALTHCHECK__SERVICES__BACKUP__ENABLED", "false"),
+            ("HEALTHCHECK__SERVICES__BACKUP__CHECK__PORT", "2222"),
+            ("HEALTHCHECK_CONFIG", "ignored.yaml"),
+            ("PATH", "/usr/bin"),
+        ];
+        let config = config
+            .with_overrides(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())))
+            .unwrap();
+
+        assert_eq!(config.web_port, Some(9000));
+        // A number is taken as a string where the setting is one
+        assert_eq!(config.telegram_token, "123456");
+        let backup = &config.services["backup"];
+        assert!(!backup.enabled);
+        let CheckType::TcpPing(ping) = &backup.check else { panic!("expected a TCP ping check") };
+        assert_eq!(ping.port, 2222);
+        // Other values are untouched
+        assert!(config.services["alive"].enabled);
+        assert_eq!(config.rereport, 10);
+
+        let error = serde_yaml::from_str::<Config>(CONFIG)
+            .unwrap()
+            .with_overrides([("HEALTHCHECK__WEB_PORT".to_string(), "not-a-port".to_string())])
+            .unwrap_err();
+        assert!(error.to_string().starts_with("Invalid configuration after environment overrides"));
+    }
+
+    #[tokio::test]
+    async fn test_environment_overrides_kept_out_of_saved_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("healthcheck.yaml");
+        std::fs::write(&path, CONFIG).unwrap();
+        let vars = [
+            ("HEALTHCHECK__TELEGRAM_TOKEN", "from-env"),
+            ("HEALTHCHECK__METRICS_PREFIX", "staging"),
+            ("HEALTHCHECK__SERVICES__BACKUP__CHECK__PORT", "2222"),
+        ];
+        let config = serde_yaml::from_str::<Config>(CONFIG)
+            .unwrap()
+            .with_overrides(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())))
+            .unwrap();
+
+        let file = config.without_overrides().unwrap();
+        assert_eq!(file.telegram_token, "token");
+        assert_eq!(file.metrics_prefix, None);
+        let CheckType::TcpPing(ping) = &file.services["backup"].check else { panic!("expected a TCP ping check") };
+        assert_eq!(ping.port, 2);
+
+        // Saving an edited configuration keeps the overrides running but out of the file
+        let state = AppState::new(config, vec![path.to_string_lossy().to_string()]);
+        let mut edited = state.get_config().await.without_overrides().unwrap();
+        edited.rereport = 20;
+        state.update_config(edited).await.unwrap();
+        state.stop_all_tasks().await;
+
+        let running = state.get_config().await;
+        assert_eq!(running.rereport, 20);
+        assert_eq!(running.telegram_token, "from-env");
+        assert_eq!(running.metrics_prefix.as_deref(), Some("staging"));
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("rereport: 20"));
+        assert!(saved.contains("telegram_token: token"));
+        assert!(!saved.contains("staging"));
+        assert!(!saved.contains("2222"));
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_flags_stalled_task() {
+        let state = app_state(CONFIG);
+        assert!(state.stalled_services().await.is_empty());
+
+        state.record_loop_iteration("alive").await;
+        state.record_loop_iteration("backup").await;
+        state
+            .services
+            .write()
+            .await
+            .get_mut("stalled")
+            .unwrap()
+            .last_loop_iteration = Utc::now() - chrono::Duration::seconds(10);
+
+        let stalled = state.stalled_services().await;
+        assert_eq!(stalled.len(), 1);
+        assert_eq!(stalled[0].id, "stalled");
+        assert_eq!(stalled[0].name, "Stalled");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_panicked_task_is_respawned() {
+        let runs = std::sync::atomic::AtomicU32::new(0);
+
+        supervise("panicky", || {
+            let run = runs.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async move {
+                if run < 2 {
+                    panic!("check exploded");
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(runs.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_result_webhook_fires_on_every_check() {
+        let webhook = crate::test_util::MockWebhook::start().await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Alive connects to the listener, the other services to a closed port
+        let yaml = format!("{}result_webhook_url: \"{}\"\n", CONFIG, webhook.url());
+        let mut config: Config = serde_yaml::from_str(&yaml).unwrap();
+        if let CheckType::TcpPing(tcp) = &mut config.services.get_mut("alive").unwrap().check {
+            tcp.port = port;
+        }
+        let state = AppState::new(config, vec!["unused.yaml".to_string()]);
+
+        state.start_monitoring_tasks().await;
+        let mut bodies = Vec::new();
+        for _ in 0..200 {
+            bodies = webhook.bodies();
+            if bodies.len() >= 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        state.stop_all_tasks().await;
+
+        let result = |service: &str| bodies.iter().find(|b| b["service"] == service).unwrap().clone();
+        assert_eq!(result("Alive")["state"], "Success");
+        assert!(result("Backup")["state"]["Failure"].is_string());
+        assert!(result("Alive")["latency_ms"].is_u64());
+        assert!(result("Alive")["timestamp"].is_string());
+    }
+
+    // Fails the first `failures` lookups, then resolves to localhost unless the host starts with "missing"
+    struct FlakyResolver {
+        failures: usize,
+        lookups: std::sync::atomic::AtomicUsize,
+    }
+
+    impl Resolver for FlakyResolver {
+        fn resolve<'a>(
+            &'a self,
+            host: &'a str,
+            port: u16,
+        ) -> futures::future::BoxFuture<'a, std::io::Result<Vec<std::net::SocketAddr>>> {
+            Box::pin(async move {
+                if self.lookups.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < self.failures {
+                    return Err(std::io::Error::other("Temporary failure in name resolution"));
+                }
+                if host.starts_with("missing") {
+                    return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no such host"));
+                }
+                Ok(vec![std::net::SocketAddr::from(([127, 0, 0, 1], port))])
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dns_failure_retried_once() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let tcp: ServiceTcpPing = serde_yaml::from_str(&format!("host: db.internal\nport: {}", port)).unwrap();
+        let check = |failures: usize| {
+            let resolver = Arc::new(FlakyResolver { failures, lookups: Default::default() });
+            let ctx = CheckContext { resolver: resolver.clone(), ..Default::default() };
+            let tcp = tcp.clone();
+            async move { (tcp.check(&ctx).await, resolver.lookups.load(std::sync::atomic::Ordering::SeqCst)) }
+        };
+
+        // A single failed lookup doesn't fail the check
+        assert_eq!(check(1).await, (State::Success, 2));
+        // Only one extra attempt is made
+        assert_eq!(
+            check(2).await,
+            (State::failure(FailureCategory::Connection, "Connection failed: Temporary failure in name resolution"), 2)
+        );
+
+        // Names that don't exist aren't retried
+        let lookups = |host: &'static str| async move {
+            let resolver = Arc::new(FlakyResolver { failures: 0, lookups: Default::default() });
+            let ctx = CheckContext { resolver: resolver.clone(), ..Default::default() };
+            let tcp = ServiceTcpPing { host: host.to_string(), ..tcp.clone() };
+            tcp.check(&ctx).await;
+            resolver.lookups.load(std::sync::atomic::Ordering::SeqCst)
+        };
+        assert_eq!(lookups("missing.internal").await, 1);
+        let error = SystemResolver.resolve("healthcheck.invalid", 80).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::NotFound, "{}", error);
+
+        // HTTP checks resolve the same way and tell lookup failures from connection failures
+        let server = crate::test_util::serve(axum::Router::new()).await;
+        let url = server.replace("127.0.0.1", "api.internal");
+        let http: ServiceHttp = serde_yaml::from_str(&format!("url: {}\nexpected_status: 404", url)).unwrap();
+        let resolver = Arc::new(FlakyResolver { failures: 1, lookups: Default::default() });
+        let ctx = CheckContext { resolver: resolver.clone(), ..Default::default() };
+        assert_eq!(http.check(&ctx).await, State::Success);
+        assert_eq!(resolver.lookups.load(std::sync::atomic::Ordering::SeqCst), 2);
+        let client = ConnectionOptions::default().http_client(&CheckContext::default()).unwrap();
+        let error = client.get("http://healthcheck.invalid/").send().await.unwrap_err();
+        assert!(is_dns_error(&error), "{:?}", error);
+        let error = client.get("http://127.0.0.1:1/").send().await.unwrap_err();
+        assert!(!is_dns_error(&error), "{:?}", error);
+    }
+
+    struct SlowResolver;
+
+    impl Resolver for SlowResolver {
+        fn resolve<'a>(
+            &'a self,
+            _host: &'a str,
+            _port: u16,
+        ) -> futures::future::BoxFuture<'a, std::io::Result<Vec<std::net::SocketAddr>>> {
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_secs(3600)).await;
+                Ok(vec![])
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_check_timeout_bounds_dns_resolution() {
+        let service: Service = serde_yaml::from_str(
+            r#"
+enabled: true
+name: "Slow DNS"
+description: "Resolver never answers"
+check_timeout_ms: 200
+check: !tcpPing
+  host: "slow.example"
+  port: 80
+  timeout_ms: 60000
+"#,
+        )
+        .unwrap();
+        let ctx = CheckContext {
+            resolver: Arc::new(SlowResolver),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            service.check(&ctx).await,
+            State::failure(FailureCategory::Timeout, "Check timed out after 200ms")
+        );
+    }
+
+    const EXTRA_SERVICES: &str = r#"
+services:
+  extra:
+    enabled: false
+    name: "Extra"
+    description: "Defined in a second file"
+    check: !tcpPing
+      host: "127.0.0.1"
+      port: 3
+"#;
+
+    #[test]
+    fn test_load_all_merges_services() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.yaml");
+        let extra = dir.path().join("extra.yaml");
+        std::fs::write(&main, CONFIG).unwrap();
+        std::fs::write(&extra, EXTRA_SERVICES).unwrap();
+
+        let config = Config::load_all(&[&main, &extra]).unwrap();
+        let mut ids: Vec<&String> = config.services.keys().collect();
+        ids.sort();
+        assert_eq!(ids, ["alive", "backup", "extra", "stalled"]);
+
+        // Saving keeps each service in the file it came from
+        config.save_all(&[&main, &extra]).unwrap();
+        assert!(!std::fs::read_to_string(&main).unwrap().contains("extra:"));
+        assert!(std::fs::read_to_string(&extra).unwrap().contains("extra:"));
+        assert_eq!(Config::load_all(&[&main, &extra]).unwrap().services.len(), 4);
+
+        std::fs::write(&extra, EXTRA_SERVICES.replace("extra:", "alive:")).unwrap();
+        let error = Config::load_all(&[&main, &extra]).unwrap_err();
+        assert!(error.to_string().contains("Duplicate service id 'alive'"));
+    }
+
+    #[tokio::test]
+    async fn test_stale_result_reported_as_unknown() {
+        let state = app_state(CONFIG);
+        state.set_state("alive".to_string(), State::Success).await;
+        state.set_state("stalled".to_string(), State::Success).await;
+        state.services.write().await.get_mut("stalled").unwrap().last_check =
+            Utc::now() - chrono::Duration::seconds(10);
+
+        let services = state.get_all_services().await;
+        let by_name = |name: &str| services.iter().find(|s| s.name == name).unwrap().state.clone();
+        assert_eq!(by_name("Alive"), State::Success);
+        assert_eq!(by_name("Stalled"), State::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_status_matches_state() {
+        let state = app_state(CONFIG);
+        state.set_state("alive".to_string(), State::Success).await;
+        state.set_state("backup".to_string(), State::Warning("Degraded".to_string())).await;
+        state.set_state("stalled".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+
+        let services = serde_json::to_value(state.get_all_services().await).unwrap();
+        let statuses: Vec<(&str, &str)> = services
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| (s["name"].as_str().unwrap(), s["status"].as_str().unwrap()))
+            .collect();
+        assert_eq!(statuses, [("Alive", "up"), ("Backup", "warning"), ("Stalled", "down")]);
+        assert_eq!(services[2]["state"]["Failure"], "Connection refused");
+        assert_eq!(Status::from(&State::Unknown), Status::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_certificate_handshake_timeout() {
+        // Accept TCP connections but never answer the TLS handshake
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let cert = ServiceCertificate {
+            host: "127.0.0.1".to_string(),
+            port,
+            days_before_expiry: None,
+            ca_cert: None,
+            accept_invalid_certs: None,
+            require_sct: None,
+            caa_issuer: None,
+            notify_fingerprint_change: None,
+            connection: ConnectionOptions {
+                timeout_ms: Some(200),
+                ..ConnectionOptions::default()
+            },
+        };
+
+        assert_eq!(
+            cert.check(&CheckContext::default()).await,
+            State::failure(FailureCategory::Timeout, "TLS handshake timed out after 200ms")
+        );
+    }
+
+    // Serve a certificate for "localhost" signed by a freshly generated CA, returning the CA PEM
+    async fn spawn_tls_server_with_custom_ca(not_after: Option<DateTime<Utc>>) -> (u16, String) {
+        use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.distinguished_name.push(DnType::CommonName, "Test Internal CA");
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let leaf_key = KeyPair::generate().unwrap();
+        let mut leaf_params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        leaf_params.distinguished_name.push(DnType::CommonName, "localhost");
+        if let Some(not_after) = not_after {
+            use chrono::Datelike;
+            leaf_params.not_after =
+                rcgen::date_time_ymd(not_after.year(), not_after.month() as u8, not_after.day() as u8);
+        }
+        let leaf = leaf_params.signed_by(&leaf_key, &ca, &ca_key).unwrap();
+
+        let identity = native_tls::Identity::from_pkcs8(
+            leaf.pem().as_bytes(),
+            leaf_key.serialize_pem().as_bytes(),
+        )
+        .unwrap();
+        let acceptor = tokio_native_tls::TlsAcceptor::from(native_tls::TlsAcceptor::new(identity).unwrap());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let _ = acceptor.accept(socket).await;
+                });
+            }
+        });
+
+        (port, ca.pem())
+    }
+
+    // Self-signed certificate, optionally carrying an embedded SCT list
+    fn certificate_der(with_sct: bool) -> Vec<u8> {
+        use rcgen::{CertificateParams, CustomExtension, KeyPair};
+
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+        if with_sct {
+            // One SCT: v1, log id, timestamp, no extensions, SHA-256/ECDSA signature
+            let mut sct = vec![0u8];
+            sct.extend([0x11; 32]);
+            sct.extend(1_700_000_000_000u64.to_be_bytes());
+            sct.extend([0, 0, 4, 3, 0, 4, 0xde, 0xad, 0xbe, 0xef]);
+            let mut list = (sct.len() as u16).to_be_bytes().to_vec();
+            list.extend(sct);
+            let mut tls = (list.len() as u16).to_be_bytes().to_vec();
+            tls.extend(list);
+            let mut content = vec![0x04, tls.len() as u8];
+            content.extend(tls);
+            params
+                .custom_extensions
+                .push(CustomExtension::from_oid_content(&[1, 3, 6, 1, 4, 1, 11129, 2, 4, 2], content));
+        }
+        params.self_signed(&KeyPair::generate().unwrap()).unwrap().der().to_vec()
+    }
+
+    #[test]
+    fn test_certificate_requires_sct() {
+        let check: ServiceCertificate =
+            serde_yaml::from_str("host: localhost\nport: 443\nrequire_sct: true\ndays_before_expiry: 0").unwrap();
+
+        let with_sct = certificate_der(true);
+        let (_, parsed) = x509_parser::parse_x509_certificate(&with_sct).unwrap();
+        assert_eq!(check.check_parsed(&parsed), State::Success);
+
+        let without_sct = certificate_der(false);
+        let (_, parsed) = x509_parser::parse_x509_certificate(&without_sct).unwrap();
+        assert_eq!(
+            check.check_parsed(&parsed),
+            State::failure(FailureCategory::Certificate, "Certificate has no embedded SCTs (certificate transparency)")
+        );
+
+        // Without the option the SCTs aren't looked at
+        let check = ServiceCertificate { require_sct: None, ..check };
+        assert_eq!(check.check_parsed(&parsed), State::Success);
+    }
+
+    #[tokio::test]
+    async fn test_certificate_fingerprint_change_notified() {
+        use rcgen::{CertificateParams, KeyPair};
+
+        // Serves two self-signed certificates in turn, like a renewal between checks
+        let mut acceptors = Vec::new();
+        for _ in 0..2 {
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec!["localhost".to_string()]).unwrap().self_signed(&key).unwrap();
+            let identity =
+                native_tls::Identity::from_pkcs8(cert.pem().as_bytes(), key.serialize_pem().as_bytes()).unwrap();
+            acceptors.push(tokio_native_tls::TlsAcceptor::from(native_tls::TlsAcceptor::new(identity).unwrap()));
+        }
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            for turn in 0.. {
+                let Ok((socket, _)) = listener.accept().await else { break };
+                let acceptor = acceptors[turn % 2].clone();
+                tokio::spawn(async move {
+                    let _ = acceptor.accept(socket).await;
+                });
+            }
+        });
+
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let slack = crate::test_util::MockWebhook::start().await;
+        let config = CONFIG.replace(
+            "normally\"\n    check: !tcpPing\n      host: \"127.0.0.1\"\n      port: 1",
+            &format!(
+                "normally\"\n    check: !certificate\n      host: localhost\n      port: {}\n      accept_invalid_certs: true\n      notify_fingerprint_change: true",
+                port
+            ),
+        );
+        let state = app_state(&format!(
+            "{}telegram_api_url: \"{}\"\nslack_webhook_url: \"{}\"\n",
+            config,
+            telegram.url(),
+            slack.url()
+        ));
+        let service = state.get_config().await.services["alive"].clone();
+        assert!(matches!(service.check, CheckType::Certificate(_)));
+
+        // The first fingerprint is only recorded
+        assert_eq!(service.run_once("alive", &state).await, State::Success);
+        let first = state.services.read().await["alive"].certificate_fingerprint.clone().unwrap();
+        assert_eq!(first.len(), 32 * 3 - 1);
+        assert!(telegram.messages().is_empty());
+
+        assert_eq!(service.run_once("alive", &state).await, State::Success);
+        state.flush_notifications().await;
+        let second = state.services.read().await["alive"].certificate_fingerprint.clone().unwrap();
+        assert_ne!(first, second);
+        // Sent to every channel, as state changes are
+        assert_eq!(slack.bodies().len(), 1);
+        assert_eq!(slack.bodies()[0]["text"], "Warning: Alive");
+        assert_eq!(
+            telegram.messages(),
+            vec![format!(
+                "⚠️ <b>Warning: Alive</b>\n\nCertificate changed\nPrevious SHA-256: {}\nCurrent SHA-256: {}",
+                first, second
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_certificate_trusts_custom_ca() {
+        let (port, ca_pem) = spawn_tls_server_with_custom_ca(None).await;
+        let dir = tempfile::tempdir().unwrap();
+        let ca_path = dir.path().join("ca.pem");
+        std::fs::write(&ca_path, ca_pem).unwrap();
+
+        let mut cert = ServiceCertificate {
+            host: "localhost".to_string(),
+            port,
+            days_before_expiry: None,
+            ca_cert: None,
+            accept_invalid_certs: None,
+            require_sct: None,
+            caa_issuer: None,
+            notify_fingerprint_change: None,
+            connection: ConnectionOptions {
+                timeout_ms: Some(5000),
+                ..ConnectionOptions::default()
+            },
+        };
+        let ctx = CheckContext::default();
+
+        // The system trust store doesn't know the CA
+        assert!(matches!(cert.check(&ctx).await, State::Failure(reason)
+            if reason.category == FailureCategory::Tls && reason.detail.starts_with("TLS handshake failed")));
+
+        cert.ca_cert = Some(ca_path.to_string_lossy().to_string());
+        assert_eq!(cert.check(&ctx).await, State::Success);
+
+        cert.ca_cert = None;
+        cert.accept_invalid_certs = Some(true);
+        assert_eq!(cert.check(&ctx).await, State::Success);
+    }
+
+    #[tokio::test]
+    async fn test_external_check_reports_reason() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("queue.sh");
+        std::fs::write(&script, "if [ \"$1\" = full ]; then echo 'Queue depth 512 over 100'; exit 2; fi\n").unwrap();
+        let external = |arg: &str| ServiceExternalCheck {
+            command: "sh".to_string(),
+            args: vec![script.to_string_lossy().into_owned(), arg.to_string()],
+            timeout_ms: None,
+        };
+        let ctx = CheckContext { allow_command_checks: true, ..CheckContext::default() };
+
+        assert_eq!(external("empty").check(&ctx).await, State::Success);
+        assert_eq!(
+            external("full").check(&ctx).await,
+            State::Failure(FailureReason::new(FailureCategory::Command, "Queue depth 512 over 100").with_code(2))
+        );
+        let slow = ServiceExternalCheck { command: "sleep".to_string(), args: vec!["5".to_string()], timeout_ms: Some(200) };
+        assert_eq!(slow.check(&ctx).await, State::failure(FailureCategory::Timeout, "Command timed out after 200ms"));
+
+        // Commands only run when the configuration allows them
+        assert_eq!(
+            external("empty").check(&CheckContext::default()).await,
+            State::failure(FailureCategory::Configuration, "Command checks are disabled, see allow_command_checks")
+        );
+        let yaml = CONFIG.replace("check: !tcpPing\n      host: \"127.0.0.1\"\n      port: 2", "check: !external\n      command: \"true\"");
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("requires allow_command_checks"));
+        let config: Config = serde_yaml::from_str(&format!("{}allow_command_checks: true\n", yaml)).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.services["backup"].check.check(&config.check_context()).await, State::Success);
+    }
+
+    #[tokio::test]
+    async fn test_icmp_ping() {
+        let ping = ServiceIcmpPing { host: "127.0.0.1".to_string(), count: Some(2), timeout_ms: Some(200) };
+
+        let state = ping.check(&CheckContext::default()).await;
+        // Without ping sockets or CAP_NET_RAW the check reports why instead of failing obscurely
+        if matches!(&state, State::Failure(reason) if reason.detail.starts_with("ICMP sockets are not permitted")) {
+            return;
+        }
+        assert_eq!(state, State::Success);
+    }
+
+    #[tokio::test]
+    async fn test_dns_check_compares_records() {
+        use hickory_resolver::proto::rr::rdata::{A, TXT};
+        use hickory_resolver::proto::rr::RData;
+
+        let server = crate::test_util::MockDns::start(vec![
+            ("app.test.", RData::A(A::new(192, 0, 2, 1))),
+            ("app.test.", RData::A(A::new(192, 0, 2, 2))),
+            // A long TXT record split into two character-strings
+            ("app.test.", RData::TXT(TXT::new(vec!["v=spf1 include:a.test ".to_string(), "-all".to_string()]))),
+        ])
+        .await;
+        let dns = |record_type: &str, expected: Option<&[&str]>| ServiceDns {
+            hostname: "app.test".to_string(),
+            record_type: record_type.to_string(),
+            expected_values: expected.map(|values| values.iter().map(|v| v.to_string()).collect()),
+            nameserver: Some(server.address()),
+            expect_absent: None,
+        };
+
+        assert_eq!(dns("A", None).check().await, State::Success);
+        assert_eq!(dns("A", Some(&["192.0.2.2", "192.0.2.1"])).check().await, State::Success);
+        assert_eq!(
+            dns("A", Some(&["192.0.2.1"])).check().await,
+            State::failure(FailureCategory::Dns, "Unexpected A records for app.test: got 192.0.2.1, 192.0.2.2, expected 192.0.2.1")
+        );
+        assert_eq!(dns("TXT", Some(&["v=spf1 include:a.test -all"])).check().await, State::Success);
+        assert_eq!(
+            dns("AAAA", None).check().await,
+            State::failure(FailureCategory::Dns, "No AAAA records found for app.test")
+        );
+
+        let absent = |record_type: &str| ServiceDns { expect_absent: Some(true), ..dns(record_type, None) };
+        assert_eq!(absent("AAAA").check().await, State::Success);
+        assert_eq!(
+            absent("A").check().await,
+            State::failure(FailureCategory::Dns, "A records for app.test still resolve: 192.0.2.1, 192.0.2.2")
+        );
+
+        let yaml = CONFIG.replace(
+            "check: !tcpPing\n      host: \"127.0.0.1\"\n      port: 2",
+            "check: !dns\n      hostname: \"app.test\"\n      record_type: \"SRV\"",
+        );
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("unsupported record type 'SRV'"));
+    }
+
+    #[tokio::test]
+    async fn test_certificate_file_expiry() {
+        use chrono::Datelike;
+
+        let dir = tempfile::tempdir().unwrap();
+        let write_cert = |name: &str, days: i64| {
+            let not_after = Utc::now() + chrono::Duration::days(days);
+            let mut params = rcgen::CertificateParams::new(vec!["client".to_string()]).unwrap();
+            params.not_after = rcgen::date_time_ymd(not_after.year(), not_after.month() as u8, not_after.day() as u8);
+            let cert = params.self_signed(&rcgen::KeyPair::generate().unwrap()).unwrap();
+            let path = dir.path().join(name);
+            std::fs::write(&path, cert.pem()).unwrap();
+            ServiceCertificateFile { path: path.to_string_lossy().into_owned(), days_before_expiry: Some(30) }
+        };
+
+        assert_eq!(write_cert("far.pem", 365).check().await, State::Success);
+        let near = write_cert("near.pem", 10).check().await;
+        assert!(matches!(&near, State::Warning(reason) if reason.starts_with("Certificate expires in")));
+        let expired = write_cert("expired.pem", -10).check().await;
+        assert!(matches!(&expired, State::Failure(reason) if reason.detail.starts_with("Certificate expired")));
+
+        let missing = ServiceCertificateFile { path: "/nonexistent.pem".to_string(), days_before_expiry: None };
+        assert!(matches!(missing.check().await, State::Failure(reason) if reason.detail.starts_with("Failed to read certificate")));
+    }
+
+    #[tokio::test]
+    async fn test_certificate_near_expiry_is_a_warning() {
+        let (port, _) = spawn_tls_server_with_custom_ca(Some(Utc::now() + chrono::Duration::days(10))).await;
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let state = app_state(&format!(
+            "{}telegram_api_url: \"{}\"\n",
+            CONFIG.replace("notify_failures: 3", "notify_failures: 1"),
+            telegram.url()
+        ));
+
+        let cert = ServiceCertificate {
+            host: "localhost".to_string(),
+            port,
+            days_before_expiry: Some(30),
+            ca_cert: None,
+            accept_invalid_certs: Some(true),
+            require_sct: None,
+            caa_issuer: None,
+            notify_fingerprint_change: None,
+            connection: ConnectionOptions {
+                timeout_ms: Some(5000),
+                ..ConnectionOptions::default()
+            },
+        };
+        let result = cert.check(&CheckContext::default()).await;
+        assert!(matches!(&result, State::Warning(reason) if reason.starts_with("Certificate expires in")));
+
+        // Repeated warnings notify once and never count as failures
+        state.set_state("alive".to_string(), result.clone()).await;
+        state.set_state("alive".to_string(), result.clone()).await;
+        state.flush_notifications().await;
+
+        let services = state.services.read().await;
+        assert_eq!(services["alive"].consecutive_failures, 0);
+        assert_eq!(services["alive"].failed_checks, 0);
+        let messages = telegram.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].starts_with("⚠️ <b>Warning: Alive</b>"));
+    }
+
+    #[tokio::test]
+    async fn test_notifications_posted_to_slack() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let slack = crate::test_util::MockWebhook::start().await;
+        let state = app_state(&format!(
+            "{}telegram_api_url: \"{}\"\nslack_webhook_url: \"{}\"\n",
+            CONFIG,
+            telegram.url(),
+            slack.url()
+        ));
+
+        for _ in 0..3 {
+            state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        }
+        state.set_state("alive".to_string(), State::Success).await;
+        state.flush_notifications().await;
+
+        assert_eq!(telegram.messages().len(), 2);
+        let bodies = slack.bodies();
+        let titles: Vec<&str> = bodies.iter().map(|body| body["text"].as_str().unwrap()).collect();
+        assert_eq!(titles, vec!["Alert: Alive", "Recovery: Alive"]);
+        assert_eq!(bodies[0]["attachments"][0]["blocks"][0]["text"]["text"], "*Alert: Alive*\n\nConnection refused");
+        assert_ne!(bodies[0]["attachments"][0]["color"], bodies[1]["attachments"][0]["color"]);
+    }
+
+    #[tokio::test]
+    async fn test_notifications_emailed() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let smtp = crate::test_util::MockSmtp::start().await;
+        let yaml = format!(
+            "{}telegram_api_url: \"{}\"\nsmtp_host: \"127.0.0.1\"\nsmtp_port: {}\nemail_to: [\"ops@example.com\"]\nemail_from: \"monitor@example.com\"\n",
+            CONFIG,
+            telegram.url(),
+            smtp.port()
+        );
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        config.validate().unwrap();
+        let state = app_state(&yaml);
+
+        for _ in 0..3 {
+            state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        }
+        state.set_state("alive".to_string(), State::Success).await;
+        state.flush_notifications().await;
+
+        assert_eq!(telegram.messages().len(), 2);
+        let messages = smtp.messages();
+        assert_eq!(messages.len(), 2);
+        assert!(messages.iter().all(|(from, to, _)| from == "monitor@example.com" && to == &["ops@example.com"]));
+        assert!(messages[0].2.contains("Subject: Alert: Alive"));
+        assert!(messages[1].2.contains("Subject: Recovery: Alive"));
+
+        let config: Config = serde_yaml::from_str(&format!("{}email_to: [\"ops@example.com\"]\n", CONFIG)).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("email_to requires smtp_host"));
+    }
+
+    #[tokio::test]
+    async fn test_notification_failover_uses_secondary_on_error() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let slack = crate::test_util::MockWebhook::start().await;
+        let notify = |slack_url: String| {
+            let state = app_state(&format!(
+                "{}telegram_api_url: \"{}\"\nslack_webhook_url: \"{}\"\nnotification_retries: 0\nnotification_failover: [slack, telegram]\n",
+                CONFIG,
+                telegram.url(),
+                slack_url
+            ));
+            async move {
+                for _ in 0..3 {
+                    state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+                }
+                state.set_state("alive".to_string(), State::Success).await;
+                state.flush_notifications().await;
+            }
+        };
+
+        // The primary works, the secondary stays unused
+        notify(slack.url()).await;
+        assert_eq!(slack.bodies().len(), 2);
+        assert!(telegram.messages().is_empty());
+
+        // Nothing listens on port 1, so the primary errors
+        notify("http://127.0.0.1:1/".to_string()).await;
+        assert_eq!(slack.bodies().len(), 2);
+        let messages = telegram.messages();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].contains("Alert: Alive"));
+        assert!(messages[1].contains("Recovery: Alive"));
+    }
+
+    #[tokio::test]
+    async fn test_notify_targets_route_to_named_notifiers() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let slack = crate::test_util::MockWebhook::start().await;
+        let yaml = format!(
+            "{}telegram_api_url: \"{}\"\nnotifiers:\n  team-a:\n    type: telegram\n    chat_id: 2\n  team-b:\n    type: slack\n    webhook_url: \"{}\"\n",
+            CONFIG
+                .replace("notify_failures: 3", "notify_failures: 1")
+                .replace("name: \"Alive\"\n", "name: \"Alive\"\n    notify_targets: [team-a, team-b]\n"),
+            telegram.url(),
+            slack.url()
+        );
+        let state = app_state(&yaml);
+
+        state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        state.set_state("backup".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        state.flush_notifications().await;
+
+        // The routed service skips the default chat, the other one keeps using it
+        let chats: Vec<(i64, String)> = telegram
+            .requests()
+            .iter()
+            .map(|(_, body)| (body["chat_id"].as_i64().unwrap(), body["text"].as_str().unwrap().to_string()))
+            .collect();
+        assert_eq!(chats.len(), 2);
+        assert_eq!(chats[0].0, 2);
+        assert!(chats[0].1.contains("Alert: Alive"));
+        assert_eq!(chats[1].0, 1);
+        assert!(chats[1].1.contains("Alert: Backup"));
+        assert_eq!(slack.bodies().len(), 1);
+        assert_eq!(slack.bodies()[0]["text"], "Alert: Alive");
+
+        let mut config: Config = serde_yaml::from_str(&yaml).unwrap();
+        config.services.get_mut("alive").unwrap().notify_targets = Some(vec!["team-c".to_string()]);
+        let err = config.validate().unwrap_err().to_string();
+        assert_eq!(err, "Service 'alive' notifies 'team-c', which is not in notifiers");
+    }
+
+    #[tokio::test]
+    async fn test_on_failure_command_runs_once_per_incident() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("restarts.log");
+        let yaml = CONFIG.replace(
+            "    name: \"Alive\"\n",
+            &format!("    name: \"Alive\"\n    on_failure_command: \"echo restart >> {}; echo restarted\"\n", log.display()),
+        );
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("requires allow_command_checks"));
+
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let state = app_state(&format!("{}allow_command_checks: true\ntelegram_api_url: \"{}\"\n", yaml, telegram.url()));
+        let restarts = || std::fs::read_to_string(&log).unwrap_or_default().lines().count();
+        // The command runs in the background
+        let remediation = |incident: usize| {
+            let state = state.clone();
+            async move {
+                for _ in 0..100 {
+                    let incidents = state.incidents.read().await;
+                    if let Some(remediation) = incidents["alive"].get(incident).and_then(|i| i.remediation.clone()) {
+                        return remediation;
+                    }
+                    drop(incidents);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                panic!("on_failure_command of incident {} didn't finish", incident);
+            }
+        };
+
+        for _ in 0..8 {
+            state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        }
+        assert_eq!(remediation(0).await, "Exited with status 0: restarted");
+        assert_eq!(restarts(), 1);
+
+        // A new incident runs it again, the outcome goes to the incident even after it ended
+        state.set_state("alive".to_string(), State::Success).await;
+        for _ in 0..3 {
+            state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        }
+        state.set_state("alive".to_string(), State::Success).await;
+        assert_eq!(remediation(1).await, "Exited with status 0: restarted");
+        assert_eq!(restarts(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_on_failure_command_does_not_hold_up_checks() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let yaml = CONFIG.replace("    name: \"Alive\"\n", "    name: \"Alive\"\n    on_failure_command: \"sleep 5\"\n");
+        let state = app_state(&format!("{}allow_command_checks: true\ntelegram_api_url: \"{}\"\n", yaml, telegram.url()));
+
+        let started = std::time::Instant::now();
+        for _ in 0..3 {
+            state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        }
+        assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+        assert_eq!(state.incidents.read().await["alive"][0].remediation, None);
+    }
+
+    #[tokio::test]
+    async fn test_recovery_message_includes_downtime() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let state = app_state(&format!("{}telegram_api_url: \"{}\"\n", CONFIG, telegram.url()));
+
+        state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        state.services.write().await.get_mut("alive").unwrap().down_since =
+            Some(Utc::now() - chrono::Duration::minutes(14));
+        state.set_state("alive".to_string(), State::Success).await;
+        state.flush_notifications().await;
+
+        assert_eq!(telegram.messages(), vec!["✅ <b>Recovery: Alive</b>\n\nRecovered after 14m"]);
+        assert_eq!(state.services.read().await["alive"].down_since, None);
+        assert_eq!(format_duration(chrono::Duration::seconds(93784)), "1d 2h");
+    }
+
+    #[tokio::test]
+    async fn test_rereport_edits_alert_message() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let state = app_state(&format!("{}telegram_api_url: \"{}\"\n", CONFIG, telegram.url()));
+
+        // Alert on the 3rd failure, rereport on the 13th
+        for _ in 0..13 {
+            state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+            state.flush_notifications().await;
+        }
+
+        let requests = telegram.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].0, "sendMessage");
+        assert_eq!(requests[1].0, "editMessageText");
+        assert_eq!(requests[1].1["message_id"], 1);
+        let text = requests[1].1["text"].as_str().unwrap();
+        assert!(text.starts_with("🚨 <b>Alert: Alive</b>\n\nConnection refused (still failing)\n\nConsecutive failures: 13"));
+
+        // Recovery forgets the message so the next incident gets a new one
+        state.set_state("alive".to_string(), State::Success).await;
+        assert_eq!(state.services.read().await["alive"].alert_message_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_telegram_chat_id_handle_resolved() {
+        use axum::{extract::Path, http::StatusCode, routing::post, Json, Router};
+
+        // Mock Bot API knowing a single channel
+        async fn get_chat(Path(_bot): Path<String>, Json(body): Json<serde_json::Value>) -> (StatusCode, Json<serde_json::Value>) {
+            if body["chat_id"] == "@ops_alerts" {
+                (StatusCode::OK, Json(serde_json::json!({ "ok": true, "result": { "id": -1001234567890i64, "type": "channel" } })))
+            } else {
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({ "ok": false, "error_code": 400, "description": "Bad Request: chat not found" })),
+                )
+            }
+        }
+        let url = crate::test_util::serve(Router::new().route("/:bot/getChat", post(get_chat))).await;
+        let config = |chat_id: &str| -> Config {
+            serde_yaml::from_str(&format!(
+                "{}telegram_api_url: \"{}\"\n",
+                CONFIG.replace("telegram_chat_id: 1", &format!("telegram_chat_id: {}", chat_id)),
+                url
+            ))
+            .unwrap()
+        };
+
+        let mut resolved = config("\"@ops_alerts\"");
+        assert_eq!(resolved.telegram_client().chat_id(), 0);
+        resolved.resolve_telegram_chat_id().await.unwrap();
+        assert_eq!(resolved.telegram_client().chat_id(), -1001234567890);
+        // The handle is kept when saving
+        assert!(serde_yaml::to_string(&resolved).unwrap().contains("telegram_chat_id: '@ops_alerts'"));
+
+        let err = config("\"@missing\"").resolve_telegram_chat_id().await.unwrap_err().to_string();
+        assert!(err.starts_with("Failed to resolve telegram_chat_id '@missing': Telegram API error: 400"), "{}", err);
+
+        // Numeric ids, also as strings, need no lookup
+        let mut numeric = config("\"-100200\"");
+        numeric.resolve_telegram_chat_id().await.unwrap();
+        assert_eq!(numeric.telegram_chat_id, ChatId::Id(-100200));
+        assert!(serde_yaml::from_str::<Config>(&CONFIG.replace("telegram_chat_id: 1", "telegram_chat_id: ops")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_alerts_link_runbook() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let config = CONFIG.replace(
+            "name: \"Alive\"",
+            "name: \"Alive\"\n    runbook_url: \"https://wiki.example.com/runbooks/alive?team=ops&env=prod\"",
+        );
+        let state = app_state(&format!("{}telegram_api_url: \"{}\"\n", config, telegram.url()));
+
+        for _ in 0..3 {
+            state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        }
+        state.flush_notifications().await;
+        let messages = telegram.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].ends_with(
+            "Connection refused\n\nRunbook: <a href=\"https://wiki.example.com/runbooks/alive?team=ops&amp;env=prod\">https://wiki.example.com/runbooks/alive?team=ops&amp;env=prod</a>"
+        ));
+
+        let services = state.get_all_services().await;
+        let alive = services.iter().find(|s| s.name == "Alive").unwrap();
+        assert_eq!(alive.runbook_url.as_deref(), Some("https://wiki.example.com/runbooks/alive?team=ops&env=prod"));
+        let json = serde_json::to_value(alive).unwrap();
+        assert_eq!(json["runbook_url"], "https://wiki.example.com/runbooks/alive?team=ops&env=prod");
+        // Services without a runbook omit the field
+        let backup = services.iter().find(|s| s.name == "Backup").unwrap();
+        assert!(serde_json::to_value(backup).unwrap().get("runbook_url").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_startup_notification() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let config = format!("{}telegram_api_url: \"{}\"\n", CONFIG.replacen("enabled: true", "enabled: false", 1), telegram.url());
+
+        app_state(&config).notify_startup().await;
+        assert!(telegram.messages().is_empty());
+
+        app_state(&format!("{}notify_on_startup: true\n", config)).notify_startup().await;
+        let messages = telegram.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0],
+            format!("<b>healthcheck {} started</b>\n\nMonitoring 2 services", env!("CARGO_PKG_VERSION"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failures_during_initial_grace_not_alerted() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let dir = tempfile::tempdir().unwrap();
+        let database = dir.path().join("healthcheck.db");
+        let config = CONFIG.replace("name: \"Alive\"", "name: \"Alive\"\n    initial_grace_ms: 60000");
+        let yaml = format!("{}telegram_api_url: \"{}\"\n", config, telegram.url());
+        let persisted = format!("{}database_path: \"{}\"\n", yaml, database.display());
+        let failure = || State::failure(FailureCategory::Connection, "Connection refused");
+
+        // The database records when the service was first seen, only the service without a grace period alerts
+        let state = app_state(&persisted);
+        for _ in 0..3 {
+            state.set_state("alive".to_string(), failure()).await;
+            state.set_state("backup".to_string(), failure()).await;
+        }
+        state.flush_notifications().await;
+        let messages = telegram.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("Backup"));
+        // The failures are still recorded
+        let alive = state.services.read().await["alive"].clone();
+        assert_eq!((alive.status, alive.failed_checks, alive.consecutive_failures), (Status::Down, 3, 0));
+        drop(state);
+
+        // A restart doesn't start the grace period again, once it's over failures count
+        let added_at = Utc::now() - chrono::Duration::minutes(2);
+        rusqlite::Connection::open(&database)
+            .unwrap()
+            .execute("UPDATE service_added SET added_at = ?1 WHERE service_id = 'alive'", [added_at])
+            .unwrap();
+        let state = app_state(&persisted);
+        for _ in 0..3 {
+            state.set_state("alive".to_string(), failure()).await;
+        }
+        state.flush_notifications().await;
+        let messages = telegram.messages();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[1].contains("Alive"));
+
+        // Without a database only services added by a config reload are known to be new
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        let path = dir.path().join("healthcheck.yaml");
+        let state = AppState::new(config.clone(), vec![path.to_string_lossy().to_string()]);
+        let mut reloaded = config;
+        let added = reloaded.services["alive"].clone();
+        reloaded.services.insert("added".to_string(), added);
+        state.update_config(reloaded).await.unwrap();
+        let services = state.services.read().await;
+        assert_eq!(services["alive"].added_at, None);
+        assert!(services["added"].added_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_debounce_holds_back_state_changes() {
+        let state = app_state(&CONFIG.replace("name: \"Alive\"", "name: \"Alive\"\n    debounce_checks: 2"));
+        let failure = || State::failure(FailureCategory::Connection, "Connection refused");
+        let alive = || async { state.services.read().await["alive"].clone() };
+
+        // The first result is recorded right away
+        state.set_state("alive".to_string(), State::Success).await;
+        assert_eq!(alive().await.status, Status::Up);
+
+        // A one-off failure is counted but doesn't flip the state
+        assert!(!state.set_state("alive".to_string(), failure()).await);
+        let service = alive().await;
+        assert_eq!(service.state, State::Success);
+        assert_eq!((service.total_checks, service.failed_checks, service.consecutive_failures), (2, 1, 0));
+        state.set_state("alive".to_string(), State::Success).await;
+
+        // Two failures in a row do, counting both as consecutive
+        state.set_state("alive".to_string(), failure()).await;
+        assert_eq!(alive().await.state, State::Success);
+        assert!(state.set_state("alive".to_string(), failure()).await);
+        let service = alive().await;
+        assert_eq!(service.state, failure());
+        assert_eq!((service.total_checks, service.failed_checks, service.consecutive_failures), (5, 3, 2));
+
+        // Services without a debounce flip on the first failure
+        state.set_state("backup".to_string(), State::Success).await;
+        state.set_state("backup".to_string(), failure()).await;
+        assert_eq!(state.services.read().await["backup"].state, failure());
+    }
+
+    #[tokio::test]
+    async fn test_alert_on_cumulative_failures_in_window() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let config = CONFIG.replace(
+            "name: \"Alive\"",
+            "name: \"Alive\"\n    failure_window:\n      failures: 3\n      checks: 5",
+        );
+        let state = app_state(&format!("{}telegram_api_url: \"{}\"\n", config, telegram.url()));
+        let check = |failed: bool| {
+            let state = state.clone();
+            async move {
+                let result = if failed { State::failure(FailureCategory::Connection, "Connection refused") } else { State::Success };
+                state.set_state("alive".to_string(), result).await;
+                state.flush_notifications().await;
+            }
+        };
+        let alerts = || telegram.messages().into_iter().filter(|m| m.contains("checks failed")).count();
+
+        // Never 3 in a row, but the 3rd failure within 5 checks alerts
+        for failed in [true, false, true, false] {
+            check(failed).await;
+        }
+        assert_eq!(alerts(), 0);
+        check(true).await;
+        assert_eq!(alerts(), 1);
+        assert!(telegram.messages().iter().any(|m| m.contains("Connection refused\n\n3 of the last 5 checks failed")));
+
+        // Further failures in the same window don't alert again
+        check(true).await;
+        assert_eq!(alerts(), 1);
+
+        // Once the window is clean, the next burst alerts again
+        for _ in 0..5 {
+            check(false).await;
+        }
+        for failed in [true, false, true, true] {
+            check(failed).await;
+        }
+        assert_eq!(alerts(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rereports_capped_per_incident() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let state = app_state(&format!(
+            "{}telegram_api_url: \"{}\"\nmax_reports_per_incident: 2\n",
+            CONFIG.replace("rereport: 10", "rereport: 1"),
+            telegram.url()
+        ));
+        let fail = |n| {
+            let state = state.clone();
+            async move {
+                for _ in 0..n {
+                    state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+                    state.flush_notifications().await;
+                }
+            }
+        };
+
+        // Alert on the 3rd failure, then only two rereports
+        fail(10).await;
+        let methods: Vec<String> = telegram.requests().into_iter().map(|(method, _)| method).collect();
+        assert_eq!(methods, ["sendMessage", "editMessageText", "editMessageText"]);
+
+        // Recovery resets the counter for the next incident
+        state.set_state("alive".to_string(), State::Success).await;
+        fail(5).await;
+        assert_eq!(telegram.requests().len(), 3 + 1 + 3);
+        assert_eq!(state.services.read().await["alive"].rereports_sent, 2);
+    }
+
+    #[tokio::test]
+    async fn test_acknowledge_button_suppresses_rereports() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let state = app_state(&format!(
+            "{}telegram_api_url: \"{}\"\n",
+            CONFIG.replace("rereport: 10", "rereport: 1"),
+            telegram.url()
+        ));
+        let fail = || state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused"));
+
+        assert_eq!(state.handle_callback("ack:alive").await, "Alive is not failing");
+        for _ in 0..3 {
+            fail().await;
+        }
+        assert_eq!(state.handle_callback("ack:alive").await, "Acknowledged Alive");
+        fail().await;
+        fail().await;
+        state.flush_notifications().await;
+        assert_eq!(telegram.requests().len(), 1);
+
+        // Recovery clears the acknowledgement
+        state.set_state("alive".to_string(), State::Success).await;
+        assert!(!state.services.read().await["alive"].acknowledged);
+        assert_eq!(state.handle_callback("ack:missing").await, "Service not found");
+        assert_eq!(state.handle_callback("delete:alive").await, "Unknown action");
+    }
+
+    #[tokio::test]
+    async fn test_undeliverable_notification_is_dead_lettered() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dead_letters.jsonl");
+        let state = app_state(&format!(
+            "{}telegram_api_url: \"http://127.0.0.1:1\"\nnotification_retries: 1\ndead_letter_path: \"{}\"\n",
+            CONFIG,
+            path.display()
+        ));
+
+        state.set_state("alive".to_string(), State::Warning("Certificate expires in 5 days".to_string())).await;
+        state.flush_notifications().await;
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let letters: Vec<DeadLetter> = content.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].channel, "telegram");
+        assert_eq!(letters[0].service, "Alive");
+        assert_eq!(letters[0].state, "warning");
+        assert_eq!(letters[0].message, "Certificate expires in 5 days");
+        assert!(!letters[0].error.is_empty());
+    }
+
+    fn http_check(yaml: &str) -> ServiceHttp {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_checks_limited_per_host() {
+        use axum::{extract::State as AxumState, http::HeaderMap, routing::get, Router};
+        use std::sync::Mutex;
+
+        // Active and peak concurrent requests per Host header, plus the overall peak
+        #[derive(Default)]
+        struct Concurrency {
+            active: HashMap<String, usize>,
+            peak: HashMap<String, usize>,
+            total_peak: usize,
+        }
+        let concurrency: Arc<Mutex<Concurrency>> = Arc::default();
+        let url = crate::test_util::serve(
+            Router::new()
+                .route(
+                    "/*path",
+                    get(|AxumState(c): AxumState<Arc<Mutex<Concurrency>>>, headers: HeaderMap| async move {
+                        let host = headers["host"].to_str().unwrap().split(':').next().unwrap().to_string();
+                        {
+                            let mut c = c.lock().unwrap();
+                            let active = c.active.entry(host.clone()).or_default();
+                            *active += 1;
+                            let active = *active;
+                            let peak = c.peak.entry(host.clone()).or_default();
+                            *peak = (*peak).max(active);
+                            let total = c.active.values().sum();
+                            c.total_peak = c.total_peak.max(total);
+                        }
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                        *c.lock().unwrap().active.get_mut(&host).unwrap() -= 1;
+                    }),
+                )
+                .with_state(concurrency.clone()),
+        )
+        .await;
+        let port = url.rsplit(':').next().unwrap();
+
+        let state = app_state(&format!(
+            r#"
+telegram_token: "token"
+telegram_chat_id: 1
+check_interval_success: 60000
+check_interval_fail: 10000
+notify_failures: 3
+rereport: 10
+max_checks_per_host: 1
+services:
+  first:
+    enabled: true
+    name: "First"
+    description: "Same host"
+    check: !http
+      url: "http://127.0.0.1:{port}/first"
+  second:
+    enabled: true
+    name: "Second"
+    description: "Same host"
+    check: !http
+      url: "http://127.0.0.1:{port}/second"
+  other:
+    enabled: true
+    name: "Other"
+    description: "Different host"
+    check: !http
+      url: "http://localhost:{port}/other"
+"#
+        ));
+        let services = state.run_once().await;
+        assert!(services.iter().all(|s| s.status == Status::Up), "{:?}", services);
+
+        let c = concurrency.lock().unwrap();
+        assert_eq!(c.peak["127.0.0.1"], 1);
+        assert_eq!(c.peak["localhost"], 1);
+        assert_eq!(c.total_peak, 2);
+    }
+
+    #[tokio::test]
+    async fn test_http_flow_sends_session_cookie() {
+        use axum::{
+            http::{header, HeaderMap, StatusCode},
+            routing::{get, post},
+            Form, Router,
+        };
+
+        let url = crate::test_util::serve(
+            Router::new()
+                .route(
+                    "/login",
+                    post(|Form(form): Form<HashMap<String, String>>| async move {
+                        if form.get("password").map(String::as_str) == Some("secret") {
+                            Ok([(header::SET_COOKIE, "session=abc123; Path=/")])
+                        } else {
+                            Err(StatusCode::UNAUTHORIZED)
+                        }
+                    }),
+                )
+                .route(
+                    "/dashboard",
+                    get(|headers: HeaderMap| async move {
+                        match headers.get(header::COOKIE).and_then(|v| v.to_str().ok()) {
+                            Some("session=abc123") => StatusCode::OK,
+                            _ => StatusCode::FORBIDDEN,
+                        }
+                    }),
+                ),
+        )
+        .await;
+
+        let flow = |password: &str| -> ServiceHttpFlow {
+            serde_yaml::from_str(&format!(
+                "steps:\n  - url: \"{url}/login\"\n    method: post\n    form:\n      user: admin\n      password: {password}\n  - url: \"{url}/dashboard\"\n",
+            ))
+            .unwrap()
+        };
+        let ctx = CheckContext::default();
+
+        assert_eq!(flow("secret").check(&ctx).await, State::Success);
+        assert_eq!(
+            flow("wrong").check(&ctx).await,
+            State::Failure(unexpected_status(401).map_detail(|detail| format!("Step 1 ({}/login): {}", url, detail)))
+        );
+
+        // Without logging in the protected page is forbidden
+        let direct: ServiceHttpFlow = serde_yaml::from_str(&format!("steps:\n  - url: \"{}/dashboard\"\n", url)).unwrap();
+        assert_eq!(
+            direct.check(&ctx).await,
+            State::Failure(unexpected_status(403).map_detail(|detail| format!("Step 1 ({}/dashboard): {}", url, detail)))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_warmup_cookie_sent_with_check() {
+        use axum::{
+            http::{header, HeaderMap, StatusCode},
+            routing::get,
+            Router,
+        };
+
+        let url = crate::test_util::serve(
+            Router::new()
+                .route("/form", get(|| async { [(header::SET_COOKIE, "csrf=token1; Path=/")] }))
+                .route(
+                    "/submit",
+                    get(|headers: HeaderMap| async move {
+                        match headers.get(header::COOKIE).and_then(|v| v.to_str().ok()) {
+                            Some("csrf=token1") => StatusCode::OK,
+                            _ => StatusCode::FORBIDDEN,
+                        }
+                    }),
+                ),
+        )
+        .await;
+        let ctx = CheckContext::default();
+
+        let http = http_check(&format!("url: \"{url}/submit\"\nexpected_status: 200\nwarmup_url: \"{url}/form\""));
+        assert_eq!(http.check(&ctx).await, State::Success);
+
+        // Without the warm-up the cookie is missing
+        let http = http_check(&format!("url: \"{url}/submit\"\nexpected_status: 200\ncookie_store: true"));
+        assert_eq!(http.check(&ctx).await, State::Failure(unexpected_status(403)));
+
+        let http = http_check(&format!("url: \"{url}/submit\"\nexpected_status: 200\nwarmup_url: \"{url}/missing\""));
+        assert_eq!(
+            http.check(&ctx).await,
+            State::Failure(FailureReason::new(FailureCategory::Status, "Warm-up request returned status 404").with_code(404))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_timeout() {
+        use axum::{routing::get, Router};
+
+        let url = crate::test_util::serve(Router::new().route(
+            "/stuck",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                "late"
+            }),
+        ))
+        .await;
+
+        let http = http_check(&format!("url: \"{}/stuck\"\ntimeout_ms: 200", url));
+        let started = std::time::Instant::now();
+        assert_eq!(http.check(&CheckContext::default()).await, State::failure(FailureCategory::Timeout, "Timeout after 200ms"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(http_check(&format!("url: \"{}/stuck\"", url)).timeout_ms(), DEFAULT_HTTP_TIMEOUT_MS);
+    }
+
+    #[tokio::test]
+    async fn test_http_connect_and_response_timeouts_reported_apart() {
+        use axum::{routing::get, Router};
+
+        let url = crate::test_util::serve(Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                "late"
+            }),
+        ))
+        .await;
+        let ctx = CheckContext::default();
+
+        let http = http_check(&format!("url: \"{}/slow\"\nconnect_timeout_ms: 1000\nread_timeout_ms: 200", url));
+        assert_eq!(http.check(&ctx).await, State::failure(FailureCategory::Timeout, "Response timed out after 200ms"));
+
+        // A listener whose accept queue is full leaves further connection attempts pending
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(0).unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut queued = Vec::new();
+        while let Ok(Ok(stream)) = tokio::time::timeout(Duration::from_millis(200), tokio::net::TcpStream::connect(address)).await {
+            queued.push(stream);
+        }
+
+        let http = http_check(&format!("url: \"http://{}/\"\nconnect_timeout_ms: 200\nread_timeout_ms: 2000", address));
+        assert_eq!(http.check(&ctx).await, State::failure(FailureCategory::Timeout, "Connect timed out after 200ms"));
+    }
+
+    #[tokio::test]
+    async fn test_http_samples_majority() {
+        use axum::{http::StatusCode, routing::get, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Every third request fails
+        let calls = Arc::new(AtomicUsize::new(0));
+        let url = crate::test_util::serve(Router::new().route(
+            "/",
+            get({
+                let calls = calls.clone();
+                move || async move {
+                    if calls.fetch_add(1, Ordering::SeqCst).is_multiple_of(3) {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::OK
+                    }
+                }
+            }),
+        ))
+        .await;
+        let ctx = CheckContext::default();
+
+        // A single request hits the failure
+        let http = http_check(&format!("url: \"{}\"\nexpected_status: 200", url));
+        assert_eq!(http.check(&ctx).await, State::Failure(unexpected_status(500)));
+
+        // Two of three samples pass, which is a majority
+        calls.store(0, Ordering::SeqCst);
+        let http = http_check(&format!("url: \"{}\"\nexpected_status: 200\nsamples: 3", url));
+        assert_eq!(http.check(&ctx).await, State::Success);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        calls.store(0, Ordering::SeqCst);
+        let http = http_check(&format!("url: \"{}\"\nexpected_status: 200\nsamples: 3\nmajority_threshold: 3", url));
+        assert_eq!(
+            http.check(&ctx).await,
+            State::Failure(unexpected_status(500).map_detail(|detail| format!("2 of 3 samples passed (3 required): {}", detail)))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_samples_not_counted() {
+        use axum::{http::StatusCode, routing::get, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // The first request fails, later ones are rate limited
+        let calls = Arc::new(AtomicUsize::new(0));
+        let url = crate::test_util::serve(Router::new().route(
+            "/",
+            get({
+                let calls = calls.clone();
+                move || async move {
+                    match calls.fetch_add(1, Ordering::SeqCst) {
+                        0 => (StatusCode::INTERNAL_SERVER_ERROR, [("retry-after", "0")]),
+                        _ => (StatusCode::TOO_MANY_REQUESTS, [("retry-after", "1")]),
+                    }
+                }
+            }),
+        ))
+        .await;
+        let http = http_check(&format!(
+            "url: \"{}\"\nexpected_status: 200\nsamples: 3\nmajority_threshold: 1\nrespect_rate_limits: true",
+            url
+        ));
+        let ctx = CheckContext::default();
+        let client = http.connection.http_client(&ctx).unwrap();
+
+        // Sampling stops at the rate limited sample, which doesn't pass
+        assert_eq!(
+            http.sample_url(&client, &url, None, &ctx).await,
+            State::Failure(unexpected_status(500).map_detail(|detail| format!("0 of 1 samples passed (1 required): {}", detail)))
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Without a sample that ran there's no result
+        *ctx.rate_limit_delay.lock().unwrap() = None;
+        assert_eq!(http.sample_url(&client, &url, None, &ctx).await, State::Unknown);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_http_respects_rate_limits() {
+        use axum::{http::StatusCode, routing::get, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let url = crate::test_util::serve(Router::new().route(
+            "/",
+            get({
+                let calls = calls.clone();
+                move || async move {
+                    if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                        (StatusCode::TOO_MANY_REQUESTS, [("retry-after", "1")])
+                    } else {
+                        (StatusCode::OK, [("retry-after", "0")])
+                    }
+                }
+            }),
+        ))
+        .await;
+        let ctx = CheckContext::default();
+
+        // The check doesn't wait, it asks for the next one to be deferred
+        let http = http_check(&format!("url: \"{}\"\nexpected_status: 200\nrespect_rate_limits: true", url));
+        let started = std::time::Instant::now();
+        assert_eq!(http.check(&ctx).await, State::Unknown);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(ctx.rate_limit_delay.lock().unwrap().take(), Some(Duration::from_secs(1)));
+        assert_eq!(http.check(&ctx).await, State::Success);
+        assert_eq!(ctx.rate_limit_delay.lock().unwrap().take(), None);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // A deferred check keeps the previous state and isn't counted
+        calls.store(0, Ordering::SeqCst);
+        let state = app_state(&CONFIG.replace(
+            "check: !tcpPing\n      host: \"127.0.0.1\"\n      port: 2",
+            &format!("check: !http\n      url: \"{}\"\n      expected_status: 200\n      respect_rate_limits: true", url),
+        ));
+        let service = state.get_config().await.services["backup"].clone();
+        assert_eq!(service.run_check("backup", &state).await, (State::Unknown, Some(Duration::from_secs(1))));
+        assert_eq!(state.services.read().await["backup"].total_checks, 0);
+        assert_eq!(service.run_check("backup", &state).await, (State::Success, None));
+        assert_eq!(state.services.read().await["backup"].total_checks, 1);
+
+        // Without the option a 429 is a failure
+        calls.store(0, Ordering::SeqCst);
+        let http = http_check(&format!("url: \"{}\"\nexpected_status: 200", url));
+        assert_eq!(http.check(&ctx).await, State::Failure(unexpected_status(429)));
+    }
+
+    #[test]
+    fn test_rate_limit_delay_falls_back_to_reset_header() {
+        let response = |headers: &[(&str, &str)]| {
+            let mut builder = axum::http::Response::builder().status(429);
+            for (name, value) in headers {
+                builder = builder.header(*name, *value);
+            }
+            reqwest::Response::from(builder.body("").unwrap())
+        };
+
+        assert_eq!(rate_limit_delay(&response(&[("retry-after", "30")])), Some(Duration::from_secs(30)));
+        assert_eq!(
+            rate_limit_delay(&response(&[("retry-after", "soon"), ("x-ratelimit-reset", "5")])),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(rate_limit_delay(&response(&[("retry-after", "soon")])), None);
+    }
+
+    #[tokio::test]
+    async fn test_checks_route_through_socks_proxy() {
+        use axum::{routing::get, Router};
+
+        let proxy = crate::test_util::MockSocks::start().await;
+        let url = crate::test_util::serve(Router::new().route("/", get(|| async { "ok" }))).await;
+        let target = url.trim_start_matches("http://").to_string();
+        let (host, port) = target.rsplit_once(':').unwrap();
+        let ctx = CheckContext::default();
+
+        let http = http_check(&format!("url: \"{}\"\nexpected_status: 200\nsocks_proxy: \"{}\"", url, proxy.address()));
+        assert_eq!(http.check(&ctx).await, State::Success);
+
+        let tcp: ServiceTcpPing = serde_yaml::from_str(&format!(
+            "host: \"{}\"\nport: {}\nsocks_proxy: \"socks5://{}\"",
+            host,
+            port,
+            proxy.address()
+        ))
+        .unwrap();
+        assert_eq!(tcp.check(&ctx).await, State::Success);
 
-                let notification = match &state {
-                    State::Success => {
-                        service_state.consecutive_failures = 0;
-                        service_state.successful_checks += 1;
+        assert_eq!(proxy.targets(), vec![target.clone(), target]);
+    }
 
-                        // Set uptime_start only on first successful check
-                        if service_state.uptime_start.is_none() {
-                            service_state.uptime_start = Some(now);
-                        }
+    #[tokio::test]
+    async fn test_checks_bind_source_address() {
+        use tokio::io::AsyncWriteExt;
 
-                        // Send recovery notification if was previously failing
-                        if was_failing {
-                            Some((service_state.name.clone(), "recovered".to_string(), true))
-                        } else {
-                            None
-                        }
-                    }
-                    State::Failure(reason) => {
-                        service_state.consecutive_failures += 1;
-                        service_state.failed_checks += 1;
-                        // Clear uptime when service fails
-                        service_state.uptime_start = None;
+        // Answers every connection with an empty HTTP 200 and records the peer address
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let peers: Arc<std::sync::Mutex<Vec<IpAddr>>> = Arc::default();
+        let recorded = peers.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, peer) = listener.accept().await.unwrap();
+                recorded.lock().unwrap().push(peer.ip());
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+            }
+        });
 
-                        // Send alert if consecutive failures reached threshold
-                        if service_state.consecutive_failures == notify_failures {
-                            Some((service_state.name.clone(), reason.clone(), false))
-                        }
-                        // Resend alert at rereport intervals
-                        else if service_state.consecutive_failures > notify_failures
-                            && (service_state.consecutive_failures - notify_failures) % rereport == 0 {
-                            Some((service_state.name.clone(), format!("{} (still failing)", reason), false))
-                        } else {
-                            None
-                        }
-                    }
-                    State::Unknown => None,
-                };
+        // The whole 127.0.0.0/8 range is local on Linux
+        let config: Config = serde_yaml::from_str(&format!(
+            "{}source_address: \"127.0.0.2\"\n",
+            CONFIG.replace("port: 1", &format!("port: {}", port))
+        ))
+        .unwrap();
+        config.validate().unwrap();
+        let ctx = config.check_context();
 
-                notification
-            } else {
-                None
+        assert_eq!(config.services["alive"].check(&ctx).await, State::Success);
+        let http = http_check(&format!("url: \"http://127.0.0.1:{}/\"\nexpected_status: 200", port));
+        assert_eq!(http.check(&ctx).await, State::Success);
+
+        // A per-service address overrides the global one
+        let mut service = config.services["alive"].clone();
+        service.source_address = Some("127.0.0.3".to_string());
+        assert_eq!(service.check(&ctx).await, State::Success);
+
+        // A TCP ping completes on connect, possibly before the server accepted it
+        for _ in 0..100 {
+            if peers.lock().unwrap().len() == 3 {
+                break;
             }
-        }; // Release locks before sending notification
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let source = |a: &str| a.parse::<IpAddr>().unwrap();
+        assert_eq!(*peers.lock().unwrap(), vec![source("127.0.0.2"), source("127.0.0.2"), source("127.0.0.3")]);
 
-        // Send notification if needed (outside of locks)
-        if let Some((service_name, message, is_recovery)) = notification {
-            let result = if is_recovery {
-                self.telegram.send_recovery(&service_name, &message).await
-            } else {
-                self.telegram.send_alert(&service_name, &message).await
+        service.source_address = Some("eth0".to_string());
+        let mut invalid = config.clone();
+        invalid.services.insert("alive".to_string(), service);
+        assert!(invalid.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connection_options_shared_by_check_types() {
+        use tokio::io::AsyncWriteExt;
+
+        // Records the peer address and answers with an HTTP 200 kept open, or stays silent on port `quiet`
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let peers: Arc<std::sync::Mutex<Vec<IpAddr>>> = Arc::default();
+        let recorded = peers.clone();
+        tokio::spawn(async move {
+            let mut open = Vec::new();
+            loop {
+                let (mut stream, peer) = listener.accept().await.unwrap();
+                recorded.lock().unwrap().push(peer.ip());
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+                open.push(stream);
+            }
+        });
+
+        let options = "source_address: \"127.0.0.4\"\nip_version: ipv4\ntimeout_ms: 300\n";
+        let checks = [
+            format!("!http\nurl: \"http://127.0.0.1:{}/\"\nexpected_status: 200\n{}", port, options),
+            format!("!tcpPing\nhost: 127.0.0.1\nport: {}\n{}", port, options),
+            format!("!tcpBanner\nhost: 127.0.0.1\nport: {}\nexpect: \"200 OK\"\n{}", port, options),
+            format!("!certificate\nhost: 127.0.0.1\nport: {}\n{}", port, options),
+        ];
+        let ctx = CheckContext::default();
+        for yaml in &checks {
+            let check: CheckType = serde_yaml::from_str(yaml).unwrap();
+            let connection = check.connection().unwrap();
+            assert_eq!(connection.timeout_ms, Some(300), "{}", yaml);
+            assert_eq!(connection.ip_version, Some(IpVersion::Ipv4), "{}", yaml);
+
+            let state = check.check(&ctx).await;
+            match check {
+                // The plain HTTP answer fails the TLS handshake, but only after connecting
+                CheckType::Certificate(_) => {
+                    assert!(matches!(&state, State::Failure(reason) if reason.detail.starts_with("TLS handshake")), "{:?}", state)
+                }
+                _ => assert_eq!(state, State::Success, "{}", yaml),
+            }
+        }
+
+        // A TCP ping completes on connect, possibly before the server accepted it
+        for _ in 0..100 {
+            if peers.lock().unwrap().len() == checks.len() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(*peers.lock().unwrap(), vec!["127.0.0.4".parse::<IpAddr>().unwrap(); checks.len()]);
+
+        // Restricting to IPv6 leaves no address to connect to
+        let tcp: ServiceTcpPing = serde_yaml::from_str(&format!("host: 127.0.0.1\nport: {}\nip_version: ipv6", port)).unwrap();
+        assert_eq!(
+            tcp.check(&ctx).await,
+            State::failure(FailureCategory::Connection, "Connection failed: No addresses found for 127.0.0.1")
+        );
+
+        // The timeout bounds the whole banner exchange
+        let banner: ServiceTcpBanner =
+            serde_yaml::from_str(&format!("host: 127.0.0.1\nport: {}\nexpect: \"SSH\"\ntimeout_ms: 100", port)).unwrap();
+        assert_eq!(
+            banner.check(&ctx).await,
+            State::failure(FailureCategory::Content, "Unexpected response: HTTP/1.1 200 OK\r\nContent-Length: 0")
+        );
+
+        let mut config: Config = serde_yaml::from_str(CONFIG).unwrap();
+        config.services.get_mut("alive").unwrap().check = serde_yaml::from_str(&format!(
+            "!tcpPing\nhost: 127.0.0.1\nport: {}\nsource_address: \"::1\"\nip_version: ipv4",
+            port
+        ))
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failure_categories_by_check_type() {
+        use axum::{http::StatusCode, routing::get, Router};
+
+        let url = crate::test_util::serve(Router::new().route("/", get(|| async { StatusCode::SERVICE_UNAVAILABLE }))).await;
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let ctx = CheckContext { allow_command_checks: true, ..CheckContext::default() };
+
+        // Run each check against a failing target, each reports the category of its failure
+        let cases = [
+            (format!("!http\nurl: \"{}/\"\nexpected_status: 200", url), FailureCategory::Status, Some(503)),
+            (format!("!httpFlow\nsteps:\n  - url: \"{}/\"", url), FailureCategory::Status, Some(503)),
+            (format!("!grpcWeb\nurl: \"{}\"", url), FailureCategory::Status, Some(404)),
+            (
+                format!("!promql\nendpoint: \"{}\"\nquery: up\ncomparator: \">\"\nthreshold: 0", url),
+                FailureCategory::Status,
+                Some(404),
+            ),
+            (format!("!tcpPing\nhost: 127.0.0.1\nport: {}", closed), FailureCategory::Connection, None),
+            (format!("!tcpBanner\nhost: 127.0.0.1\nport: {}\nexpect: \"SSH\"", closed), FailureCategory::Connection, None),
+            (format!("!portScan\nhost: 127.0.0.1\nexpected_open: [{}]", closed), FailureCategory::Content, None),
+            (format!("!certificate\nhost: 127.0.0.1\nport: {}", closed), FailureCategory::Connection, None),
+            ("!certificateFile\npath: /nonexistent.pem".to_string(), FailureCategory::Certificate, None),
+            ("!external\ncommand: \"false\"".to_string(), FailureCategory::Command, Some(1)),
+            ("!external\ncommand: \"sh\"\nargs: [\"-c\", \"echo Invalid queue; exit 2\"]".to_string(), FailureCategory::Command, Some(2)),
+            ("!external\ncommand: \"sleep\"\nargs: [\"5\"]\ntimeout_ms: 100".to_string(), FailureCategory::Timeout, None),
+        ];
+        for (yaml, category, code) in cases {
+            let check: CheckType = serde_yaml::from_str(&yaml).unwrap();
+            let State::Failure(reason) = check.check(&ctx).await else { panic!("expected {} to fail", yaml) };
+            assert_eq!((reason.category, reason.code), (category, code), "{}: {}", yaml, reason);
+        }
+
+        // A message mentioning another kind of failure doesn't change the category
+        let check: CheckType =
+            serde_yaml::from_str("!external\ncommand: \"sh\"\nargs: [\"-c\", \"echo Timeout after 5ms; exit 3\"]").unwrap();
+        assert_eq!(
+            check.check(&ctx).await,
+            State::Failure(FailureReason::new(FailureCategory::Command, "Timeout after 5ms").with_code(3))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_port_scan() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open = listener.local_addr().unwrap().port();
+        // A port that was just released has nothing listening on it
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let scan = |expected_open: u16, expected_closed: u16| -> ServicePortScan {
+            serde_yaml::from_str(&format!(
+                "host: 127.0.0.1\nexpected_open: [{}]\nexpected_closed: [{}]",
+                expected_open, expected_closed
+            ))
+            .unwrap()
+        };
+        let ctx = CheckContext::default();
+
+        assert_eq!(scan(open, closed).check(&ctx).await, State::Success);
+        let State::Failure(FailureReason { detail: reason, .. }) = scan(closed, open).check(&ctx).await else {
+            panic!("expected a failure")
+        };
+        assert!(
+            reason.starts_with(&format!("Unexpected port states: {} closed (", closed)),
+            "{}",
+            reason
+        );
+        assert!(reason.ends_with(&format!(", {} open", open)), "{}", reason);
+
+        // Failing to resolve the host or to reach the proxy fails the check instead of closing every port
+        let mut unresolvable = scan(open, closed);
+        unresolvable.host = "unresolvable.invalid".to_string();
+        let State::Failure(reason) = unresolvable.check(&ctx).await else {
+            panic!("expected a failure")
+        };
+        assert_eq!(reason.category, FailureCategory::Dns);
+        let mut proxied = scan(closed, open);
+        proxied.connection.socks_proxy = Some(format!("127.0.0.1:{}", closed));
+        let State::Failure(reason) = proxied.check(&ctx).await else {
+            panic!("expected a failure")
+        };
+        assert_eq!(reason.category, FailureCategory::Connection);
+        assert!(reason.detail.contains("Failed to connect to SOCKS proxy"), "{}", reason);
+
+        let mut config: Config = serde_yaml::from_str(CONFIG).unwrap();
+        config.services.get_mut("alive").unwrap().check =
+            serde_yaml::from_str("!portScan\nhost: 127.0.0.1\nexpected_open: [22]\nexpected_closed: [22]").unwrap();
+        assert!(config.validate().is_err());
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn test_grpc_web_health_check() {
+        use axum::{body::Bytes, http::header, routing::post, Router};
+
+        // Health status per requested service name, "broken" fails with a gRPC error
+        async fn health(body: Bytes) -> impl axum::response::IntoResponse {
+            let service = String::from_utf8_lossy(body.get(7..).unwrap_or_default()).to_string();
+            let (status, trailers) = match service.as_str() {
+                "" => (1, "grpc-status: 0\r\n"),
+                "db" => (2, "grpc-status: 0\r\n"),
+                _ => (0, "grpc-status: 14\r\ngrpc-message: unavailable\r\n"),
             };
+            let mut frames = vec![0, 0, 0, 0, 2, 0x08, status];
+            frames.push(0x80);
+            frames.extend_from_slice(&(trailers.len() as u32).to_be_bytes());
+            frames.extend_from_slice(trailers.as_bytes());
+            ([(header::CONTENT_TYPE, grpc_web::CONTENT_TYPE)], frames)
+        }
+
+        let url = crate::test_util::serve(Router::new().route(grpc_web::HEALTH_CHECK_PATH, post(health))).await;
+        let grpc = |service: &str| ServiceGrpcWeb {
+            url: url.clone(),
+            service: Some(service.to_string()),
+        };
+        let ctx = CheckContext::default();
 
-            if let Err(e) = result {
-                tracing::error!("Failed to send Telegram notification: {}", e);
+        assert_eq!(grpc("").check(&ctx).await, State::Success);
+        assert_eq!(grpc("db").check(&ctx).await, State::failure(FailureCategory::Content, "Health status: NOT_SERVING"));
+        assert_eq!(
+            grpc("broken").check(&ctx).await,
+            State::Failure(FailureReason::new(FailureCategory::Status, "gRPC status 14: unavailable").with_code(14))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_promql_check() {
+        use axum::{extract::Query, http::StatusCode, routing::get, Json, Router};
+
+        // Mock Prometheus answering a few known queries
+        async fn query(Query(params): Query<HashMap<String, String>>) -> (StatusCode, Json<serde_json::Value>) {
+            let result = |result_type: &str, result: serde_json::Value| {
+                serde_json::json!({ "status": "success", "data": { "resultType": result_type, "result": result } })
+            };
+            match params["query"].as_str() {
+                "scalar(queue_depth)" => (StatusCode::OK, Json(result("scalar", serde_json::json!([1700000000.0, "42"])))),
+                "up{job=\"api\"}" => (
+                    StatusCode::OK,
+                    Json(result("vector", serde_json::json!([{ "metric": { "job": "api" }, "value": [1700000000.0, "1"] }]))),
+                ),
+                "up" => (StatusCode::OK, Json(result("vector", serde_json::json!([])))),
+                _ => (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({ "status": "error", "errorType": "bad_data", "error": "parse error" })),
+                ),
             }
         }
+        let endpoint = crate::test_util::serve(Router::new().route(promql::QUERY_PATH, get(query))).await;
+        let check = |query: &str, comparator: &str, threshold: f64| ServicePromQL {
+            endpoint: endpoint.clone(),
+            query: query.to_string(),
+            comparator: comparator.to_string(),
+            threshold,
+        };
+        let ctx = CheckContext::default();
+
+        assert_eq!(check("scalar(queue_depth)", "<", 100.0).check(&ctx).await, State::Success);
+        assert_eq!(
+            check("scalar(queue_depth)", "<", 10.0).check(&ctx).await,
+            State::failure(FailureCategory::Content, "Value 42 violates < 10")
+        );
+        assert_eq!(check("up{job=\"api\"}", "==", 1.0).check(&ctx).await, State::Success);
+        assert_eq!(
+            check("up", "==", 1.0).check(&ctx).await,
+            State::failure(FailureCategory::Content, "Query returned 0 series, expected a single value")
+        );
+        assert_eq!(
+            check("up{", "==", 1.0).check(&ctx).await,
+            State::failure(FailureCategory::Content, "Query failed: parse error")
+        );
     }
 
-    pub async fn get_all_services(&self) -> Vec<ServiceState> {
-        let services = self.services.read().await;
-        let mut result: Vec<ServiceState> = services.values().cloned().collect();
-        result.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-        result
+    #[tokio::test]
+    async fn test_http_body_format() {
+        use axum::{http::header, routing::get, Router};
+
+        let url = crate::test_util::serve(
+            Router::new()
+                .route("/json", get(|| async { ([(header::CONTENT_TYPE, "application/json")], r#"{"status":"ok"}"#) }))
+                .route("/xml", get(|| async { ([(header::CONTENT_TYPE, "application/xml")], "<status>ok</status>") }))
+                .route("/html", get(|| async { ([(header::CONTENT_TYPE, "text/html")], "<html><body>Error</html>") })),
+        )
+        .await;
+
+        let ctx = CheckContext::default();
+        let json = http_check(&format!("url: {}/json\nexpected_content_type: application/json\nbody_format: json", url));
+        assert_eq!(json.check(&ctx).await, State::Success);
+
+        let xml = http_check(&format!("url: {}/xml\nbody_format: xml", url));
+        assert_eq!(xml.check(&ctx).await, State::Success);
+
+        let html = http_check(&format!("url: {}/html\nbody_format: json", url));
+        assert!(matches!(html.check(&ctx).await, State::Failure(reason) if reason.detail.starts_with("Body is not valid JSON")));
+
+        let html = http_check(&format!("url: {}/html\nexpected_content_type: application/json", url));
+        assert_eq!(html.check(&ctx).await, State::failure(FailureCategory::Content, "Unexpected content type: text/html"));
     }
 
-    pub async fn get_config(&self) -> Config {
-        self.config.read().await.clone()
+    #[tokio::test]
+    async fn test_http_expected_charset() {
+        use axum::{http::header, routing::get, Router};
+
+        let url = crate::test_util::serve(
+            Router::new()
+                .route("/utf8", get(|| async { ([(header::CONTENT_TYPE, "text/plain; charset=UTF-8")], "Grüße, 世界") }))
+                // Latin-1 bytes labeled as UTF-8
+                .route("/mislabeled", get(|| async { ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], b"Gr\xfc\xdfe".to_vec()) }))
+                .route("/latin1", get(|| async { ([(header::CONTENT_TYPE, "text/plain; charset=iso-8859-1")], b"Gr\xfc\xdfe".to_vec()) }))
+                .route("/unlabeled", get(|| async { ([(header::CONTENT_TYPE, "text/plain")], "ok") })),
+        )
+        .await;
+        let ctx = CheckContext::default();
+        let check = |path: &str, charset: &str| http_check(&format!("url: {}{}\nexpected_charset: {}\nbody_contains: Gr", url, path, charset));
+
+        assert_eq!(check("/utf8", "utf8").check(&ctx).await, State::Success);
+        match check("/mislabeled", "utf-8").check(&ctx).await {
+            State::Failure(reason) => assert!(reason.detail.starts_with("Body is not valid UTF-8: Gr"), "{}", reason),
+            state => panic!("{:?}", state),
+        }
+        assert_eq!(
+            check("/latin1", "utf-8").check(&ctx).await,
+            State::failure(FailureCategory::Content, "Unexpected charset: iso-8859-1, expected UTF-8")
+        );
+        assert_eq!(check("/latin1", "latin1").check(&ctx).await, State::Success);
+        assert_eq!(
+            check("/unlabeled", "utf-8").check(&ctx).await,
+            State::failure(FailureCategory::Content, "Content type declares no charset: text/plain")
+        );
     }
 
-    pub async fn start_monitoring_tasks(&self) {
-        let config = self.config.read().await;
-        let mut handles = self.task_handles.write().await;
+    #[tokio::test]
+    async fn test_http_method_and_body() {
+        use axum::{http::{header, HeaderMap, StatusCode}, routing::post, Router};
 
-        for (uuid, service) in config.services.iter() {
-            if !service.enabled {
-                tracing::info!("Service '{}' is disabled, skipping", service.name);
-                continue;
+        // Healthy only for a JSON GraphQL query
+        let url = crate::test_util::serve(Router::new().route(
+            "/graphql",
+            post(|headers: HeaderMap, body: String| async move {
+                let json = headers.get(header::CONTENT_TYPE).is_some_and(|v| v == "application/json");
+                match json && body == r#"{"query":"{ health }"}"# {
+                    true => StatusCode::OK,
+                    false => StatusCode::BAD_REQUEST,
+                }
+            }),
+        ))
+        .await;
+        let ctx = CheckContext::default();
+
+        let graphql = http_check(&format!("url: {}/graphql\nmethod: post\nbody: '{{\"query\":\"{{ health }}\"}}'", url));
+        assert_eq!(graphql.check(&ctx).await, State::Success);
+        let get = http_check(&format!("url: {}/graphql", url));
+        assert_eq!(get.check(&ctx).await, State::Failure(unexpected_status(405)));
+        let invalid = http_check(&format!("url: {}/graphql\nmethod: \"GE T\"", url));
+        assert_eq!(invalid.check(&ctx).await, State::failure(FailureCategory::Configuration, "Invalid method: GE T"));
+    }
+
+    #[tokio::test]
+    async fn test_http_request_headers() {
+        use axum::{http::{HeaderMap, StatusCode}, routing::get, Router};
+
+        let url = crate::test_util::serve(Router::new().route(
+            "/internal",
+            get(|headers: HeaderMap| async move {
+                match headers.get("x-api-key").is_some_and(|key| key == "k3y") {
+                    true => StatusCode::OK,
+                    false => StatusCode::UNAUTHORIZED,
+                }
+            }),
+        ))
+        .await;
+        let check = |headers: &str| http_check(&format!("url: {}/internal\nheaders: {}", url, headers));
+        let ctx = CheckContext::default();
+
+        assert_eq!(check("{X-Api-Key: k3y}").check(&ctx).await, State::Success);
+        assert_eq!(check("{X-Api-Key: wrong}").check(&ctx).await, State::Failure(unexpected_status(401)));
+        assert_eq!(
+            check("{\"X Api Key\": k3y}").check(&ctx).await,
+            State::failure(FailureCategory::Configuration, "Invalid header name: X Api Key")
+        );
+        assert_eq!(
+            check("{X-Api-Key: \"line\\nbreak\"}").check(&ctx).await,
+            State::failure(FailureCategory::Configuration, "Invalid value for header x-api-key")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_header_threshold() {
+        use axum::{extract::Query, response::IntoResponse, routing::get, Router};
+
+        // Returns the queue depth given in the query string as a header
+        let url = crate::test_util::serve(Router::new().route(
+            "/queue",
+            get(|Query(query): Query<HashMap<String, String>>| async move {
+                match query.get("depth") {
+                    Some(depth) => ([("x-queue-depth", depth.clone())], "ok").into_response(),
+                    None => "ok".into_response(),
+                }
+            }),
+        ))
+        .await;
+        let check = |query: &str| {
+            http_check(&format!("url: {}/queue?{}\nheader_threshold:\n  header: X-Queue-Depth\n  max: 100", url, query))
+        };
+        let ctx = CheckContext::default();
+
+        assert_eq!(check("depth=42").check(&ctx).await, State::Success);
+        assert_eq!(
+            check("depth=512").check(&ctx).await,
+            State::failure(FailureCategory::Content, "Header X-Queue-Depth is 512, above maximum 100")
+        );
+        assert_eq!(
+            check("depth=full").check(&ctx).await,
+            State::failure(FailureCategory::Content, "Header X-Queue-Depth is not a number: full")
+        );
+        assert_eq!(check("").check(&ctx).await, State::failure(FailureCategory::Content, "Missing header X-Queue-Depth"));
+    }
+
+    #[tokio::test]
+    async fn test_http_body_contains_and_regex() {
+        use axum::{routing::get, Router};
+
+        let url = crate::test_util::serve(
+            Router::new().route("/", get(|| async { "<html>\n  <p>Status: all systems operational</p>\n</html>" })),
+        )
+        .await;
+        let check = |option: &str| http_check(&format!("url: {}/\n{}", url, option));
+        let ctx = CheckContext::default();
+
+        assert_eq!(check("body_contains: operational").check(&ctx).await, State::Success);
+        assert_eq!(
+            check("body_contains: degraded").check(&ctx).await,
+            State::failure(
+                FailureCategory::Content,
+                "Body does not contain \"degraded\": <html> <p>Status: all systems operational</p> </html>"
+            )
+        );
+        assert_eq!(check("body_regex: 'Status: \\w+ systems'").check(&ctx).await, State::Success);
+        assert_eq!(
+            check("body_regex: '^\\d+$'").check(&ctx).await,
+            State::failure(FailureCategory::Content, "Body does not match /^\\d+$/: <html> <p>Status: all systems operational</p> </html>")
+        );
+        match check("body_regex: '(unclosed'").check(&ctx).await {
+            State::Failure(reason) => {
+                assert_eq!(reason.category, FailureCategory::Configuration);
+                assert!(reason.detail.starts_with("Invalid body_regex: "), "{}", reason);
             }
+            state => panic!("unexpected state {:?}", state),
+        }
+    }
 
-            tracing::info!("Starting monitor for service '{}'", service.name);
-            let service_clone = service.clone();
-            let state_clone = self.clone();
-            let id_clone = uuid.clone();
+    #[tokio::test]
+    async fn test_http_cache_headers() {
+        use axum::{extract::Query, routing::get, Router};
 
-            let handle = tokio::spawn(async move {
-                service_clone.run(id_clone, state_clone).await;
-            });
+        // Responds with the headers given in the query string
+        let url = crate::test_util::serve(Router::new().route(
+            "/asset",
+            get(|Query(query): Query<Vec<(String, String)>>| async move {
+                let mut headers = axum::http::HeaderMap::new();
+                for (name, value) in query {
+                    headers.insert(axum::http::HeaderName::from_bytes(name.as_bytes()).unwrap(), value.parse().unwrap());
+                }
+                (headers, "body")
+            }),
+        ))
+        .await;
+        let check = |query: &str| {
+            http_check(&format!(
+                "url: \"{}/asset?{}\"\ncache_headers:\n  min_max_age: 300\n  require_etag: true\n  max_age_header: 3600",
+                url, query
+            ))
+        };
+        let ctx = CheckContext::default();
 
-            handles.insert(uuid.clone(), handle);
+        let compliant = "cache-control=public,%20max-age%3D600&etag=%22v1%22&age=120";
+        assert_eq!(check(compliant).check(&ctx).await, State::Success);
+        // s-maxage governs shared caches and takes precedence over max-age
+        assert_eq!(check("cache-control=max-age%3D60,%20s-maxage%3D900&etag=v1").check(&ctx).await, State::Success);
+
+        let failures = [
+            ("etag=v1", "Missing header Cache-Control"),
+            ("cache-control=no-store&etag=v1", "Cache-Control forbids caching: no-store"),
+            ("cache-control=public&etag=v1", "Cache-Control has no max-age: public"),
+            ("cache-control=max-age%3D60&etag=v1", "Cache-Control max-age=60 is below minimum 300"),
+            ("cache-control=max-age%3D600", "Missing header ETag"),
+            ("cache-control=max-age%3D600&etag=v1&age=7200", "Header Age is 7200, above maximum 3600"),
+        ];
+        for (query, reason) in failures {
+            assert_eq!(check(query).check(&ctx).await, State::failure(FailureCategory::Content, reason), "{}", query);
         }
     }
 
-    pub async fn stop_all_tasks(&self) {
-        tracing::info!("Stopping all monitoring tasks");
-        let mut handles = self.task_handles.write().await;
+    #[tokio::test]
+    async fn test_http_expected_json() {
+        use axum::{routing::get, Router};
 
-        for (id, handle) in handles.drain() {
-            tracing::debug!("Aborting task for service ID: {}", id);
-            handle.abort();
+        let url = crate::test_util::serve(Router::new().route(
+            "/status",
+            get(|| async {
+                axum::Json(serde_json::json!({
+                    "status": "ok",
+                    "generated_at": "2026-01-01T00:00:00Z",
+                    "nodes": [{"id": 7, "role": "primary"}, {"id": 9, "role": "replica"}]
+                }))
+            }),
+        ))
+        .await;
+        let check = |expected: &str, ignored: &str| {
+            http_check(&format!("url: {}/status\nexpected_json: {}\nignore_json_fields: {}", url, expected, ignored))
+        };
+        let ctx = CheckContext::default();
+
+        // Exact match, keys in any order
+        let exact = r#"{"generated_at": "2026-01-01T00:00:00Z", "status": "ok", "nodes": [{"id": 7, "role": "primary"}, {"id": 9, "role": "replica"}]}"#;
+        assert_eq!(check(exact, "[]").check(&ctx).await, State::Success);
+
+        // The first differing path is reported
+        let drifted = r#"{"status": "ok", "generated_at": "2026-01-01T00:00:00Z", "nodes": [{"id": 7, "role": "primary"}, {"id": 9, "role": "primary"}]}"#;
+        assert_eq!(
+            check(drifted, "[]").check(&ctx).await,
+            State::failure(FailureCategory::Content, "JSON differs at $.nodes[1].role: expected \"primary\", got \"replica\"")
+        );
+        let missing = r#"{"status": "ok", "nodes": [{"id": 7, "role": "primary"}, {"id": 9, "role": "replica"}]}"#;
+        assert_eq!(
+            check(missing, "[]").check(&ctx).await,
+            State::failure(FailureCategory::Content, "JSON differs at $.generated_at: unexpected field")
+        );
+
+        // Ignored fields may differ or be absent
+        let ignoring = r#"{"status": "ok", "nodes": [{"id": 1, "role": "primary"}, {"id": 2, "role": "replica"}]}"#;
+        assert_eq!(check(ignoring, r#"["generated_at", "nodes[*].id"]"#).check(&ctx).await, State::Success);
+    }
+
+    #[tokio::test]
+    async fn test_http_body_assertions_see_decompressed_body() {
+        use axum::{http::{header, HeaderMap}, routing::get, Router};
+        use std::io::Write;
+
+        // Compresses the JSON body whatever the client asked for, reporting the Accept-Encoding it got
+        async fn compressed(headers: HeaderMap) -> impl axum::response::IntoResponse {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(br#"{"status":"ok"}"#).unwrap();
+            let accept = headers.get(header::ACCEPT_ENCODING).and_then(|v| v.to_str().ok()).unwrap_or("").to_string();
+            (
+                [
+                    (header::CONTENT_TYPE, "application/json".to_string()),
+                    (header::CONTENT_ENCODING, "gzip".to_string()),
+                    (header::HeaderName::from_static("x-accept-encoding"), accept),
+                ],
+                encoder.finish().unwrap(),
+            )
         }
+        let url = crate::test_util::serve(Router::new().route("/", get(compressed))).await;
+
+        let response = reqwest::get(&url).await.unwrap();
+        assert!(response.headers()["x-accept-encoding"].to_str().unwrap().contains("gzip"));
+        assert_eq!(response.text().await.unwrap(), r#"{"status":"ok"}"#);
+
+        let check = http_check(&format!("url: {}/
+expected_content_type: application/json
+body_format: json", url));
+        assert_eq!(check.check(&CheckContext::default()).await, State::Success);
     }
 
-    pub async fn update_config(&self, new_config: Config) -> anyhow::Result<()> {
-        tracing::info!("Updating configuration and restarting tasks");
+    #[tokio::test]
+    async fn test_http_multiple_urls() {
+        use axum::{http::StatusCode, routing::get, Router};
 
-        // Stop all existing tasks
-        self.stop_all_tasks().await;
+        let url = crate::test_util::serve(
+            Router::new()
+                .route("/ok", get(|| async { "ok" }))
+                .route("/down", get(|| async { StatusCode::SERVICE_UNAVAILABLE })),
+        )
+        .await;
+        let ctx = CheckContext::default();
+        let check = |urls: &[&str], mode: &str| {
+            let urls: Vec<String> = urls.iter().map(|path| format!("  - {}{}", url, path)).collect();
+            http_check(&format!("urls:\n{}\nmode: {}", urls.join("\n"), mode))
+        };
 
-        // Write configuration to file
-        tracing::info!("Writing configuration to {}", self.config_path);
-        let yaml_content = serde_yaml::to_string(&new_config)?;
-        std::fs::write(self.config_path.as_ref(), yaml_content)?;
-        tracing::info!("Configuration file updated successfully");
+        // All pass
+        assert_eq!(check(&["/ok", "/ok"], "all").check(&ctx).await, State::Success);
 
-        // Update the configuration
-        {
-            let mut config = self.config.write().await;
-            *config = new_config.clone();
+        // One of two failing is enough to fail in "all" mode but not in "any" mode
+        assert_eq!(
+            check(&["/ok", "/down"], "all").check(&ctx).await,
+            State::Failure(unexpected_status(503).map_detail(|_| format!("1 of 2 URLs failed\n{0}/ok: OK\n{0}/down: Unexpected status: 503", url)))
+        );
+        assert_eq!(check(&["/down", "/ok"], "any").check(&ctx).await, State::Success);
+
+        // All failing
+        assert_eq!(
+            check(&["/down", "/down"], "any").check(&ctx).await,
+            State::Failure(unexpected_status(503).map_detail(|_| {
+                format!("All 2 URLs failed\n{0}/down: Unexpected status: 503\n{0}/down: Unexpected status: 503", url)
+            }))
+        );
+
+        // The single `url` is checked along with `urls`
+        let combined = http_check(&format!("url: {0}/ok\nurls:\n  - {0}/down\nmode: any", url));
+        assert_eq!(combined.targets(), vec![format!("{}/ok", url), format!("{}/down", url)]);
+        assert_eq!(combined.check(&ctx).await, State::Success);
+    }
+
+    // Collects formatted log output for assertions
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
         }
 
-        // Update service states, preserving existing data where possible
-        {
-            let mut services = self.services.write().await;
-            let now = Utc::now();
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
 
-            // Remove services that no longer exist or are now disabled
-            services.retain(|id, _| {
-                new_config.services.get(id)
-                    .map(|s| s.enabled)
-                    .unwrap_or(false)
-            });
+    #[tokio::test]
+    async fn test_request_id_sent_and_logged() {
+        use axum::{http::HeaderMap, routing::get, Router};
 
-            // Add or update enabled services only
-            for (id, service) in new_config.services.iter().filter(|(_, s)| s.enabled) {
-                services.entry(id.clone()).or_insert_with(|| ServiceState {
-                    name: service.name.clone(),
-                    description: service.description.clone(),
-                    state: State::Unknown,
-                    last_check: now,
-                    consecutive_failures: 0,
-                    total_checks: 0,
-                    successful_checks: 0,
-                    failed_checks: 0,
-                    uptime_start: None,
+        let received: Arc<std::sync::Mutex<Vec<String>>> = Arc::default();
+        let recorded = received.clone();
+        let url = crate::test_util::serve(Router::new().route(
+            "/",
+            get(move |headers: HeaderMap| async move {
+                let id = headers["x-request-id"].to_str().unwrap().to_string();
+                recorded.lock().unwrap().push(id);
+                axum::http::StatusCode::SERVICE_UNAVAILABLE
+            }),
+        ))
+        .await;
+
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let service: Service = serde_yaml::from_str(&format!(
+            "enabled: true\nname: \"Web\"\ndescription: \"\"\ninclude_request_id: true\ncheck: !http\n  url: \"{}\"\n",
+            url
+        ))
+        .unwrap();
+        let state = service.check(&CheckContext::default()).await;
+
+        let request_id = received.lock().unwrap()[0].clone();
+        assert!(uuid::Uuid::parse_str(&request_id).is_ok());
+        assert_eq!(
+            state,
+            State::Failure(unexpected_status(503).map_detail(|detail| format!("{} (request id: {})", detail, request_id)))
+        );
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains(&format!("request_id={}", request_id)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_global_timeout_caps_check_timeouts() {
+        let service: Service = serde_yaml::from_str(
+            r#"
+enabled: true
+name: "Slow DNS"
+description: "Generous per-check timeouts"
+check_timeout_ms: 600000
+check: !tcpPing
+  host: "slow.example"
+  port: 80
+  timeout_ms: 600000
+"#,
+        )
+        .unwrap();
+        let ctx = CheckContext {
+            resolver: Arc::new(SlowResolver),
+            global_timeout_ms: Some(1000),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            service.check(&ctx).await,
+            State::failure(FailureCategory::Timeout, "Check exceeded global timeout of 1000ms")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tcp_banner_check() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Greets with an SSH banner and answers PING with +PONG
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    socket.write_all(b"SSH-2.0-OpenSSH_9.6\r\n").await.unwrap();
+                    let mut buf = [0u8; 64];
+                    if let Ok(read) = socket.read(&mut buf).await {
+                        if buf[..read].starts_with(b"PING") {
+                            socket.write_all(b"+PONG\r\n").await.unwrap();
+                        }
+                    }
                 });
+            }
+        });
+
+        let banner = |yaml: &str| -> ServiceTcpBanner {
+            serde_yaml::from_str(&format!("host: 127.0.0.1\nport: {}\ntimeout_ms: 500\n{}", port, yaml)).unwrap()
+        };
+        let ctx = CheckContext::default();
+
+        assert_eq!(banner("expect: \"SSH-2.0\"").check(&ctx).await, State::Success);
+        assert_eq!(banner("send: \"PING\\r\\n\"\nexpect: [43, 80, 79, 78, 71]").check(&ctx).await, State::Success);
+        assert_eq!(
+            banner("expect: \"220 \"").check(&ctx).await,
+            State::failure(FailureCategory::Content, "Unexpected response: SSH-2.0-OpenSSH_9.6")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_failures_are_coalesced() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let state = app_state(&format!(
+            "{}telegram_api_url: \"{}\"\nnotification_coalesce_ms: 200\n",
+            CONFIG.replace("notify_failures: 3", "notify_failures: 1"),
+            telegram.url()
+        ));
+
+        for id in ["alive", "backup", "stalled"] {
+            state.set_state(id.to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        assert_eq!(
+            telegram.messages(),
+            vec!["🚨 <b>Alert: 3 services failing</b>\n\n<b>Alive</b>: Connection refused\n<b>Backup</b>: Connection refused\n<b>Stalled</b>: Connection refused"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_alerts_fail_over() {
+        let slack = crate::test_util::MockWebhook::start().await;
+        let state = app_state(&format!(
+            "{}telegram_api_url: \"http://127.0.0.1:1\"\nslack_webhook_url: \"{}\"\nnotification_retries: 0\nnotification_coalesce_ms: 100\nnotification_failover: [telegram, slack]\n",
+            CONFIG.replace("notify_failures: 3", "notify_failures: 1"),
+            slack.url()
+        ));
+
+        // The combined Telegram message fails, so every alert in it goes to Slack
+        for id in ["alive", "backup"] {
+            state.set_state(id.to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        }
+        state.flush_notifications().await;
+        let mut titles: Vec<String> = slack.bodies().iter().map(|body| body["text"].as_str().unwrap().to_string()).collect();
+        titles.sort();
+        assert_eq!(titles, ["Alert: Alive", "Alert: Backup"]);
+    }
+
+    #[tokio::test]
+    async fn test_single_run_alerts_first_failure() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let yaml = format!(
+            "{}telegram_api_url: \"{}\"\n",
+            CONFIG.replace("name: \"Alive\"", "name: \"Alive\"\n    debounce_checks: 2\n    initial_grace_ms: 60000"),
+            telegram.url()
+        );
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+
+        // Normally a single failure stays below notify_failures: 3
+        for config in [config.clone(), config.for_single_run()] {
+            let state = AppState::new(config, vec!["unused.yaml".to_string()]);
+            state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+            state.set_state("backup".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+            state.flush_notifications().await;
+        }
+        let messages = telegram.messages();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages.iter().any(|m| m.contains("Alert: Alive")));
+        assert!(messages.iter().any(|m| m.contains("Alert: Backup")));
+    }
+
+    #[tokio::test]
+    async fn test_first_check_notified_once() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let state = app_state(&format!("{}telegram_api_url: \"{}\"\nnotify_first_check: true\n", CONFIG, telegram.url()));
 
-                // Update name and description for existing services
-                if let Some(service_state) = services.get_mut(id) {
-                    service_state.name = service.name.clone();
-                    service_state.description = service.description.clone();
+        for _ in 0..3 {
+            state.set_state("alive".to_string(), State::Success).await;
+        }
+        state.flush_notifications().await;
+        let messages = telegram.messages();
+        assert_eq!(messages.len(), 1);
+        // Nothing recovered, so it's neither a recovery nor marked with its emoji
+        assert_eq!(messages[0], "<b>Info: Alive</b>\n\nFirst check succeeded, monitoring is active");
+
+        // Below notify_failures, only the first failure is announced
+        state.set_state("backup".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        state.set_state("backup".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        state.flush_notifications().await;
+        let messages = telegram.messages();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[1].contains("First check failed: Connection refused"), "{}", messages[1]);
+    }
+
+    #[tokio::test]
+    async fn test_counters_restored_from_database() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let yaml = format!(
+            "{}telegram_api_url: \"{}\"\ndatabase_path: \"{}\"\n",
+            CONFIG.replace("port: 2\n", &format!("port: {}\n", port)),
+            telegram.url(),
+            path.display()
+        );
+        // Results are written in the background
+        let recorded = |count: u64| {
+            let path = path.clone();
+            async move {
+                for _ in 0..100 {
+                    let counters = StateStore::open(path.to_str().unwrap(), 30).unwrap().counters().unwrap();
+                    if counters.get("backup").is_some_and(|c| c.total_checks == count) {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
                 }
+                panic!("{} results weren't recorded", count);
             }
+        };
+
+        let state = app_state(&yaml);
+        state.run_once().await;
+        state.run_once().await;
+        recorded(2).await;
+
+        let state = app_state(&yaml);
+        let backup = state.get_service("backup").await.unwrap();
+        assert_eq!((backup.total_checks, backup.successful_checks, backup.failed_checks), (2, 2, 0));
+        assert!(backup.uptime_start.is_some());
+        let alive = state.get_service("alive").await.unwrap();
+        assert_eq!((alive.total_checks, alive.failed_checks), (2, 2));
+        assert_eq!(alive.uptime_start, None);
+    }
+
+    #[tokio::test]
+    async fn test_database_write_failure_alerted_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let slack = crate::test_util::MockWebhook::start().await;
+        let state = app_state(&format!(
+            "{}telegram_api_url: \"{}\"\nslack_webhook_url: \"{}\"\ndatabase_path: \"{}\"\n",
+            CONFIG,
+            telegram.url(),
+            slack.url(),
+            path.display()
+        ));
+        let store = state.store.clone().unwrap();
+        let write = || {
+            let result = CheckResult {
+                service: "Alive".to_string(),
+                state: State::Success,
+                latency_ms: 1,
+                timestamp: Utc::now(),
+            };
+            state.write_result(store.clone(), "alive".to_string(), result, false)
+        };
+
+        // Writes fail once the table is gone, the warning is sent for the first failure only
+        rusqlite::Connection::open(&path).unwrap().execute("DROP TABLE check_results", []).unwrap();
+        for _ in 0..3 {
+            write().await;
         }
+        let messages = telegram.messages();
+        assert_eq!(messages.len(), 1, "{:?}", messages);
+        assert!(messages[0].contains("Check results can't be written to"), "{}", messages[0]);
+        // Like service notifications, it goes to every configured channel
+        assert_eq!(slack.bodies().len(), 1);
+        assert_eq!(slack.bodies()[0]["text"], "Warning: healthcheck");
 
-        // Start new tasks
-        self.start_monitoring_tasks().await;
+        // Still failing once the interval has passed
+        *state.store_alerted_at.lock().unwrap() = Some(Utc::now() - chrono::Duration::hours(2));
+        write().await;
+        write().await;
+        assert_eq!(telegram.messages().len(), 2);
+    }
 
-        tracing::info!("Configuration updated and tasks restarted");
-        Ok(())
+    #[tokio::test]
+    async fn test_stale_alert_dropped_after_recovery() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let state = app_state(&format!(
+            "{}telegram_api_url: \"{}\"\nnotification_coalesce_ms: 200\norder_notifications: true\n",
+            CONFIG.replace("notify_failures: 3", "notify_failures: 1"),
+            telegram.url()
+        ));
+
+        // The service recovers while its alert waits to be coalesced
+        state.set_state("alive".to_string(), State::failure(FailureCategory::Connection, "Connection refused")).await;
+        state.set_state("alive".to_string(), State::Success).await;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let messages = telegram.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("Recovery"), "{}", messages[0]);
+    }
+
+    #[tokio::test]
+    async fn test_many_coalesced_alerts_split_at_message_limit() {
+        let services: String = (0..50)
+            .map(|i| format!("  s{i}:\n    enabled: true\n    name: \"Service {i}\"\n    description: \"\"\n    check: !tcpPing\n      host: \"127.0.0.1\"\n      port: 1\n"))
+            .collect();
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let state = app_state(&format!(
+            "telegram_token: \"token\"\ntelegram_chat_id: 1\ncheck_interval_success: 1000\ncheck_interval_fail: 500\nnotify_failures: 1\nrereport: 10\ntelegram_api_url: \"{}\"\nnotification_coalesce_ms: 200\nservices:\n{}",
+            telegram.url(),
+            services
+        ));
+
+        let reason = format!("Connection refused {}", "x".repeat(180));
+        for i in 0..50 {
+            state.set_state(format!("s{}", i), State::failure(FailureCategory::Connection, reason.clone())).await;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        // About 10000 characters of alerts fit in three messages
+        let messages = telegram.messages();
+        assert_eq!(messages.len(), 3);
+        for (index, message) in messages.iter().enumerate() {
+            assert!(message.encode_utf16().count() <= telegram::MAX_MESSAGE_LENGTH);
+            assert!(message.starts_with(&format!("🚨 <b>Alert: 50 services failing ({}/3)</b>\n\n", index + 1)));
+        }
+        // Every alert is sent exactly once
+        let all = messages.concat();
+        for i in 0..50 {
+            assert_eq!(all.matches(&format!("<b>Service {}</b>: {}", i, reason)).count(), 1);
+        }
     }
 }
@@ -0,0 +1,46 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+// DeadLetter is a notification that could not be delivered after all retries
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub timestamp: DateTime<Utc>,
+    pub channel: String,
+    pub service: String,
+    // Kind of notification: alert, recovery or warning
+    pub state: String,
+    pub message: String,
+    pub error: String,
+}
+
+// Log the dropped notification and append it as a JSON line to `path` when configured
+pub async fn record(path: Option<&str>, letter: &DeadLetter) {
+    tracing::error!(
+        "Dropping {} notification for '{}' via {}: {}",
+        letter.state,
+        letter.service,
+        letter.channel,
+        letter.error
+    );
+
+    let Some(path) = path else {
+        return;
+    };
+    if let Err(e) = append(path, letter).await {
+        tracing::error!("Failed to write dead letter to {}: {}", path, e);
+    }
+}
+
+async fn append(path: &str, letter: &DeadLetter) -> anyhow::Result<()> {
+    let mut line = serde_json::to_string(letter)?;
+    line.push('\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(line.as_bytes()).await?;
+    file.flush().await?;
+    Ok(())
+}
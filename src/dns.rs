@@ -0,0 +1,105 @@
+use std::net::{IpAddr, SocketAddr};
+
+use hickory_resolver::config::{ConnectionConfig, NameServerConfig, ResolverConfig};
+use hickory_resolver::net::runtime::TokioRuntimeProvider;
+use hickory_resolver::proto::rr::{RData, RecordType};
+use hickory_resolver::TokioResolver;
+
+// Record types supported by DNS checks
+pub const RECORD_TYPES: [&str; 5] = ["A", "AAAA", "CNAME", "MX", "TXT"];
+
+// Resolver using the system DNS configuration (/etc/resolv.conf)
+pub fn system_resolver() -> anyhow::Result<TokioResolver> {
+    Ok(TokioResolver::builder_tokio()?.build()?)
+}
+
+// Resolver querying a single nameserver over UDP, given as "ip" or "ip:port"
+pub fn nameserver_resolver(nameserver: &str) -> anyhow::Result<TokioResolver> {
+    let address = match nameserver.parse::<SocketAddr>() {
+        Ok(address) => address,
+        Err(_) => SocketAddr::new(
+            nameserver
+                .parse::<IpAddr>()
+                .map_err(|_| anyhow::anyhow!("Invalid nameserver '{}', expected an IP address", nameserver))?,
+            53,
+        ),
+    };
+    let mut connection = ConnectionConfig::udp();
+    connection.port = address.port();
+    let config = ResolverConfig::from_parts(
+        None,
+        vec![],
+        vec![NameServerConfig::new(address.ip(), true, vec![connection])],
+    );
+    Ok(TokioResolver::builder_with_config(config, TokioRuntimeProvider::default()).build()?)
+}
+
+// Values of the `record_type` records of `hostname` in presentation form: addresses, names
+// without the trailing dot, "preference exchange" for MX and TXT strings with their chunks
+// joined (long TXT records are split into chunks of up to 255 bytes). Empty when the name
+// doesn't exist or has no such records.
+pub async fn lookup_values(resolver: &TokioResolver, hostname: &str, record_type: &str) -> anyhow::Result<Vec<String>> {
+    let record_type: RecordType = record_type
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Unknown record type '{}'", record_type))?;
+    let lookup = match resolver.lookup(hostname, record_type).await {
+        Ok(lookup) => lookup,
+        Err(e) if e.is_no_records_found() => return Ok(Vec::new()),
+        Err(e) => anyhow::bail!("{} lookup for {} failed: {}", record_type, hostname, e),
+    };
+
+    let name = |name: &hickory_resolver::proto::rr::Name| name.to_ascii().trim_end_matches('.').to_string();
+    Ok(lookup
+        .answers()
+        .iter()
+        // Answers for A/AAAA lookups also include the CNAME chain leading to them
+        .filter_map(|record| match &record.data {
+            RData::A(a) if record_type == RecordType::A => Some(a.0.to_string()),
+            RData::AAAA(aaaa) if record_type == RecordType::AAAA => Some(aaaa.0.to_string()),
+            RData::CNAME(cname) if record_type == RecordType::CNAME => Some(name(&cname.0)),
+            RData::MX(mx) if record_type == RecordType::MX => Some(format!("{} {}", mx.preference, name(&mx.exchange))),
+            RData::TXT(txt) if record_type == RecordType::TXT => {
+                Some(txt.txt_data.iter().map(|chunk| String::from_utf8_lossy(chunk)).collect())
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+// Issuers allowed by the relevant CAA record set of `host` as (domain it was found at, issuers).
+// Per RFC 8659 the closest ancestor with CAA records applies; None when no domain has any.
+pub async fn caa_issuers(resolver: &TokioResolver, host: &str) -> anyhow::Result<Option<(String, Vec<String>)>> {
+    let host = host.trim_end_matches('.');
+    let labels: Vec<&str> = host.split('.').collect();
+
+    // Climb towards the top-level domain, which is never queried
+    for start in 0..labels.len().saturating_sub(1) {
+        let domain = labels[start..].join(".");
+        let lookup = match resolver.lookup(format!("{}.", domain), RecordType::CAA).await {
+            Ok(lookup) => lookup,
+            Err(e) if e.is_no_records_found() => continue,
+            Err(e) => anyhow::bail!("CAA lookup for {} failed: {}", domain, e),
+        };
+
+        let records: Vec<_> = lookup
+            .answers()
+            .iter()
+            .filter_map(|record| match &record.data {
+                RData::CAA(caa) => Some(caa),
+                _ => None,
+            })
+            .collect();
+        if records.is_empty() {
+            continue;
+        }
+
+        let issuers = records
+            .iter()
+            .filter(|caa| caa.tag.eq_ignore_ascii_case("issue"))
+            .filter_map(|caa| caa.value_as_issue().ok())
+            .filter_map(|(name, _)| name.map(|name| name.to_ascii().trim_end_matches('.').to_lowercase()))
+            .collect();
+        return Ok(Some((domain, issuers)));
+    }
+    Ok(None)
+}
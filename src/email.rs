@@ -0,0 +1,164 @@
+// Email notifications over SMTP, for environments without chat integrations
+use std::time::Duration;
+
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+
+pub const DEFAULT_SMTP_PORT: u16 = 587;
+// Sender used when neither `email_from` nor an address-like `smtp_username` is set
+const DEFAULT_FROM: &str = "healthcheck@localhost";
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct EmailClient {
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+    from: String,
+    to: Vec<String>,
+    environment: Option<String>,
+}
+
+// Clickable link to a service's runbook, appended to alerts
+pub fn runbook_link(url: &str) -> String {
+    format!("Runbook: {}", url)
+}
+
+impl EmailClient {
+    pub fn new(host: String, port: u16, to: Vec<String>) -> Self {
+        Self {
+            host,
+            port,
+            credentials: None,
+            from: DEFAULT_FROM.to_string(),
+            to,
+            environment: None,
+        }
+    }
+
+    // Authenticate with the server; an address-like username is also the default sender
+    pub fn with_credentials(mut self, username: String, password: String) -> Self {
+        if username.contains('@') {
+            self.from = username.clone();
+        }
+        self.credentials = Some((username, password));
+        self
+    }
+
+    pub fn with_from(mut self, from: Option<String>) -> Self {
+        if let Some(from) = from {
+            self.from = from;
+        }
+        self
+    }
+
+    // Prefix every service name with the environment, e.g. "[staging] API"
+    pub fn with_environment(mut self, environment: Option<String>) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    fn service_label(&self, service_name: &str) -> String {
+        match &self.environment {
+            Some(environment) => format!("[{}] {}", environment, service_name),
+            None => service_name.to_string(),
+        }
+    }
+
+    // Check the addresses, so mistakes show when the configuration is loaded
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.to.is_empty() {
+            anyhow::bail!("email_to must list at least one address");
+        }
+        for address in std::iter::once(&self.from).chain(&self.to) {
+            address
+                .parse::<Mailbox>()
+                .map_err(|e| anyhow::anyhow!("Invalid email address '{}': {}", address, e))?;
+        }
+        Ok(())
+    }
+
+    // Implicit TLS on 465, STARTTLS required on 587 and used when offered on other ports
+    fn transport(&self) -> anyhow::Result<AsyncSmtpTransport<Tokio1Executor>> {
+        let tls = match self.port {
+            465 => Tls::Wrapper(TlsParameters::new(self.host.clone())?),
+            587 => Tls::Required(TlsParameters::new(self.host.clone())?),
+            _ => Tls::Opportunistic(TlsParameters::new(self.host.clone())?),
+        };
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.host)
+            .port(self.port)
+            .tls(tls)
+            .timeout(Some(SMTP_TIMEOUT));
+        if let Some((username, password)) = &self.credentials {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(builder.build())
+    }
+
+    async fn send(&self, subject: &str, body: &str) -> anyhow::Result<()> {
+        let mut message = Message::builder().from(self.from.parse()?).subject(subject);
+        for to in &self.to {
+            message = message.to(to.parse()?);
+        }
+        let message = message.body(body.to_string())?;
+
+        tracing::debug!("Sending email '{}' to {}", subject, self.to.join(", "));
+        match self.transport()?.send(message).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::error!("Failed to send email via {}:{}: {}", self.host, self.port, e);
+                Err(anyhow::anyhow!("SMTP error: {}", e))
+            }
+        }
+    }
+
+    pub async fn send_alert(&self, service_name: &str, message: &str, footer: Option<&str>) -> anyhow::Result<()> {
+        let body = match footer {
+            Some(footer) => format!("{}\n\n{}", message, footer),
+            None => message.to_string(),
+        };
+        self.send(&format!("Alert: {}", self.service_label(service_name)), &body).await
+    }
+
+    pub async fn send_warning(&self, service_name: &str, message: &str) -> anyhow::Result<()> {
+        self.send(&format!("Warning: {}", self.service_label(service_name)), message).await
+    }
+
+    pub async fn send_recovery(&self, service_name: &str, message: &str) -> anyhow::Result<()> {
+        self.send(&format!("Recovery: {}", self.service_label(service_name)), message).await
+    }
+
+    pub async fn send_info(&self, service_name: &str, message: &str) -> anyhow::Result<()> {
+        self.send(&format!("Info: {}", self.service_label(service_name)), message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_alert_email() {
+        let server = crate::test_util::MockSmtp::start().await;
+        let client = EmailClient::new("127.0.0.1".to_string(), server.port(), vec!["ops@example.com".to_string()])
+            .with_credentials("monitor@example.com".to_string(), "secret".to_string())
+            .with_environment(Some("prod".to_string()));
+        client.validate().unwrap();
+
+        client
+            .send_alert("API", "Unexpected status: 502", Some(&runbook_link("https://wiki.example.com/api")))
+            .await
+            .unwrap();
+
+        let messages = server.messages();
+        assert_eq!(messages.len(), 1);
+        let (from, to, data) = &messages[0];
+        assert_eq!(from, "monitor@example.com");
+        assert_eq!(to, &vec!["ops@example.com".to_string()]);
+        assert!(data.contains("Subject: Alert: [prod] API"));
+        assert!(data.contains("Unexpected status: 502\r\n\r\nRunbook: https://wiki.example.com/api"));
+        assert!(EmailClient::new("smtp".to_string(), 25, vec!["not an address".to_string()]).validate().is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::config::{State, Status};
+
+// Events kept for clients reconnecting with Last-Event-ID
+pub const REPLAY_BUFFER: usize = 256;
+
+// CheckEvent is a recorded check result, streamed by /api/events
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CheckEvent {
+    // Monotonic, sent as the SSE event id
+    pub id: u64,
+    pub service_id: String,
+    pub name: String,
+    pub status: Status,
+    pub state: State,
+    pub timestamp: DateTime<Utc>,
+}
+
+// EventLog assigns event ids, broadcasts events and keeps the most recent ones for replay
+pub struct EventLog {
+    inner: std::sync::Mutex<Inner>,
+    sender: broadcast::Sender<CheckEvent>,
+}
+
+struct Inner {
+    next_id: u64,
+    buffer: VecDeque<CheckEvent>,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        // Ids start from the startup time in microseconds, so they keep increasing across restarts
+        let next_id = Utc::now().timestamp_micros().max(1) as u64;
+        Self {
+            inner: std::sync::Mutex::new(Inner { next_id, buffer: VecDeque::new() }),
+            sender: broadcast::channel(REPLAY_BUFFER).0,
+        }
+    }
+}
+
+impl EventLog {
+    pub fn publish(&self, service_id: &str, name: &str, state: &State, timestamp: DateTime<Utc>) {
+        let mut inner = self.inner.lock().unwrap();
+        let event = CheckEvent {
+            id: inner.next_id,
+            service_id: service_id.to_string(),
+            name: name.to_string(),
+            status: Status::from(state),
+            state: state.clone(),
+            timestamp,
+        };
+        inner.next_id += 1;
+        if inner.buffer.len() == REPLAY_BUFFER {
+            inner.buffer.pop_front();
+        }
+        inner.buffer.push_back(event.clone());
+        // Sent under the lock so subscribers see the same order as the buffer
+        let _ = self.sender.send(event);
+    }
+
+    // Subscribe to new events; with `last_event_id` the buffered events after it are returned
+    // first. Events older than the buffer are lost. An id this log hasn't assigned yet was seen
+    // before the clock went back across a restart, so every buffered event is returned.
+    pub fn subscribe(&self, last_event_id: Option<u64>) -> (Vec<CheckEvent>, broadcast::Receiver<CheckEvent>) {
+        let inner = self.inner.lock().unwrap();
+        let missed = match last_event_id {
+            Some(last) if last >= inner.next_id => inner.buffer.iter().cloned().collect(),
+            Some(last) => inner.buffer.iter().filter(|e| e.id > last).cloned().collect(),
+            None => Vec::new(),
+        };
+        (missed, self.sender.subscribe())
+    }
+}
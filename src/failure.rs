@@ -0,0 +1,89 @@
+// Structured reasons of failed checks, so API clients can tell kinds of failures apart
+use serde::{Deserialize, Serialize};
+
+// FailureCategory is the kind of problem a failed check ran into
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCategory {
+    // The check or a step of it took too long
+    Timeout,
+    Dns,
+    // The target couldn't be reached or the request couldn't be completed
+    Connection,
+    Tls,
+    // The certificate expired, is about to or failed its checks
+    Certificate,
+    // The target answered with an unexpected HTTP or gRPC status
+    Status,
+    // The response didn't meet the check's expectations
+    Content,
+    // An external command failed
+    Command,
+    // The check's settings can't be used, e.g. an invalid regex
+    Configuration,
+    // The check itself panicked
+    Internal,
+}
+
+// FailureReason is a failure message with its category and the code it reported, if any
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Hash)]
+pub struct FailureReason {
+    pub category: FailureCategory,
+    // Human readable message, the same as in the failure state
+    pub detail: String,
+    // HTTP or gRPC status, or the exit status of a command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<i64>,
+}
+
+impl FailureReason {
+    pub fn new(category: FailureCategory, detail: impl Into<String>) -> Self {
+        Self {
+            category,
+            detail: detail.into(),
+            code: None,
+        }
+    }
+
+    pub fn with_code(mut self, code: impl Into<i64>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    // The same failure with a different message, e.g. prefixed with the step it happened in
+    pub fn map_detail(mut self, f: impl FnOnce(String) -> String) -> Self {
+        self.detail = f(self.detail);
+        self
+    }
+}
+
+impl std::fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.detail)
+    }
+}
+
+// Serde helper writing a failure as its message, so failure states keep their plain form in the API.
+// A bare message has no known category and reads back as internal.
+pub mod detail {
+    use super::{FailureCategory, FailureReason};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Failure {
+        Detail(String),
+        Reason(FailureReason),
+    }
+
+    pub fn serialize<S: Serializer>(reason: &FailureReason, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&reason.detail)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FailureReason, D::Error> {
+        Ok(match Failure::deserialize(deserializer)? {
+            Failure::Detail(detail) => FailureReason::new(FailureCategory::Internal, detail),
+            Failure::Reason(reason) => reason,
+        })
+    }
+}
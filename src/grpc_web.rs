@@ -0,0 +1,164 @@
+// Minimal gRPC-Web framing for the standard grpc.health.v1 unary health call
+
+pub const HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+pub const CONTENT_TYPE: &str = "application/grpc-web+proto";
+
+// Flag marking a frame that carries trailers instead of a message
+const TRAILER_FLAG: u8 = 0x80;
+
+// Serving status from grpc.health.v1.HealthCheckResponse
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServingStatus {
+    Unknown,
+    Serving,
+    NotServing,
+    ServiceUnknown,
+}
+
+impl ServingStatus {
+    fn from_value(value: u64) -> Self {
+        match value {
+            1 => ServingStatus::Serving,
+            2 => ServingStatus::NotServing,
+            3 => ServingStatus::ServiceUnknown,
+            _ => ServingStatus::Unknown,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ServingStatus::Unknown => "UNKNOWN",
+            ServingStatus::Serving => "SERVING",
+            ServingStatus::NotServing => "NOT_SERVING",
+            ServingStatus::ServiceUnknown => "SERVICE_UNKNOWN",
+        }
+    }
+}
+
+// Decoded unary response: the message's serving status and the trailers
+#[derive(Debug, Default, PartialEq)]
+pub struct HealthResponse {
+    pub status: Option<ServingStatus>,
+    pub grpc_status: Option<u32>,
+    pub grpc_message: Option<String>,
+}
+
+fn encode_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn decode_varint(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+// Framed HealthCheckRequest { string service = 1; }
+pub fn encode_request(service: &str) -> Vec<u8> {
+    let mut message = Vec::new();
+    if !service.is_empty() {
+        message.push(0x0a);
+        encode_varint(service.len() as u64, &mut message);
+        message.extend_from_slice(service.as_bytes());
+    }
+
+    let mut frame = vec![0x00];
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    frame
+}
+
+// HealthCheckResponse { ServingStatus status = 1; }, other fields are skipped
+fn decode_status(message: &[u8]) -> Result<ServingStatus, String> {
+    let mut pos = 0;
+    let mut status = ServingStatus::Unknown;
+    while pos < message.len() {
+        let invalid = || "Invalid health check response message".to_string();
+        let tag = decode_varint(message, &mut pos).ok_or_else(invalid)?;
+        match tag & 0x07 {
+            0 => {
+                let value = decode_varint(message, &mut pos).ok_or_else(invalid)?;
+                if tag >> 3 == 1 {
+                    status = ServingStatus::from_value(value);
+                }
+            }
+            2 => {
+                let len = decode_varint(message, &mut pos).ok_or_else(invalid)? as usize;
+                pos = pos.checked_add(len).filter(|&end| end <= message.len()).ok_or_else(invalid)?;
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(status)
+}
+
+// Split a response body into its message and trailer frames
+pub fn decode_response(body: &[u8]) -> Result<HealthResponse, String> {
+    let mut response = HealthResponse::default();
+    let mut rest = body;
+    while !rest.is_empty() {
+        if rest.len() < 5 {
+            return Err("Truncated gRPC-Web frame".to_string());
+        }
+        let flag = rest[0];
+        let len = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+        let payload = rest
+            .get(5..5 + len)
+            .ok_or_else(|| "Truncated gRPC-Web frame".to_string())?;
+        rest = &rest[5 + len..];
+
+        if flag & TRAILER_FLAG != 0 {
+            for line in String::from_utf8_lossy(payload).split("\r\n") {
+                apply_trailer(&mut response, line);
+            }
+        } else {
+            response.status = Some(decode_status(payload)?);
+        }
+    }
+    Ok(response)
+}
+
+// Record a "name: value" trailer, also used for trailers sent as HTTP headers
+pub fn apply_trailer(response: &mut HealthResponse, line: &str) {
+    let Some((name, value)) = line.split_once(':') else {
+        return;
+    };
+    match name.trim().to_ascii_lowercase().as_str() {
+        "grpc-status" => response.grpc_status = value.trim().parse().ok(),
+        "grpc-message" => response.grpc_message = Some(value.trim().to_string()),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_and_response_framing() {
+        assert_eq!(encode_request(""), vec![0, 0, 0, 0, 0]);
+        assert_eq!(encode_request("db"), vec![0, 0, 0, 0, 4, 0x0a, 2, b'd', b'b']);
+
+        let mut body = vec![0, 0, 0, 0, 2, 0x08, 0x02];
+        let trailers = b"grpc-status: 0\r\ngrpc-message: \r\n";
+        body.push(TRAILER_FLAG);
+        body.extend_from_slice(&(trailers.len() as u32).to_be_bytes());
+        body.extend_from_slice(trailers);
+
+        let response = decode_response(&body).unwrap();
+        assert_eq!(response.status, Some(ServingStatus::NotServing));
+        assert_eq!(response.grpc_status, Some(0));
+        assert!(decode_response(&body[..3]).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{State, Status};
+
+// Maximum number of incidents kept per service
+pub const MAX_INCIDENTS: usize = 1000;
+
+// Incident is a continuous period during which a service was failing
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Incident {
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub reason: String,
+    // Outcome of the service's on_failure_command, with its output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remediation: Option<String>,
+}
+
+// CheckRecord is a single recorded check result, listed by /api/services/:id/history
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct CheckRecord {
+    pub timestamp: DateTime<Utc>,
+    pub status: Status,
+    pub state: State,
+}
+
+// MaintenanceWindow is planned downtime, left out of uptime reports
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Hash)]
+pub struct MaintenanceWindow {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+// UptimeReport summarizes the availability of a service over a date range
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct UptimeReport {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub monitored_seconds: i64,
+    pub downtime_seconds: i64,
+    // Time in maintenance windows, excluded from the other figures
+    pub maintenance_seconds: i64,
+    // None when the service wasn't monitored at all during the range
+    pub uptime_percentage: Option<f64>,
+    pub incident_count: usize,
+}
+
+// Sorted, non-overlapping maintenance periods
+fn merge_windows(maintenance: &[MaintenanceWindow]) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut windows: Vec<_> = maintenance.iter().map(|w| (w.start, w.end)).collect();
+    windows.sort();
+    let mut merged: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+    for (start, end) in windows {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+// Length of [start, end] outside of the maintenance windows
+fn outside_maintenance(
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    windows: &[(DateTime<Utc>, DateTime<Utc>)],
+) -> Duration {
+    let mut length = (end - start).max(Duration::zero());
+    for &(window_start, window_end) in windows {
+        let overlap = window_end.min(end) - window_start.max(start);
+        if overlap > Duration::zero() {
+            length -= overlap;
+        }
+    }
+    length
+}
+
+// Compute the uptime for [from, to], only counting time during which the service was monitored
+// and not in maintenance
+pub fn uptime_report(
+    incidents: &[Incident],
+    maintenance: &[MaintenanceWindow],
+    monitored_since: DateTime<Utc>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> UptimeReport {
+    let start = from.max(monitored_since);
+    let end = to.min(now);
+    let windows = merge_windows(maintenance);
+    let monitored = outside_maintenance(start, end, &windows);
+    let maintenance_time = (end - start).max(Duration::zero()) - monitored;
+
+    let mut downtime = Duration::zero();
+    let mut incident_count = 0;
+    for incident in incidents {
+        let overlap_start = incident.started_at.max(start);
+        let overlap_end = incident.ended_at.unwrap_or(now).min(end);
+        let incident_downtime = outside_maintenance(overlap_start, overlap_end, &windows);
+        if incident_downtime > Duration::zero() {
+            downtime += incident_downtime;
+            incident_count += 1;
+        }
+    }
+
+    let uptime_percentage = if monitored > Duration::zero() {
+        let monitored_ms = monitored.num_milliseconds() as f64;
+        Some((monitored_ms - downtime.num_milliseconds() as f64) / monitored_ms * 100.0)
+    } else {
+        None
+    };
+
+    UptimeReport {
+        from,
+        to,
+        monitored_seconds: monitored.num_seconds(),
+        downtime_seconds: downtime.num_seconds(),
+        maintenance_seconds: maintenance_time.num_seconds(),
+        uptime_percentage,
+        incident_count,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(&format!("2026-01-01T{:02}:00:00Z", hour))
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_uptime_report_for_range() {
+        let incidents = vec![
+            // Partly before the range, only one hour counts
+            Incident { started_at: at(1), ended_at: Some(at(3)), reason: "down".to_string(), remediation: None },
+            Incident { started_at: at(6), ended_at: Some(at(7)), reason: "down".to_string(), remediation: None },
+            // After the range
+            Incident { started_at: at(20), ended_at: None, reason: "down".to_string(), remediation: None },
+        ];
+
+        let report = uptime_report(&incidents, &[], at(0), at(2), at(12), at(22));
+        assert_eq!(report.monitored_seconds, 10 * 3600);
+        assert_eq!(report.downtime_seconds, 2 * 3600);
+        assert_eq!(report.incident_count, 2);
+        assert_eq!(report.uptime_percentage, Some(80.0));
+
+        // Ongoing incident is counted up to now
+        let report = uptime_report(&incidents, &[], at(0), at(18), at(23), at(22));
+        assert_eq!(report.downtime_seconds, 2 * 3600);
+        assert_eq!(report.uptime_percentage, Some(50.0));
+
+        // Range before monitoring started has no data
+        let report = uptime_report(&incidents, &[], at(10), at(2), at(5), at(22));
+        assert_eq!(report.monitored_seconds, 0);
+        assert_eq!(report.uptime_percentage, None);
+        assert_eq!(report.incident_count, 0);
+    }
+
+    #[test]
+    fn test_uptime_excludes_maintenance() {
+        let incidents = vec![
+            // Planned downtime during maintenance
+            Incident { started_at: at(2), ended_at: Some(at(4)), reason: "down".to_string(), remediation: None },
+            // Overruns the maintenance window by an hour
+            Incident { started_at: at(9), ended_at: Some(at(11)), reason: "down".to_string(), remediation: None },
+        ];
+        let maintenance = vec![
+            MaintenanceWindow { start: at(1), end: at(5) },
+            MaintenanceWindow { start: at(8), end: at(10) },
+            // Overlaps the first window, counted once
+            MaintenanceWindow { start: at(4), end: at(6) },
+        ];
+
+        let report = uptime_report(&incidents, &maintenance, at(0), at(0), at(20), at(22));
+        assert_eq!(report.maintenance_seconds, 7 * 3600);
+        assert_eq!(report.monitored_seconds, 13 * 3600);
+        assert_eq!(report.downtime_seconds, 3600);
+        assert_eq!(report.incident_count, 1);
+        assert_eq!(report.uptime_percentage, Some(1200.0 / 13.0));
+    }
+}
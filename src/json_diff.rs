@@ -0,0 +1,113 @@
+// Deep comparison of JSON documents, reporting where they first differ
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Longest value quoted in a difference, longer ones are cut
+const MAX_VALUE_LENGTH: usize = 80;
+
+// JsonDocument is an expected JSON document in the configuration.
+// serde_json::Value has no Hash, documents are hashed by their serialization.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct JsonDocument(pub Value);
+
+impl std::hash::Hash for JsonDocument {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_string().hash(state);
+    }
+}
+
+// First difference of `actual` from `expected`, e.g. "$.items[1].name: expected \"a\", got \"b\"".
+// Paths in `ignored` ("meta.generated_at", "items[*].id") are skipped on both sides.
+pub fn first_difference(actual: &Value, expected: &Value, ignored: &[String]) -> Option<String> {
+    let ignored: Vec<&str> = ignored
+        .iter()
+        .map(|path| path.trim_start_matches('$').trim_start_matches('.'))
+        .collect();
+    difference(actual, expected, &ignored, &Path::default())
+}
+
+// Path of a value both with concrete array indexes and with [*] wildcards
+#[derive(Default)]
+struct Path {
+    concrete: String,
+    wildcard: String,
+}
+
+impl Path {
+    fn key(&self, key: &str) -> Path {
+        let join = |path: &str| if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+        Path { concrete: join(&self.concrete), wildcard: join(&self.wildcard) }
+    }
+
+    fn index(&self, index: usize) -> Path {
+        Path {
+            concrete: format!("{}[{}]", self.concrete, index),
+            wildcard: format!("{}[*]", self.wildcard),
+        }
+    }
+
+    fn is_ignored(&self, ignored: &[&str]) -> bool {
+        ignored.iter().any(|path| *path == self.concrete || *path == self.wildcard)
+    }
+
+    fn display(&self) -> String {
+        match self.concrete.starts_with('[') || self.concrete.is_empty() {
+            true => format!("${}", self.concrete),
+            false => format!("$.{}", self.concrete),
+        }
+    }
+}
+
+fn difference(actual: &Value, expected: &Value, ignored: &[&str], path: &Path) -> Option<String> {
+    if path.is_ignored(ignored) {
+        return None;
+    }
+    match (actual, expected) {
+        (Value::Object(actual), Value::Object(expected)) => {
+            for (key, expected) in expected {
+                let child = path.key(key);
+                let difference = match actual.get(key) {
+                    Some(actual) => difference(actual, expected, ignored, &child),
+                    None if child.is_ignored(ignored) => None,
+                    None => Some(format!("{}: missing", child.display())),
+                };
+                if difference.is_some() {
+                    return difference;
+                }
+            }
+            actual
+                .keys()
+                .filter(|key| !expected.contains_key(*key))
+                .map(|key| path.key(key))
+                .find(|child| !child.is_ignored(ignored))
+                .map(|child| format!("{}: unexpected field", child.display()))
+        }
+        (Value::Array(actual), Value::Array(expected)) => {
+            if actual.len() != expected.len() {
+                return Some(format!(
+                    "{}: expected {} items, got {}",
+                    path.display(),
+                    expected.len(),
+                    actual.len()
+                ));
+            }
+            actual
+                .iter()
+                .zip(expected)
+                .enumerate()
+                .find_map(|(index, (actual, expected))| difference(actual, expected, ignored, &path.index(index)))
+        }
+        _ if actual == expected => None,
+        _ => Some(format!("{}: expected {}, got {}", path.display(), shorten(expected), shorten(actual))),
+    }
+}
+
+fn shorten(value: &Value) -> String {
+    let value = value.to_string();
+    match value.char_indices().nth(MAX_VALUE_LENGTH) {
+        Some((end, _)) => format!("{}...", &value[..end]),
+        None => value,
+    }
+}
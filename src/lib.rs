@@ -1,6 +1,25 @@
 pub mod config;
+pub mod dead_letter;
+pub mod dns;
+pub mod email;
+pub mod events;
+pub mod failure;
+pub mod grpc_web;
+pub mod history;
+pub mod json_diff;
+pub mod metrics;
+pub mod postgres;
+pub mod promql;
+pub mod resolver;
+pub mod slack;
+pub mod slo;
+pub mod socks;
+pub mod sqlite;
 pub mod telegram;
 pub mod web;
 
-pub use config::{AppState, Config, CheckType, State};
+#[cfg(test)]
+mod test_util;
+
+pub use config::{AppState, Config, CheckType, State, Status};
 pub use telegram::TelegramClient;
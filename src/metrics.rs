@@ -0,0 +1,149 @@
+use std::fmt::Write;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ServiceState, Status};
+
+// Prefix of metric names unless `metrics_prefix` is configured
+pub const DEFAULT_METRICS_PREFIX: &str = "healthcheck";
+
+// Escape a label value for the Prometheus text format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Summary of all enabled services with the overall health score
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub total: usize,
+    pub up: usize,
+    pub down: usize,
+    pub warning: usize,
+    pub unknown: usize,
+    // Weighted fraction (0-1) of services up, None until any service has a result
+    pub health_score: Option<f64>,
+}
+
+pub fn summary(services: &[ServiceState]) -> Summary {
+    let count = |status: Status| services.iter().filter(|s| s.status == status).count();
+
+    // Services without a result yet don't count either way
+    let (healthy, known) = services.iter().fold((0u64, 0u64), |(healthy, known), service| {
+        let weight = u64::from(service.health_weight);
+        match service.status {
+            Status::Up | Status::Warning => (healthy + weight, known + weight),
+            Status::Down => (healthy, known + weight),
+            Status::Unknown => (healthy, known),
+        }
+    });
+
+    Summary {
+        total: services.len(),
+        up: count(Status::Up),
+        down: count(Status::Down),
+        warning: count(Status::Warning),
+        unknown: count(Status::Unknown),
+        health_score: (known > 0).then(|| healthy as f64 / known as f64),
+    }
+}
+
+// Exposition format of /metrics, negotiated through the Accept header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Prometheus,
+    OpenMetrics,
+}
+
+impl Format {
+    // OpenMetrics when the scraper accepts it, the Prometheus text format otherwise
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        match accept {
+            Some(accept) if accept.contains("application/openmetrics-text") => Format::OpenMetrics,
+            _ => Format::Prometheus,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Prometheus => "text/plain; version=0.0.4",
+            Format::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        }
+    }
+}
+
+// Write the metadata of a metric family; OpenMetrics also gets the unit
+fn family(out: &mut String, format: Format, name: &str, kind: &str, unit: Option<&str>, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    if let (Format::OpenMetrics, Some(unit)) = (format, unit) {
+        let _ = writeln!(out, "# UNIT {} {}", name, unit);
+    }
+}
+
+// Per-service counter as (name without prefix, help, value)
+type Counter = (&'static str, &'static str, fn(&ServiceState) -> u64);
+
+// Render service metrics in the Prometheus text or OpenMetrics exposition format
+pub fn render(prefix: &str, services: &[ServiceState], format: Format) -> String {
+    let mut out = String::new();
+    let now = Utc::now();
+
+    family(&mut out, format, &format!("{}_up", prefix), "gauge", None, "Whether the service's last check succeeded (1) or failed (0)");
+    for service in services {
+        // Services without a result yet have no meaningful value
+        let up = match service.status {
+            Status::Up | Status::Warning => 1,
+            Status::Down => 0,
+            Status::Unknown => continue,
+        };
+        let _ = writeln!(out, "{}_up{{service=\"{}\"}} {}", prefix, escape_label(&service.name), up);
+    }
+
+    let counters: [Counter; 2] = [
+        ("total_checks", "Checks run since monitoring started", |s| s.total_checks),
+        ("failed_checks", "Failed checks since monitoring started", |s| s.failed_checks),
+    ];
+    for (suffix, help, value) in counters {
+        let name = format!("{}_{}", prefix, suffix);
+        family(&mut out, format, &name, "counter", None, help);
+        // OpenMetrics counter samples carry the _total suffix
+        let sample = match format {
+            Format::Prometheus => name.clone(),
+            Format::OpenMetrics => format!("{}_total", name),
+        };
+        for service in services {
+            let _ = writeln!(out, "{}{{service=\"{}\"}} {}", sample, escape_label(&service.name), value(service));
+        }
+    }
+
+    // A gauge rather than a counter, it drops back to 0 on recovery
+    let name = format!("{}_consecutive_failures", prefix);
+    family(&mut out, format, &name, "gauge", None, "Checks in a row that failed");
+    for service in services {
+        let _ = writeln!(out, "{}{{service=\"{}\"}} {}", name, escape_label(&service.name), service.consecutive_failures);
+    }
+
+    let name = format!("{}_uptime_seconds", prefix);
+    family(&mut out, format, &name, "gauge", Some("seconds"), "Seconds since the service last came up");
+    for service in services {
+        if let Some(uptime_start) = service.uptime_start {
+            let seconds = (now - uptime_start).num_milliseconds().max(0) as f64 / 1000.0;
+            let _ = writeln!(out, "{}{{service=\"{}\"}} {}", name, escape_label(&service.name), seconds);
+        }
+    }
+
+    if let Some(score) = summary(services).health_score {
+        let name = format!("{}_health_score", prefix);
+        family(&mut out, format, &name, "gauge", None, "Weighted fraction of services up");
+        let _ = writeln!(out, "{} {}", name, score);
+    }
+
+    if format == Format::OpenMetrics {
+        out.push_str("# EOF\n");
+    }
+    out
+}
@@ -0,0 +1,334 @@
+// Check results written to an external Postgres table in batches, for dashboards such as
+// Grafana or Metabase querying the history directly
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::config::State;
+
+const DEFAULT_TABLE: &str = "check_results";
+const DEFAULT_BATCH_SIZE: usize = 100;
+const DEFAULT_FLUSH_INTERVAL_MS: u64 = 5000;
+// Results kept while the database is unreachable, the oldest are dropped beyond this
+const MAX_PENDING: usize = 10000;
+// Rows per INSERT statement, Postgres allows at most 65535 parameters
+const ROWS_PER_STATEMENT: usize = 1000;
+
+// PostgresResults configures the table check results are written to
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct PostgresResults {
+    // URL or key-value connection string, e.g. "postgres://healthcheck:secret@db/metrics"
+    pub connection: String,
+    // Created when missing (default: check_results)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table: Option<String>,
+    // Results collected before they are written (default: 100)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch_size: Option<usize>,
+    // Longest time a result waits to be written (default: 5000)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flush_interval_ms: Option<u64>,
+}
+
+impl PostgresResults {
+    pub fn table(&self) -> &str {
+        self.table.as_deref().unwrap_or(DEFAULT_TABLE)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.connection
+            .parse::<tokio_postgres::Config>()
+            .map_err(|e| anyhow::anyhow!("Invalid Postgres connection '{}': {}", redact(&self.connection), e))?;
+        let table = self.table();
+        let valid = table.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            anyhow::bail!("Invalid Postgres table '{}', use letters, digits and '_'", table);
+        }
+        if self.batch_size == Some(0) || self.flush_interval_ms == Some(0) {
+            anyhow::bail!("Postgres batch_size and flush_interval_ms must be greater than zero");
+        }
+        Ok(())
+    }
+}
+
+// ResultRow is a single check result as stored in the table
+#[derive(Debug, Clone)]
+pub struct ResultRow {
+    pub service_id: String,
+    pub service: String,
+    pub state: State,
+    pub latency_ms: i64,
+    pub checked_at: DateTime<Utc>,
+}
+
+// ResultWriter queues results for a background task writing them in batches. Dropping it
+// writes the queued results and stops the task.
+pub struct ResultWriter {
+    settings: PostgresResults,
+    sender: mpsc::UnboundedSender<ResultRow>,
+}
+
+impl ResultWriter {
+    pub fn start(settings: PostgresResults) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run(settings.clone(), receiver));
+        Self { settings, sender }
+    }
+
+    pub fn settings(&self) -> &PostgresResults {
+        &self.settings
+    }
+
+    pub fn write(&self, row: ResultRow) {
+        let _ = self.sender.send(row);
+    }
+}
+
+async fn run(settings: PostgresResults, mut receiver: mpsc::UnboundedReceiver<ResultRow>) {
+    let batch_size = settings.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+    let period = Duration::from_millis(settings.flush_interval_ms.unwrap_or(DEFAULT_FLUSH_INTERVAL_MS));
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut client = None;
+    let mut pending = Vec::new();
+    let mut failing = false;
+
+    loop {
+        let closed = tokio::select! {
+            row = receiver.recv() => match row {
+                Some(row) => {
+                    pending.push(row);
+                    // While the database is unreachable only the interval retries
+                    if failing || pending.len() < batch_size {
+                        continue;
+                    }
+                    false
+                }
+                None => true,
+            },
+            _ = interval.tick() => false,
+        };
+
+        if !pending.is_empty() {
+            match flush(&settings, &mut client, &pending).await {
+                Ok(()) => {
+                    tracing::debug!("Wrote {} check results to Postgres", pending.len());
+                    pending.clear();
+                    failing = false;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to write {} check results to Postgres ({}): {}",
+                        pending.len(),
+                        redact(&settings.connection),
+                        e
+                    );
+                    client = None;
+                    failing = true;
+                    let excess = pending.len().saturating_sub(MAX_PENDING);
+                    pending.drain(..excess);
+                }
+            }
+        }
+        if closed {
+            break;
+        }
+    }
+}
+
+async fn flush(
+    settings: &PostgresResults,
+    client: &mut Option<tokio_postgres::Client>,
+    rows: &[ResultRow],
+) -> anyhow::Result<()> {
+    let client = match client {
+        Some(client) if !client.is_closed() => client,
+        _ => client.insert(connect(settings).await?),
+    };
+
+    let transaction = client.transaction().await?;
+    for chunk in rows.chunks(ROWS_PER_STATEMENT) {
+        let values: Vec<_> = chunk
+            .iter()
+            .map(|row| {
+                let (status, reason) = match &row.state {
+                    State::Success => ("up", None),
+                    State::Warning(reason) => ("warning", Some(reason.as_str())),
+                    State::Failure(reason) => ("down", Some(reason.detail.as_str())),
+                    State::Unknown => ("unknown", None),
+                };
+                (row, status, reason)
+            })
+            .collect();
+        let mut params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = Vec::new();
+        let mut placeholders = Vec::new();
+        for (row, status, reason) in &values {
+            let n = params.len();
+            placeholders.push(format!("(${}, ${}, ${}, ${}, ${}, ${})", n + 1, n + 2, n + 3, n + 4, n + 5, n + 6));
+            params.extend_from_slice(&[&row.service_id, &row.service, status, reason, &row.latency_ms, &row.checked_at]);
+        }
+        let query = format!(
+            "INSERT INTO {} (service_id, service, status, reason, latency_ms, checked_at) VALUES {}",
+            settings.table(),
+            placeholders.join(", ")
+        );
+        transaction.execute(query.as_str(), &params).await?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+async fn connect(settings: &PostgresResults) -> anyhow::Result<tokio_postgres::Client> {
+    // TLS is used when the server offers it, as with libpq's default sslmode=prefer
+    let tls = postgres_native_tls::MakeTlsConnector::new(native_tls::TlsConnector::new()?);
+    let (client, connection) = tokio_postgres::connect(&settings.connection, tls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::warn!("Postgres connection closed: {}", e);
+        }
+    });
+
+    let table = settings.table();
+    client
+        .batch_execute(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (
+                id BIGSERIAL PRIMARY KEY,
+                service_id TEXT NOT NULL,
+                service TEXT NOT NULL,
+                status TEXT NOT NULL,
+                reason TEXT,
+                latency_ms BIGINT NOT NULL,
+                checked_at TIMESTAMPTZ NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS {table}_service_checked_at ON {table} (service_id, checked_at);"
+        ))
+        .await?;
+    tracing::info!("Writing check results to Postgres ({})", redact(&settings.connection));
+    Ok(client)
+}
+
+// Connection string without the password, for logs and errors
+pub fn redact(connection: &str) -> String {
+    // Rebuilt from the parsed settings, so no spelling of the password can slip through
+    let Ok(config) = connection.parse::<tokio_postgres::Config>() else {
+        return "<invalid connection string>".to_string();
+    };
+    let hosts: Vec<String> = config
+        .get_hosts()
+        .iter()
+        .map(|host| match host {
+            tokio_postgres::config::Host::Tcp(host) => host.clone(),
+            #[cfg(unix)]
+            tokio_postgres::config::Host::Unix(path) => path.display().to_string(),
+        })
+        .collect();
+    let ports: Vec<String> = config.get_ports().iter().map(u16::to_string).collect();
+
+    let mut parts = Vec::new();
+    let values = [
+        ("host", Some(hosts.join(","))),
+        ("port", Some(ports.join(","))),
+        ("user", config.get_user().map(str::to_string)),
+        ("password", config.get_password().map(|_| "***".to_string())),
+        ("dbname", config.get_dbname().map(str::to_string)),
+        ("application_name", config.get_application_name().map(str::to_string)),
+    ];
+    for (key, value) in values {
+        match value.filter(|value| !value.is_empty()) {
+            Some(value) if value.contains([' ', '\'', '\\']) => {
+                parts.push(format!("{}='{}'", key, value.replace('\\', "\\\\").replace('\'', "\\'")))
+            }
+            Some(value) => parts.push(format!("{}={}", key, value)),
+            None => {}
+        }
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failure::FailureCategory;
+
+    #[test]
+    fn test_redact_connection() {
+        assert_eq!(
+            redact("postgres://grafana:s3cret@db:5432/metrics"),
+            "host=db port=5432 user=grafana password=*** dbname=metrics"
+        );
+        assert_eq!(redact("postgres://db/metrics"), "host=db port=5432 dbname=metrics");
+        assert_eq!(redact("postgres://db/metrics?password=s3cret"), "host=db port=5432 password=*** dbname=metrics");
+        assert_eq!(
+            redact("host=db user=grafana password='two words' dbname=metrics"),
+            "host=db user=grafana password=*** dbname=metrics"
+        );
+        assert_eq!(redact("host=db password = s3cret"), "host=db password=***");
+        assert_eq!(redact("host=db application_name='health check'"), "host=db application_name='health check'");
+        assert_eq!(redact("host=db password='unterminated"), "<invalid connection string>");
+    }
+
+    // Needs a database, e.g. HEALTHCHECK_TEST_POSTGRES="host=localhost user=postgres" cargo test -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_results_written_in_batches() {
+        let Ok(connection) = std::env::var("HEALTHCHECK_TEST_POSTGRES") else {
+            return;
+        };
+        let table = format!("results_{}", uuid::Uuid::new_v4().simple());
+        let settings = PostgresResults {
+            connection: connection.clone(),
+            table: Some(table.clone()),
+            batch_size: Some(3),
+            flush_interval_ms: Some(60000),
+        };
+        settings.validate().unwrap();
+
+        let writer = ResultWriter::start(settings);
+        let row = |state: State| ResultRow {
+            service_id: "api".to_string(),
+            service: "API".to_string(),
+            state,
+            latency_ms: 12,
+            checked_at: Utc::now(),
+        };
+        writer.write(row(State::Success));
+        writer.write(row(State::failure(FailureCategory::Status, "Unexpected status: 502")));
+        writer.write(row(State::Success));
+        // Below the batch size, written when the writer is dropped
+        writer.write(row(State::Warning("Certificate expires in 10 days".to_string())));
+
+        let (client, connection) = tokio_postgres::connect(&connection, tokio_postgres::NoTls).await.unwrap();
+        tokio::spawn(connection);
+        let query = format!("SELECT count(*) FROM {}", table);
+        let rows = || async { client.query_one(query.as_str(), &[]).await.map(|row| row.get::<_, i64>(0)).unwrap_or(0) };
+        for _ in 0..50 {
+            if rows().await == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(rows().await, 3);
+
+        drop(writer);
+        for _ in 0..50 {
+            if rows().await == 4 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let reasons: Vec<Option<String>> = client
+            .query(format!("SELECT reason FROM {} WHERE status = 'down'", table).as_str(), &[])
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(reasons, vec![Some("Unexpected status: 502".to_string())]);
+        assert_eq!(rows().await, 4);
+        client.batch_execute(&format!("DROP TABLE {}", table)).await.unwrap();
+    }
+}
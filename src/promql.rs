@@ -0,0 +1,64 @@
+// Evaluation of instant queries against the Prometheus HTTP API
+
+use serde::Deserialize;
+
+pub const QUERY_PATH: &str = "/api/v1/query";
+
+// Comparators accepted by PromQL checks
+pub const COMPARATORS: [&str; 6] = [">", ">=", "<", "<=", "==", "!="];
+
+// Response of /api/v1/query, `data` is only present on success
+#[derive(Deserialize, Debug)]
+pub struct QueryResponse {
+    pub status: String,
+    pub data: Option<QueryData>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "resultType", content = "result", rename_all = "lowercase")]
+pub enum QueryData {
+    // [timestamp, "value"]
+    Scalar((f64, String)),
+    Vector(Vec<Sample>),
+    Matrix(serde_json::Value),
+    String(serde_json::Value),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Sample {
+    pub value: (f64, String),
+}
+
+// Single numeric value of a query result: a scalar or a vector with exactly one sample
+pub fn scalar_value(response: QueryResponse) -> Result<f64, String> {
+    if response.status != "success" {
+        return Err(format!("Query failed: {}", response.error.unwrap_or(response.status)));
+    }
+    let value = match response.data {
+        Some(QueryData::Scalar((_, value))) => value,
+        Some(QueryData::Vector(samples)) => match <[Sample; 1]>::try_from(samples) {
+            Ok([sample]) => sample.value.1,
+            Err(samples) => {
+                return Err(format!("Query returned {} series, expected a single value", samples.len()))
+            }
+        },
+        Some(_) => return Err("Query must return a scalar or a single-sample vector".to_string()),
+        None => return Err("Query response has no data".to_string()),
+    };
+    // Prometheus encodes sample values as strings, including NaN and +Inf
+    value.parse().map_err(|_| format!("Invalid sample value: {}", value))
+}
+
+// Whether `value <comparator> threshold` holds
+pub fn compare(value: f64, comparator: &str, threshold: f64) -> Result<bool, String> {
+    match comparator {
+        ">" => Ok(value > threshold),
+        ">=" => Ok(value >= threshold),
+        "<" => Ok(value < threshold),
+        "<=" => Ok(value <= threshold),
+        "==" => Ok(value == threshold),
+        "!=" => Ok(value != threshold),
+        _ => Err(format!("Unknown comparator '{}', expected one of {}", comparator, COMPARATORS.join(" "))),
+    }
+}
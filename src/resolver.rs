@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use hickory_resolver::net::NetError;
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::time::Instant;
+
+use crate::dns;
+
+// Delay before the single retry of a failed lookup
+pub const DNS_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+// Resolver turns a host and port into socket addresses for TCP-based checks
+pub trait Resolver: Send + Sync {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, std::io::Result<Vec<SocketAddr>>>;
+}
+
+// SystemResolver uses the system DNS configuration (/etc/resolv.conf and /etc/hosts). Lookups of
+// names that don't exist fail with `ErrorKind::NotFound`, which the system's getaddrinfo can't tell.
+#[derive(Debug, Default, Clone)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, std::io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move {
+            let resolver = dns::system_resolver().map_err(|e| std::io::Error::other(e.to_string()))?;
+            let lookup = resolver.lookup_ip(host).await.map_err(|e| {
+                let kind = match e {
+                    _ if e.is_no_records_found() => std::io::ErrorKind::NotFound,
+                    NetError::Timeout => std::io::ErrorKind::TimedOut,
+                    _ => std::io::ErrorKind::Other,
+                };
+                std::io::Error::new(kind, e.to_string())
+            })?;
+            Ok(lookup.iter().map(|ip| SocketAddr::new(ip, port)).collect())
+        })
+    }
+}
+
+// Resolved addresses keyed by host and port, with the time of the lookup
+type CacheEntries = HashMap<(String, u16), (Instant, Vec<SocketAddr>)>;
+
+// CachingResolver keeps successful lookups of another resolver for `ttl`; failures aren't cached
+pub struct CachingResolver {
+    inner: Arc<dyn Resolver>,
+    ttl: Duration,
+    entries: Mutex<CacheEntries>,
+}
+
+impl CachingResolver {
+    pub fn new(inner: Arc<dyn Resolver>, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+}
+
+impl Resolver for CachingResolver {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, std::io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move {
+            let key = (host.to_string(), port);
+            if let Some((resolved_at, addrs)) = self.entries.lock().unwrap().get(&key) {
+                if resolved_at.elapsed() < self.ttl {
+                    return Ok(addrs.clone());
+                }
+            }
+
+            let addrs = self.inner.resolve(host, port).await?;
+            let mut entries = self.entries.lock().unwrap();
+            entries.retain(|_, (resolved_at, _)| resolved_at.elapsed() < self.ttl);
+            entries.insert(key, (Instant::now(), addrs.clone()));
+            Ok(addrs)
+        })
+    }
+}
+
+// Resolve the host, retrying once after a short delay since lookup failures are often transient.
+// Names that don't exist aren't retried.
+pub async fn resolve_with_retry(resolver: &dyn Resolver, host: &str, port: u16) -> std::io::Result<Vec<SocketAddr>> {
+    match resolver.resolve(host, port).await {
+        Ok(addrs) => Ok(addrs),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(e),
+        Err(e) => {
+            tracing::debug!("Resolving {} failed, retrying in {:?}: {}", host, DNS_RETRY_DELAY, e);
+            tokio::time::sleep(DNS_RETRY_DELAY).await;
+            resolver.resolve(host, port).await
+        }
+    }
+}
+
+// LookupError is a failed lookup of an HTTP request, telling it apart from connection errors
+#[derive(Debug)]
+pub struct LookupError(pub std::io::Error);
+
+impl std::fmt::Display for LookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for LookupError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+// HttpResolver lets HTTP clients resolve through a Resolver, with the same retry as other checks
+pub struct HttpResolver(pub Arc<dyn Resolver>);
+
+impl reqwest::dns::Resolve for HttpResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let resolver = self.0.clone();
+        Box::pin(async move {
+            let addrs = resolve_with_retry(resolver.as_ref(), name.as_str(), 0).await.map_err(LookupError)?;
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+// Resolve the host and connect to the first address that accepts the connection,
+// originating from `source` when given (addresses of the other IP family are skipped)
+pub async fn connect(
+    resolver: &dyn Resolver,
+    host: &str,
+    port: u16,
+    source: Option<IpAddr>,
+) -> std::io::Result<TcpStream> {
+    let addrs = resolve_with_retry(resolver, host, port).await?;
+
+    let mut last_error = None;
+    for addr in addrs {
+        if source.is_some_and(|source| source.is_ipv4() != addr.is_ipv4()) {
+            continue;
+        }
+        match connect_addr(addr, source).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No addresses found for {}", host),
+        )
+    }))
+}
+
+// Connect to an already resolved address, originating from `source` when given
+pub async fn connect_addr(addr: SocketAddr, source: Option<IpAddr>) -> std::io::Result<TcpStream> {
+    match source {
+        Some(source) => connect_from(source, addr).await,
+        None => TcpStream::connect(addr).await,
+    }
+}
+
+async fn connect_from(source: IpAddr, addr: SocketAddr) -> std::io::Result<TcpStream> {
+    let socket = match source {
+        IpAddr::V4(_) => TcpSocket::new_v4()?,
+        IpAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.bind(SocketAddr::new(source, 0))?;
+    socket.connect(addr).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Counts lookups and fails for hosts named "missing"
+    #[derive(Default)]
+    struct CountingResolver {
+        lookups: AtomicUsize,
+    }
+
+    impl Resolver for CountingResolver {
+        fn resolve<'a>(&'a self, host: &'a str, port: u16) -> BoxFuture<'a, std::io::Result<Vec<SocketAddr>>> {
+            Box::pin(async move {
+                self.lookups.fetch_add(1, Ordering::SeqCst);
+                if host == "missing" {
+                    return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no such host"));
+                }
+                Ok(vec![SocketAddr::from(([127, 0, 0, 1], port))])
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_caching_resolver_respects_ttl() {
+        let counting = Arc::new(CountingResolver::default());
+        let resolver = CachingResolver::new(counting.clone(), Duration::from_secs(60));
+
+        resolver.resolve("db.internal", 5432).await.unwrap();
+        resolver.resolve("db.internal", 5432).await.unwrap();
+        assert_eq!(counting.lookups.load(Ordering::SeqCst), 1);
+
+        // A different port is a different entry
+        resolver.resolve("db.internal", 5433).await.unwrap();
+        assert_eq!(counting.lookups.load(Ordering::SeqCst), 2);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        resolver.resolve("db.internal", 5432).await.unwrap();
+        assert_eq!(counting.lookups.load(Ordering::SeqCst), 3);
+
+        // Failures are retried on the next lookup
+        assert!(resolver.resolve("missing", 80).await.is_err());
+        assert!(resolver.resolve("missing", 80).await.is_err());
+        assert_eq!(counting.lookups.load(Ordering::SeqCst), 5);
+    }
+}
@@ -0,0 +1,122 @@
+// Slack notifications through an incoming webhook
+use serde_json::json;
+
+const ALERT_COLOR: &str = "#d32f2f";
+const WARNING_COLOR: &str = "#f9a825";
+const RECOVERY_COLOR: &str = "#388e3c";
+const INFO_COLOR: &str = "#1976d2";
+
+#[derive(Debug, Clone)]
+pub struct SlackClient {
+    webhook_url: String,
+    environment: Option<String>,
+    client: reqwest::Client,
+}
+
+// Escape the characters Slack's mrkdwn treats as markup
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// Clickable link to a service's runbook, appended to alerts
+pub fn runbook_link(url: &str) -> String {
+    format!("Runbook: <{}>", url.replace('|', "%7C").replace('>', "%3E"))
+}
+
+impl SlackClient {
+    pub fn new(webhook_url: String) -> Self {
+        Self {
+            webhook_url,
+            environment: None,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // Prefix every service name with the environment, e.g. "[staging] API"
+    pub fn with_environment(mut self, environment: Option<String>) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    fn service_label(&self, service_name: &str) -> String {
+        match &self.environment {
+            Some(environment) => format!("[{}] {}", environment, service_name),
+            None => service_name.to_string(),
+        }
+    }
+
+    // Post a message as an attachment colored by its kind; `footer` is sent as is, e.g. a runbook link
+    async fn post(&self, color: &str, title: &str, message: &str, footer: Option<&str>) -> anyhow::Result<()> {
+        let mut text = format!("*{}*\n\n{}", escape(title), escape(message));
+        if let Some(footer) = footer {
+            text = format!("{}\n\n{}", text, footer);
+        }
+        let payload = json!({
+            // Shown in notifications, which don't render attachments
+            "text": escape(title),
+            "attachments": [{
+                "color": color,
+                "blocks": [{
+                    "type": "section",
+                    "text": {"type": "mrkdwn", "text": text},
+                }],
+            }],
+        });
+
+        tracing::debug!("Sending Slack message: {}", title);
+        let response = self.client.post(&self.webhook_url).json(&payload).send().await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            Err(anyhow::anyhow!("Slack webhook error: {} - {}", status, error_text))
+        }
+    }
+
+    pub async fn send_alert(&self, service_name: &str, message: &str, footer: Option<&str>) -> anyhow::Result<()> {
+        let title = format!("Alert: {}", self.service_label(service_name));
+        self.post(ALERT_COLOR, &title, message, footer).await
+    }
+
+    pub async fn send_warning(&self, service_name: &str, message: &str) -> anyhow::Result<()> {
+        let title = format!("Warning: {}", self.service_label(service_name));
+        self.post(WARNING_COLOR, &title, message, None).await
+    }
+
+    pub async fn send_recovery(&self, service_name: &str, message: &str) -> anyhow::Result<()> {
+        let title = format!("Recovery: {}", self.service_label(service_name));
+        self.post(RECOVERY_COLOR, &title, message, None).await
+    }
+
+    pub async fn send_info(&self, service_name: &str, message: &str) -> anyhow::Result<()> {
+        let title = format!("Info: {}", self.service_label(service_name));
+        self.post(INFO_COLOR, &title, message, None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_alert_payload() {
+        let webhook = crate::test_util::MockWebhook::start().await;
+        let client = SlackClient::new(webhook.url()).with_environment(Some("prod".to_string()));
+
+        client
+            .send_alert("API", "Unexpected status: 502 <html>", Some(&runbook_link("https://wiki.example.com/api")))
+            .await
+            .unwrap();
+        client.send_recovery("API", "Recovered after 3m").await.unwrap();
+
+        let bodies = webhook.bodies();
+        assert_eq!(bodies[0]["text"], "Alert: [prod] API");
+        assert_eq!(bodies[0]["attachments"][0]["color"], ALERT_COLOR);
+        assert_eq!(
+            bodies[0]["attachments"][0]["blocks"][0]["text"]["text"],
+            "*Alert: [prod] API*\n\nUnexpected status: 502 &lt;html&gt;\n\nRunbook: <https://wiki.example.com/api>"
+        );
+        assert_eq!(bodies[1]["attachments"][0]["color"], RECOVERY_COLOR);
+    }
+}
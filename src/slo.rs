@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+// Number of most recent check latencies kept per service
+pub const LATENCY_WINDOW: usize = 1000;
+// Upper bounds of the latency histogram buckets unless `latency_buckets_ms` is set
+pub const DEFAULT_LATENCY_BUCKETS_MS: [u64; 8] = [50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+// SloReport summarizes check latencies over the rolling window
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct SloReport {
+    pub samples: usize,
+    // Percentiles are None until the first check completes
+    pub p50_ms: Option<u64>,
+    pub p95_ms: Option<u64>,
+    pub p99_ms: Option<u64>,
+    pub target_latency_ms: Option<u64>,
+    // Share of checks that completed within the target, None without a target or samples
+    pub compliance_percentage: Option<f64>,
+}
+
+// HistogramBucket counts the latencies above the previous bucket's bound up to `le_ms`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct HistogramBucket {
+    // None for the last bucket, which has no upper bound
+    pub le_ms: Option<u64>,
+    pub count: usize,
+}
+
+// LatencyHistogram is the distribution of check latencies over the rolling window
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct LatencyHistogram {
+    pub samples: usize,
+    pub buckets: Vec<HistogramBucket>,
+}
+
+// Count latencies into buckets bounded by `bounds`, which must be ascending
+pub fn latency_histogram(latencies: &[u64], bounds: &[u64]) -> LatencyHistogram {
+    let mut buckets: Vec<HistogramBucket> = bounds
+        .iter()
+        .map(|&bound| Some(bound))
+        .chain(std::iter::once(None))
+        .map(|le_ms| HistogramBucket { le_ms, count: 0 })
+        .collect();
+    for &latency in latencies {
+        let index = bounds.partition_point(|&bound| bound < latency);
+        buckets[index].count += 1;
+    }
+    LatencyHistogram {
+        samples: latencies.len(),
+        buckets,
+    }
+}
+
+// Nearest-rank percentile of sorted latencies
+fn percentile(sorted: &[u64], p: f64) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+pub fn slo_report(latencies: &[u64], target_latency_ms: Option<u64>) -> SloReport {
+    let mut sorted = latencies.to_vec();
+    sorted.sort_unstable();
+
+    let compliance_percentage = match target_latency_ms {
+        Some(target) if !sorted.is_empty() => {
+            let within = sorted.iter().filter(|&&latency| latency <= target).count();
+            Some(within as f64 / sorted.len() as f64 * 100.0)
+        }
+        _ => None,
+    };
+
+    SloReport {
+        samples: sorted.len(),
+        p50_ms: percentile(&sorted, 50.0),
+        p95_ms: percentile(&sorted, 95.0),
+        p99_ms: percentile(&sorted, 99.0),
+        target_latency_ms,
+        compliance_percentage,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slo_report() {
+        // 1..=100ms, shuffled order must not matter
+        let latencies: Vec<u64> = (1..=100).rev().collect();
+
+        let report = slo_report(&latencies, Some(90));
+        assert_eq!(report.samples, 100);
+        assert_eq!(report.p50_ms, Some(50));
+        assert_eq!(report.p95_ms, Some(95));
+        assert_eq!(report.p99_ms, Some(99));
+        assert_eq!(report.compliance_percentage, Some(90.0));
+
+        let report = slo_report(&[120, 80, 200, 40], Some(100));
+        assert_eq!(report.p50_ms, Some(80));
+        assert_eq!(report.p99_ms, Some(200));
+        assert_eq!(report.compliance_percentage, Some(50.0));
+
+        // No target or no samples leave the compliance undefined
+        assert_eq!(slo_report(&latencies, None).compliance_percentage, None);
+        let empty = slo_report(&[], Some(100));
+        assert_eq!(empty.p50_ms, None);
+        assert_eq!(empty.compliance_percentage, None);
+    }
+}
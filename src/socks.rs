@@ -0,0 +1,114 @@
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::resolver::{self, Resolver};
+
+// Split "socks5h://host:port", "socks5://host:port" or "host:port" into host and port
+pub fn parse_proxy(proxy: &str) -> std::io::Result<(String, u16)> {
+    let address = proxy
+        .strip_prefix("socks5h://")
+        .or_else(|| proxy.strip_prefix("socks5://"))
+        .unwrap_or(proxy)
+        .trim_end_matches('/');
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("Invalid SOCKS proxy: {}", proxy));
+
+    let (host, port) = address.rsplit_once(':').ok_or_else(invalid)?;
+    let port = port.parse().map_err(|_| invalid())?;
+    Ok((host.trim_matches(|c| c == '[' || c == ']').to_string(), port))
+}
+
+// URL for reqwest, defaulting to socks5h so the proxy resolves host names (needed for .onion)
+pub fn proxy_url(proxy: &str) -> String {
+    if proxy.contains("://") {
+        proxy.to_string()
+    } else {
+        format!("socks5h://{}", proxy)
+    }
+}
+
+// Open a TCP connection to host:port through a SOCKS5 proxy without authentication.
+// The host name is passed to the proxy unresolved.
+pub async fn connect(
+    resolver: &dyn Resolver,
+    proxy: &str,
+    host: &str,
+    port: u16,
+    source: Option<IpAddr>,
+) -> std::io::Result<TcpStream> {
+    let (proxy_host, proxy_port) = parse_proxy(proxy)?;
+    let mut stream = resolver::connect(resolver, &proxy_host, proxy_port, source)
+        .await
+        .map_err(|e| Error::other(format!("Failed to connect to SOCKS proxy {}: {}", proxy, e)))?;
+
+    // Greeting offering only "no authentication"
+    stream.write_all(&[0x05, 0x01, 0x00]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply != [0x05, 0x00] {
+        return Err(Error::other("SOCKS proxy requires unsupported authentication"));
+    }
+
+    let host_len = u8::try_from(host.len())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "Host name too long for SOCKS"))?;
+    let mut request = vec![0x05, 0x01, 0x00, 0x03, host_len];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    if header[1] != 0x00 {
+        // Only "connection refused" and "TTL expired" say something about the target port
+        let kind = match header[1] {
+            0x05 => ErrorKind::ConnectionRefused,
+            0x06 => ErrorKind::TimedOut,
+            _ => ErrorKind::Other,
+        };
+        return Err(Error::new(
+            kind,
+            format!("SOCKS proxy failed to connect to {}:{} (code {})", host, port, header[1]),
+        ));
+    }
+
+    // Skip the bound address and port
+    let address_len = match header[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => stream.read_u8().await? as usize,
+        other => return Err(Error::other(format!("Unknown SOCKS address type: {}", other))),
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(stream)
+}
+
+// Connect directly or, when a proxy is given, through it
+pub async fn connect_via(
+    resolver: &dyn Resolver,
+    proxy: Option<&str>,
+    host: &str,
+    port: u16,
+    source: Option<IpAddr>,
+) -> std::io::Result<TcpStream> {
+    match proxy {
+        Some(proxy) => connect(resolver, proxy, host, port, source).await,
+        None => resolver::connect(resolver, host, port, source).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proxy() {
+        assert_eq!(parse_proxy("socks5h://127.0.0.1:9050").unwrap(), ("127.0.0.1".to_string(), 9050));
+        assert_eq!(parse_proxy("tor:9050").unwrap(), ("tor".to_string(), 9050));
+        assert!(parse_proxy("socks5://tor").is_err());
+        assert_eq!(proxy_url("tor:9050"), "socks5h://tor:9050");
+    }
+}
@@ -0,0 +1,342 @@
+// Check results kept in a local SQLite database, so counters and uptime survive restarts
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, TransactionBehavior};
+
+use crate::config::State;
+
+// How often results older than the retention period are deleted
+const PRUNE_INTERVAL_HOURS: i64 = 1;
+
+// StoredCounters are the totals of a service's recorded results, restored on startup
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StoredCounters {
+    pub total_checks: u64,
+    pub successful_checks: u64,
+    pub failed_checks: u64,
+    // First result of the current run without failures, None when the last result failed
+    pub uptime_start: Option<DateTime<Utc>>,
+}
+
+// StateStore writes every check result to the `check_results` table and keeps the totals of each
+// service in `service_counters`, so results can be deleted after `retention_days`
+pub struct StateStore {
+    path: String,
+    connection: Mutex<Connection>,
+    retention: chrono::Duration,
+    // Result time of the last deletion of old results
+    pruned_at: Mutex<Option<DateTime<Utc>>>,
+}
+
+impl StateStore {
+    pub fn open(path: &str, retention_days: u64) -> anyhow::Result<Self> {
+        let mut connection = Connection::open(path)?;
+        // A write per check stays cheap with the write-ahead log
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS check_results (
+                id INTEGER PRIMARY KEY,
+                service_id TEXT NOT NULL,
+                status TEXT NOT NULL,
+                reason TEXT,
+                latency_ms INTEGER NOT NULL,
+                checked_at TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS check_results_service_time ON check_results (service_id, checked_at);
+            CREATE INDEX IF NOT EXISTS check_results_time ON check_results (checked_at);
+            CREATE TABLE IF NOT EXISTS service_counters (
+                service_id TEXT PRIMARY KEY,
+                total_checks INTEGER NOT NULL,
+                successful_checks INTEGER NOT NULL,
+                failed_checks INTEGER NOT NULL,
+                uptime_start TEXT
+            );
+            CREATE TABLE IF NOT EXISTS service_added (
+                service_id TEXT PRIMARY KEY,
+                added_at TEXT NOT NULL
+            );",
+        )?;
+
+        // Another process may be recording results meanwhile, so the backfills take the write lock
+        // up front and skip services whose rows appeared in between
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        // Databases written before the counters table existed start from their recorded results
+        let counted: i64 = transaction.query_row("SELECT COUNT(*) FROM service_counters", [], |row| row.get(0))?;
+        if counted == 0 {
+            // Timestamps are all stored in UTC, so they compare as text
+            transaction.execute(
+                "INSERT OR IGNORE INTO service_counters
+                SELECT service_id,
+                    COUNT(*),
+                    SUM(status IN ('up', 'warning')),
+                    SUM(status = 'down'),
+                    (SELECT MIN(up.checked_at) FROM check_results up
+                        WHERE up.service_id = results.service_id
+                        AND up.status IN ('up', 'warning')
+                        AND up.checked_at > COALESCE(
+                            (SELECT MAX(down.checked_at) FROM check_results down
+                                WHERE down.service_id = results.service_id AND down.status = 'down'),
+                            ''))
+                FROM check_results results
+                GROUP BY service_id",
+                [],
+            )?;
+        }
+
+        // Services of databases written before the table existed were added by their first result
+        let added: i64 = transaction.query_row("SELECT COUNT(*) FROM service_added", [], |row| row.get(0))?;
+        if added == 0 {
+            transaction.execute(
+                "INSERT OR IGNORE INTO service_added SELECT service_id, MIN(checked_at) FROM check_results GROUP BY service_id",
+                [],
+            )?;
+        }
+        transaction.commit()?;
+
+        Ok(Self {
+            path: path.to_string(),
+            connection: Mutex::new(connection),
+            retention: chrono::Duration::days(retention_days as i64),
+            pruned_at: Mutex::new(None),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Write a check result; `held_back` results were held back by `debounce_checks` and only count in the totals
+    pub fn record(
+        &self,
+        service_id: &str,
+        state: &State,
+        latency_ms: u64,
+        checked_at: DateTime<Utc>,
+        held_back: bool,
+    ) -> anyhow::Result<()> {
+        let (status, reason) = match state {
+            State::Success => ("up", None),
+            State::Warning(reason) => ("warning", Some(reason.as_str())),
+            State::Failure(reason) => ("down", Some(reason.detail.as_str())),
+            State::Unknown => ("unknown", None),
+        };
+        // Counted as `set_state` counts them: warnings are successes and only failures that weren't
+        // held back end the uptime
+        let (successful, failed) = match state {
+            State::Success | State::Warning(_) => (1, 0),
+            State::Failure(_) => (0, 1),
+            State::Unknown => (0, 0),
+        };
+
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO check_results (service_id, status, reason, latency_ms, checked_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![service_id, status, reason, latency_ms as i64, checked_at],
+        )?;
+        transaction.execute(
+            "INSERT INTO service_counters (service_id, total_checks, successful_checks, failed_checks, uptime_start)
+            VALUES (?1, 1, ?2, ?3, CASE WHEN ?2 = 1 AND NOT ?5 THEN ?4 END)
+            ON CONFLICT (service_id) DO UPDATE SET
+                total_checks = total_checks + 1,
+                successful_checks = successful_checks + excluded.successful_checks,
+                failed_checks = failed_checks + excluded.failed_checks,
+                uptime_start = CASE WHEN ?5 THEN uptime_start
+                    WHEN excluded.failed_checks = 1 THEN NULL
+                    ELSE COALESCE(uptime_start, excluded.uptime_start) END",
+            params![service_id, successful, failed, checked_at, held_back],
+        )?;
+
+        let mut pruned_at = self.pruned_at.lock().unwrap();
+        if pruned_at.is_none_or(|at| checked_at - at >= chrono::Duration::hours(PRUNE_INTERVAL_HOURS)) {
+            transaction.execute("DELETE FROM check_results WHERE checked_at < ?1", params![checked_at - self.retention])?;
+            *pruned_at = Some(checked_at);
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    // Totals of every service with recorded results, kept beyond the deletion of old results
+    pub fn counters(&self) -> anyhow::Result<HashMap<String, StoredCounters>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT service_id, total_checks, successful_checks, failed_checks, uptime_start FROM service_counters",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                StoredCounters {
+                    total_checks: row.get::<_, i64>(1)? as u64,
+                    successful_checks: row.get::<_, i64>(2)? as u64,
+                    failed_checks: row.get::<_, i64>(3)? as u64,
+                    uptime_start: row.get(4)?,
+                },
+            ))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    // When each service was first seen, services seen for the first time are recorded as added `now`
+    pub fn added_at(&self, service_ids: &[&str], now: DateTime<Utc>) -> anyhow::Result<HashMap<String, DateTime<Utc>>> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        let mut added = HashMap::new();
+        for service_id in service_ids {
+            transaction.execute(
+                "INSERT OR IGNORE INTO service_added (service_id, added_at) VALUES (?1, ?2)",
+                params![service_id, now],
+            )?;
+            let added_at = transaction.query_row(
+                "SELECT added_at FROM service_added WHERE service_id = ?1",
+                params![service_id],
+                |row| row.get(0),
+            )?;
+            added.insert(service_id.to_string(), added_at);
+        }
+        transaction.commit()?;
+        Ok(added)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failure::FailureCategory;
+
+    #[test]
+    fn test_counters_of_recorded_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let at = |minute: u32| "2026-03-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap() + chrono::Duration::minutes(minute.into());
+
+        let store = StateStore::open(path.to_str().unwrap(), 30).unwrap();
+        store.record("api", &State::Success, 20, at(0), false).unwrap();
+        store.record("api", &State::failure(FailureCategory::Status, "Unexpected status: 502"), 30, at(1), false).unwrap();
+        store.record("api", &State::Success, 20, at(2), false).unwrap();
+        store.record("api", &State::Warning("Slow".to_string()), 900, at(3), false).unwrap();
+        store.record("db", &State::failure(FailureCategory::Connection, "Connection refused"), 1, at(0), false).unwrap();
+        drop(store);
+
+        // Reopening keeps the results
+        let counters = StateStore::open(path.to_str().unwrap(), 30).unwrap().counters().unwrap();
+        assert_eq!(
+            counters["api"],
+            StoredCounters {
+                total_checks: 4,
+                successful_checks: 3,
+                failed_checks: 1,
+                uptime_start: Some(at(2)),
+            }
+        );
+        assert_eq!(counters["db"].failed_checks, 1);
+        assert_eq!(counters["db"].uptime_start, None);
+    }
+
+    #[test]
+    fn test_held_back_results_keep_uptime() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let at = |minute: i64| "2026-03-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap() + chrono::Duration::minutes(minute);
+        let store = StateStore::open(path.to_str().unwrap(), 30).unwrap();
+        let failure = || State::failure(FailureCategory::Connection, "Connection refused");
+
+        store.record("api", &State::Success, 20, at(0), false).unwrap();
+        // A one-off failure held back by debouncing is counted, the uptime continues
+        store.record("api", &failure(), 1, at(1), true).unwrap();
+        assert_eq!(store.counters().unwrap()["api"].uptime_start, Some(at(0)));
+        assert_eq!(store.counters().unwrap()["api"].failed_checks, 1);
+        store.record("api", &failure(), 1, at(2), false).unwrap();
+        assert_eq!(store.counters().unwrap()["api"].uptime_start, None);
+
+        // Neither does a held back success start it
+        store.record("api", &State::Success, 20, at(3), true).unwrap();
+        assert_eq!(store.counters().unwrap()["api"].uptime_start, None);
+        store.record("api", &State::Success, 20, at(4), false).unwrap();
+        assert_eq!(store.counters().unwrap()["api"].uptime_start, Some(at(4)));
+    }
+
+    #[test]
+    fn test_old_results_deleted_counters_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let at = |day: i64| "2026-03-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap() + chrono::Duration::days(day);
+        let results = |path: &std::path::Path| -> i64 {
+            Connection::open(path).unwrap().query_row("SELECT COUNT(*) FROM check_results", [], |row| row.get(0)).unwrap()
+        };
+
+        let store = StateStore::open(path.to_str().unwrap(), 7).unwrap();
+        store.record("api", &State::failure(FailureCategory::Connection, "Connection refused"), 1, at(0), false).unwrap();
+        store.record("api", &State::Success, 20, at(1), false).unwrap();
+        assert_eq!(results(&path), 2);
+
+        // Results older than 7 days go, the totals stay
+        store.record("api", &State::Success, 20, at(10), false).unwrap();
+        assert_eq!(results(&path), 1);
+        assert_eq!(
+            store.counters().unwrap()["api"],
+            StoredCounters {
+                total_checks: 3,
+                successful_checks: 2,
+                failed_checks: 1,
+                uptime_start: Some(at(1)),
+            }
+        );
+    }
+
+    #[test]
+    fn test_counters_of_earlier_databases_backfilled() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let store = StateStore::open(path.to_str().unwrap(), 30).unwrap();
+        store.record("api", &State::failure(FailureCategory::Connection, "Connection refused"), 1, Utc::now(), false).unwrap();
+        store.record("api", &State::Success, 20, Utc::now(), false).unwrap();
+        drop(store);
+
+        // As left by a version without the counters table
+        Connection::open(&path).unwrap().execute("DROP TABLE service_counters", []).unwrap();
+        let counters = StateStore::open(path.to_str().unwrap(), 30).unwrap().counters().unwrap();
+        assert_eq!((counters["api"].total_checks, counters["api"].failed_checks), (2, 1));
+        assert!(counters["api"].uptime_start.is_some());
+    }
+
+    #[test]
+    fn test_open_while_another_store_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let store = StateStore::open(path.to_str().unwrap(), 30).unwrap();
+
+        // Each open may find the tables empty and backfill them while results are being written
+        let writer = std::thread::spawn(move || {
+            for i in 0..200 {
+                store.record(&format!("service-{}", i), &State::Success, 1, Utc::now(), false).unwrap();
+            }
+        });
+        for _ in 0..20 {
+            Connection::open(&path).unwrap().execute_batch("DELETE FROM service_counters; DELETE FROM service_added").unwrap();
+            StateStore::open(path.to_str().unwrap(), 30).unwrap();
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_services_added_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let at = |minute: i64| "2026-03-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap() + chrono::Duration::minutes(minute);
+        let store = StateStore::open(path.to_str().unwrap(), 30).unwrap();
+        store.record("api", &State::Success, 20, at(0), false).unwrap();
+        drop(store);
+
+        // Earlier databases count a service's first result as its addition
+        Connection::open(&path).unwrap().execute("DROP TABLE service_added", []).unwrap();
+        let store = StateStore::open(path.to_str().unwrap(), 30).unwrap();
+        let added = store.added_at(&["api", "db"], at(5)).unwrap();
+        assert_eq!((added["api"], added["db"]), (at(0), at(5)));
+        // Later lookups keep the first time
+        assert_eq!(store.added_at(&["db"], at(10)).unwrap()["db"], at(5));
+    }
+}
@@ -1,18 +1,237 @@
-use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Debug, Clone)]
 pub struct TelegramClient {
     bot_token: String,
     chat_id: i64,
+    api_url: String,
+    environment: Option<String>,
+    emoji: NotificationEmoji,
+    // Attach re-check/acknowledge buttons to service alerts
+    actions: bool,
     client: reqwest::Client,
 }
 
+const DEFAULT_API_URL: &str = "https://api.telegram.org";
+
+// ChatId is a numeric chat id or the "@username" handle of a public channel or group,
+// which is resolved to its numeric id at startup
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatId {
+    Id(i64),
+    Handle { handle: String, id: Option<i64> },
+}
+
+impl ChatId {
+    // Numeric id, None for a handle that hasn't been resolved yet
+    pub fn id(&self) -> Option<i64> {
+        match self {
+            ChatId::Id(id) => Some(*id),
+            ChatId::Handle { id, .. } => *id,
+        }
+    }
+}
+
+impl Serialize for ChatId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ChatId::Id(id) => serializer.serialize_i64(*id),
+            ChatId::Handle { handle, .. } => serializer.serialize_str(handle),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ChatId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Id(i64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Id(id) => Ok(ChatId::Id(id)),
+            Raw::Text(text) => {
+                let text = text.trim();
+                // Numbers as strings, e.g. from the config editor
+                if let Ok(id) = text.parse() {
+                    return Ok(ChatId::Id(id));
+                }
+                if text.len() > 1 && text.starts_with('@') {
+                    return Ok(ChatId::Handle { handle: text.to_string(), id: None });
+                }
+                Err(serde::de::Error::custom(format!(
+                    "invalid telegram_chat_id '{}', expected a numeric id or an @handle",
+                    text
+                )))
+            }
+        }
+    }
+}
+
+// NotificationEmoji is the marker put in front of each kind of notification,
+// an empty string leaves the message without one
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct NotificationEmoji {
+    pub alert: String,
+    pub recovery: String,
+    pub warning: String,
+}
+
+impl Default for NotificationEmoji {
+    fn default() -> Self {
+        Self {
+            alert: "🚨".to_string(),
+            recovery: "✅".to_string(),
+            warning: "⚠️".to_string(),
+        }
+    }
+}
+
+// Longest message the Bot API accepts, in UTF-16 code units
+pub const MAX_MESSAGE_LENGTH: usize = 4096;
+
+// Group lines into as few batches as possible, each at most `limit` long when joined by newlines.
+// A line longer than the limit is shortened to fit a batch of its own.
+fn split_lines(lines: &[String], limit: usize) -> Vec<Vec<String>> {
+    let mut batches: Vec<Vec<String>> = Vec::new();
+    let mut length = 0;
+    for line in lines {
+        let line = truncate_line(line, limit);
+        let line_length = line.encode_utf16().count();
+        match batches.last_mut() {
+            Some(batch) if length + 1 + line_length <= limit => {
+                batch.push(line);
+                length += 1 + line_length;
+            }
+            _ => {
+                batches.push(vec![line]);
+                length = line_length;
+            }
+        }
+    }
+    batches
+}
+
+// Cut a line to at most `limit` UTF-16 code units, ending it with an ellipsis. The cut moves
+// in front of a tag it would split or leave unclosed, since Telegram rejects broken HTML.
+fn truncate_line(line: &str, limit: usize) -> String {
+    if line.encode_utf16().count() <= limit {
+        return line.to_string();
+    }
+    let mut end = 0;
+    let mut length = 0;
+    for (index, c) in line.char_indices() {
+        length += c.len_utf16();
+        if length > limit.saturating_sub(1) {
+            break;
+        }
+        end = index + c.len_utf8();
+    }
+    let mut text = &line[..end];
+    if let Some(open) = text.rfind('<').filter(|&open| !text[open..].contains('>')) {
+        text = &text[..open];
+    }
+    if let Some(open) = text.rfind("<a ").filter(|&open| !text[open..].contains("</a>")) {
+        text = &text[..open];
+    }
+    format!("{}…", text)
+}
+
+fn with_emoji(emoji: &str, text: String) -> String {
+    if emoji.is_empty() {
+        text
+    } else {
+        format!("{} {}", emoji, text)
+    }
+}
+
 #[derive(Serialize)]
 struct SendMessageRequest {
     chat_id: i64,
     text: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     parse_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+#[derive(Serialize)]
+struct EditMessageRequest {
+    chat_id: i64,
+    message_id: i64,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parse_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_markup: Option<InlineKeyboardMarkup>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct InlineKeyboardMarkup {
+    inline_keyboard: Vec<Vec<InlineKeyboardButton>>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct InlineKeyboardButton {
+    text: String,
+    callback_data: String,
+}
+
+// Callback data of the alert buttons, followed by ":" and the service id
+pub const RECHECK_ACTION: &str = "recheck";
+pub const ACKNOWLEDGE_ACTION: &str = "ack";
+
+#[derive(Serialize)]
+struct GetUpdatesRequest {
+    offset: i64,
+    timeout: u64,
+    allowed_updates: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct GetChatRequest {
+    chat_id: String,
+}
+
+#[derive(Serialize)]
+struct AnswerCallbackQueryRequest {
+    callback_query_id: String,
+    text: String,
+}
+
+// Update is the subset of a Bot API update needed to handle button presses
+#[derive(Deserialize, Debug)]
+pub struct Update {
+    pub update_id: i64,
+    pub callback_query: Option<CallbackQuery>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CallbackQuery {
+    pub id: String,
+    pub data: Option<String>,
+    pub message: Option<CallbackMessage>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CallbackMessage {
+    pub chat: Chat,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Chat {
+    pub id: i64,
+}
+
+// Clickable link to a service's runbook, appended to alerts
+pub fn runbook_link(url: &str) -> String {
+    let escaped = url.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+    format!("Runbook: <a href=\"{}\">{}</a>", escaped, escaped)
 }
 
 impl TelegramClient {
@@ -20,55 +239,250 @@ impl TelegramClient {
         Self {
             bot_token,
             chat_id,
+            api_url: DEFAULT_API_URL.to_string(),
+            environment: None,
+            emoji: NotificationEmoji::default(),
+            actions: false,
             client: reqwest::Client::new(),
         }
     }
 
-    pub async fn send_message(&self, text: &str) -> anyhow::Result<()> {
-        let url = format!("https://api.telegram.org/bot{}/sendMessage", self.bot_token);
+    // Prefix every service name with the environment, e.g. "[staging] API"
+    pub fn with_environment(mut self, environment: Option<String>) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    pub fn with_emoji(mut self, emoji: NotificationEmoji) -> Self {
+        self.emoji = emoji;
+        self
+    }
+
+    pub fn with_actions(mut self, actions: bool) -> Self {
+        self.actions = actions;
+        self
+    }
+
+    pub fn chat_id(&self) -> i64 {
+        self.chat_id
+    }
+
+    fn alert_keyboard(&self, service_id: &str) -> Option<InlineKeyboardMarkup> {
+        if !self.actions {
+            return None;
+        }
+        let button = |text: &str, action: &str| InlineKeyboardButton {
+            text: text.to_string(),
+            callback_data: format!("{}:{}", action, service_id),
+        };
+        Some(InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![
+                button("🔄 Re-check", RECHECK_ACTION),
+                button("👍 Acknowledge", ACKNOWLEDGE_ACTION),
+            ]],
+        })
+    }
 
+    fn service_label(&self, service_name: &str) -> String {
+        match &self.environment {
+            Some(environment) => format!("[{}] {}", environment, service_name),
+            None => service_name.to_string(),
+        }
+    }
+
+    // Use a different Bot API server (e.g. a self-hosted one)
+    pub fn with_api_url(mut self, api_url: String) -> Self {
+        self.api_url = api_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    // Send a message and return its id so it can be edited later
+    pub async fn send_message(&self, text: &str) -> anyhow::Result<i64> {
+        self.send_message_with_markup(text, None).await
+    }
+
+    async fn send_message_with_markup(&self, text: &str, reply_markup: Option<InlineKeyboardMarkup>) -> anyhow::Result<i64> {
         let request = SendMessageRequest {
             chat_id: self.chat_id,
             text: text.to_string(),
             parse_mode: Some("HTML".to_string()),
+            reply_markup,
         };
 
         tracing::debug!("Sending Telegram message to chat_id: {}", self.chat_id);
+        let result = self.call("sendMessage", &request).await?;
+        result["message_id"]
+            .as_i64()
+            .ok_or_else(|| anyhow::anyhow!("Telegram API response has no message_id"))
+    }
+
+    // Replace the text of a previously sent message
+    pub async fn edit_message(&self, message_id: i64, text: &str) -> anyhow::Result<()> {
+        self.edit_message_with_markup(message_id, text, None).await
+    }
+
+    async fn edit_message_with_markup(
+        &self,
+        message_id: i64,
+        text: &str,
+        reply_markup: Option<InlineKeyboardMarkup>,
+    ) -> anyhow::Result<()> {
+        let request = EditMessageRequest {
+            chat_id: self.chat_id,
+            message_id,
+            text: text.to_string(),
+            parse_mode: Some("HTML".to_string()),
+            reply_markup,
+        };
+
+        tracing::debug!("Editing Telegram message {} in chat_id: {}", message_id, self.chat_id);
+        self.call("editMessageText", &request).await?;
+        Ok(())
+    }
+
+    // Long-poll for button presses after `offset`
+    pub async fn get_updates(&self, offset: i64, timeout_secs: u64) -> anyhow::Result<Vec<Update>> {
+        let request = GetUpdatesRequest {
+            offset,
+            timeout: timeout_secs,
+            allowed_updates: vec!["callback_query".to_string()],
+        };
+        let result = self.call("getUpdates", &request).await?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    // Numeric id of the chat behind an "@username" handle; the bot must be able to see the chat
+    pub async fn resolve_handle(&self, handle: &str) -> anyhow::Result<i64> {
+        let request = GetChatRequest {
+            chat_id: handle.to_string(),
+        };
+        let result = self.call("getChat", &request).await?;
+        result["id"]
+            .as_i64()
+            .ok_or_else(|| anyhow::anyhow!("Telegram API response has no chat id"))
+    }
+
+    // Confirm a button press, showing `text` to the user
+    pub async fn answer_callback_query(&self, callback_query_id: &str, text: &str) -> anyhow::Result<()> {
+        let request = AnswerCallbackQueryRequest {
+            callback_query_id: callback_query_id.to_string(),
+            text: text.to_string(),
+        };
+        self.call("answerCallbackQuery", &request).await?;
+        Ok(())
+    }
+
+    // Call a Bot API method and return its `result`
+    async fn call<T: Serialize>(&self, method: &str, request: &T) -> anyhow::Result<serde_json::Value> {
+        let url = format!("{}/bot{}/{}", self.api_url, self.bot_token, method);
 
         let response = self.client
             .post(&url)
-            .json(&request)
+            .json(request)
             .send()
             .await?;
 
         if response.status().is_success() {
-            tracing::debug!("Telegram message sent successfully");
-            Ok(())
+            tracing::debug!("Telegram {} succeeded", method);
+            let mut body: serde_json::Value = response.json().await?;
+            Ok(body["result"].take())
         } else {
             let status = response.status();
             let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            tracing::error!("Failed to send Telegram message: {} - {}", status, error_text);
+            tracing::error!("Failed to call Telegram {}: {} - {}", method, status, error_text);
             Err(anyhow::anyhow!("Telegram API error: {} - {}", status, error_text))
         }
     }
 
-    pub async fn send_alert(&self, service_name: &str, message: &str) -> anyhow::Result<()> {
-        let formatted_message = format!(
-            "🚨 <b>Alert: {}</b>\n\n{}",
-            service_name,
+    fn format_alert(&self, service_name: &str, message: &str) -> String {
+        with_emoji(&self.emoji.alert, format!(
+            "<b>Alert: {}</b>\n\n{}",
+            self.service_label(service_name),
             message
-        );
+        ))
+    }
+
+    pub async fn send_alert(&self, service_name: &str, message: &str) -> anyhow::Result<i64> {
+        self.send_message(&self.format_alert(service_name, message)).await
+    }
+
+    // Alert about a monitored service, with action buttons when enabled
+    pub async fn send_service_alert(&self, service_id: &str, service_name: &str, message: &str) -> anyhow::Result<i64> {
+        let formatted_message = self.format_alert(service_name, message);
+        self.send_message_with_markup(&formatted_message, self.alert_keyboard(service_id))
+            .await
+    }
+
+    // Update a previously sent alert in place instead of sending a new one
+    pub async fn edit_alert(
+        &self,
+        message_id: i64,
+        service_id: &str,
+        service_name: &str,
+        message: &str,
+    ) -> anyhow::Result<()> {
+        let formatted_message = self.format_alert(service_name, message);
+        self.edit_message_with_markup(message_id, &formatted_message, self.alert_keyboard(service_id))
+            .await
+    }
+
+    // Send several alerts as a single message, split into parts when it exceeds the
+    // Bot API's length limit. `sent_parts` counts the parts delivered so far, so sending
+    // again after an error continues with the failed part. Returns the id of the last part.
+    pub async fn send_alerts(&self, alerts: &[(String, String)], sent_parts: &AtomicUsize) -> anyhow::Result<i64> {
+        let lines: Vec<String> = alerts
+            .iter()
+            .map(|(service_name, message)| format!("<b>{}</b>: {}", self.service_label(service_name), message))
+            .collect();
+        let header = |part: &str| with_emoji(&self.emoji.alert, format!("<b>Alert: {} services failing{}</b>\n\n", alerts.len(), part));
+        // Room for the longest possible header, with a part counter
+        let counter = format!(" ({}/{})", alerts.len(), alerts.len());
+        let limit = MAX_MESSAGE_LENGTH.saturating_sub(header(&counter).encode_utf16().count());
+
+        let batches = split_lines(&lines, limit);
+        let mut message_id = 0;
+        for (index, batch) in batches.iter().enumerate().skip(sent_parts.load(Ordering::SeqCst)) {
+            let part = if batches.len() > 1 { format!(" ({}/{})", index + 1, batches.len()) } else { String::new() };
+            message_id = self.send_message(&format!("{}{}", header(&part), batch.join("\n"))).await?;
+            sent_parts.store(index + 1, Ordering::SeqCst);
+        }
+        Ok(message_id)
+    }
+
+    pub async fn send_warning(&self, service_name: &str, message: &str) -> anyhow::Result<i64> {
+        let formatted_message = with_emoji(&self.emoji.warning, format!(
+            "<b>Warning: {}</b>\n\n{}",
+            self.service_label(service_name),
+            message
+        ));
         self.send_message(&formatted_message).await
     }
 
-    pub async fn send_recovery(&self, service_name: &str, message: &str) -> anyhow::Result<()> {
+    // Neutral message without an emoji
+    pub async fn send_info(&self, service_name: &str, message: &str) -> anyhow::Result<i64> {
+        let formatted_message = format!("<b>Info: {}</b>\n\n{}", self.service_label(service_name), message);
+        self.send_message(&formatted_message).await
+    }
+
+    // Announce that the monitor started, repeated messages point at a crash loop
+    pub async fn send_startup(&self, version: &str, services: usize) -> anyhow::Result<i64> {
         let formatted_message = format!(
-            "✅ <b>Recovery: {}</b>\n\n{}",
-            service_name,
-            message
+            "<b>{} {} started</b>\n\nMonitoring {} services",
+            self.service_label("healthcheck"),
+            version,
+            services
         );
         self.send_message(&formatted_message).await
     }
+
+    pub async fn send_recovery(&self, service_name: &str, message: &str) -> anyhow::Result<i64> {
+        let formatted_message = with_emoji(&self.emoji.recovery, format!(
+            "<b>Recovery: {}</b>\n\n{}",
+            self.service_label(service_name),
+            message
+        ));
+        self.send_message(&formatted_message).await
+    }
 }
 
 #[cfg(test)]
@@ -80,4 +494,153 @@ mod tests {
         let client = TelegramClient::new("test_token".to_string(), 12345);
         assert_eq!(client.chat_id, 12345);
     }
+
+    #[tokio::test]
+    async fn test_environment_prefix() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let client = TelegramClient::new("test_token".to_string(), 12345)
+            .with_api_url(telegram.url())
+            .with_environment(Some("staging".to_string()));
+
+        client.send_alert("API", "Unexpected status: 500").await.unwrap();
+        client.send_recovery("API", "recovered").await.unwrap();
+
+        assert_eq!(
+            telegram.messages(),
+            vec![
+                "🚨 <b>Alert: [staging] API</b>\n\nUnexpected status: 500",
+                "✅ <b>Recovery: [staging] API</b>\n\nrecovered",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_emoji() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let emoji: NotificationEmoji = serde_yaml::from_str("alert: \"[DOWN]\"\nrecovery: \"\"").unwrap();
+        let client = TelegramClient::new("test_token".to_string(), 12345)
+            .with_api_url(telegram.url())
+            .with_emoji(emoji);
+
+        client.send_alert("API", "Unexpected status: 500").await.unwrap();
+        client.send_recovery("API", "recovered").await.unwrap();
+        client.send_warning("API", "slow").await.unwrap();
+
+        assert_eq!(
+            telegram.messages(),
+            vec![
+                "[DOWN] <b>Alert: API</b>\n\nUnexpected status: 500",
+                "<b>Recovery: API</b>\n\nrecovered",
+                "⚠️ <b>Warning: API</b>\n\nslow",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert_includes_action_keyboard() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let client = TelegramClient::new("test_token".to_string(), 12345)
+            .with_api_url(telegram.url())
+            .with_actions(true);
+
+        client.send_service_alert("web", "Web", "Unexpected status: 500").await.unwrap();
+        client.send_alert("CLI", "manual").await.unwrap();
+
+        let requests = telegram.requests();
+        assert_eq!(
+            requests[0].1["reply_markup"],
+            serde_json::json!({
+                "inline_keyboard": [[
+                    { "text": "🔄 Re-check", "callback_data": "recheck:web" },
+                    { "text": "👍 Acknowledge", "callback_data": "ack:web" },
+                ]]
+            })
+        );
+        // Only service alerts can be acted on
+        assert!(requests[1].1.get("reply_markup").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_send_alerts_combines_services() {
+        let telegram = crate::test_util::MockTelegram::start().await;
+        let client = TelegramClient::new("test_token".to_string(), 12345).with_api_url(telegram.url());
+
+        client
+            .send_alerts(
+                &[
+                    ("Web".to_string(), "Unexpected status: 500".to_string()),
+                    ("DB".to_string(), "Connection failed".to_string()),
+                ],
+                &AtomicUsize::new(0),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            telegram.messages(),
+            vec!["🚨 <b>Alert: 2 services failing</b>\n\n<b>Web</b>: Unexpected status: 500\n<b>DB</b>: Connection failed"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_alerts_resumes_after_failed_part() {
+        use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+        use std::sync::{Arc, Mutex};
+
+        type Texts = Arc<Mutex<Vec<String>>>;
+
+        // The second request fails once, the others are recorded
+        let texts: Texts = Arc::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let router = Router::new()
+            .route(
+                "/:bot/sendMessage",
+                post(
+                    |State((texts, calls)): State<(Texts, Arc<AtomicUsize>)>, Json(body): Json<serde_json::Value>| async move {
+                        if calls.fetch_add(1, Ordering::SeqCst) == 1 {
+                            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({ "ok": false })));
+                        }
+                        let mut texts = texts.lock().unwrap();
+                        texts.push(body["text"].as_str().unwrap().to_string());
+                        (StatusCode::OK, Json(serde_json::json!({ "ok": true, "result": { "message_id": texts.len() } })))
+                    },
+                ),
+            )
+            .with_state((texts.clone(), calls));
+        let url = crate::test_util::serve(router).await;
+        let client = TelegramClient::new("test_token".to_string(), 12345).with_api_url(url);
+
+        let alerts: Vec<(String, String)> = (0..3).map(|i| (format!("Service {}", i), "x".repeat(3000))).collect();
+        let sent_parts = AtomicUsize::new(0);
+        assert!(client.send_alerts(&alerts, &sent_parts).await.is_err());
+        assert_eq!(sent_parts.load(Ordering::SeqCst), 1);
+        client.send_alerts(&alerts, &sent_parts).await.unwrap();
+
+        let texts = texts.lock().unwrap();
+        let headers: Vec<&str> = texts.iter().map(|text| text.lines().next().unwrap()).collect();
+        assert_eq!(
+            headers,
+            [
+                "🚨 <b>Alert: 3 services failing (1/3)</b>",
+                "🚨 <b>Alert: 3 services failing (2/3)</b>",
+                "🚨 <b>Alert: 3 services failing (3/3)</b>"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_overlong_line_truncated() {
+        let link = runbook_link("https://wiki.example.com/runbook");
+        let line = format!("<b>Web</b>: {} {}", "ü".repeat(5000), link);
+        let batches = split_lines(&[line, "<b>DB</b>: Connection failed".to_string()], 100);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0][0].encode_utf16().count(), 100);
+        assert!(batches[0][0].ends_with("üü…"), "{}", batches[0][0]);
+        assert_eq!(batches[1], ["<b>DB</b>: Connection failed"]);
+
+        // A cut inside the runbook link drops the whole link
+        let line = format!("<b>Web</b>: down {}", link);
+        assert_eq!(truncate_line(&line, 40), "<b>Web</b>: down Runbook: …");
+        assert_eq!(truncate_line(&line, line.encode_utf16().count()), line);
+    }
 }
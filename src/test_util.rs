@@ -0,0 +1,285 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{extract::{Path, State}, routing::post, Json, Router};
+
+// Spawn a router on an ephemeral local port and return its base URL
+pub async fn serve(router: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.unwrap();
+    });
+    format!("http://{}", addr)
+}
+
+type Requests = Arc<Mutex<Vec<(String, serde_json::Value)>>>;
+
+// MockTelegram records every Bot API call, numbering sent messages from 1
+pub struct MockTelegram {
+    url: String,
+    requests: Requests,
+}
+
+impl MockTelegram {
+    pub async fn start() -> Self {
+        let requests: Requests = Arc::default();
+        let router = Router::new()
+            .route(
+                "/:bot/:method",
+                post(
+                    |State(requests): State<Requests>,
+                     Path((_bot, method)): Path<(String, String)>,
+                     Json(body): Json<serde_json::Value>| async move {
+                        let mut requests = requests.lock().unwrap();
+                        requests.push((method, body));
+                        Json(serde_json::json!({ "ok": true, "result": { "message_id": requests.len() } }))
+                    },
+                ),
+            )
+            .with_state(requests.clone());
+
+        Self {
+            url: serve(router).await,
+            requests,
+        }
+    }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    pub fn messages(&self) -> Vec<String> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(_, body)| body["text"].as_str().map(str::to_string))
+            .collect()
+    }
+
+    // Bot API method names and request bodies in call order
+    pub fn requests(&self) -> Vec<(String, serde_json::Value)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+// MockSocks is a SOCKS5 proxy without authentication that relays connections
+// and records the requested "host:port" targets
+pub struct MockSocks {
+    address: String,
+    targets: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockSocks {
+    pub async fn start() -> Self {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let targets: Arc<Mutex<Vec<String>>> = Arc::default();
+
+        let recorded = targets.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut client, _) = listener.accept().await.unwrap();
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let mut greeting = [0u8; 3];
+                    client.read_exact(&mut greeting).await.unwrap();
+                    client.write_all(&[0x05, 0x00]).await.unwrap();
+
+                    let mut header = [0u8; 4];
+                    client.read_exact(&mut header).await.unwrap();
+                    let host = match header[3] {
+                        0x01 => {
+                            let mut ip = [0u8; 4];
+                            client.read_exact(&mut ip).await.unwrap();
+                            std::net::Ipv4Addr::from(ip).to_string()
+                        }
+                        0x03 => {
+                            let mut host = vec![0u8; client.read_u8().await.unwrap() as usize];
+                            client.read_exact(&mut host).await.unwrap();
+                            String::from_utf8(host).unwrap()
+                        }
+                        other => panic!("unsupported address type {}", other),
+                    };
+                    let port = client.read_u16().await.unwrap();
+                    let target = format!("{}:{}", host, port);
+                    recorded.lock().unwrap().push(target.clone());
+
+                    let mut upstream = tokio::net::TcpStream::connect(&target).await.unwrap();
+                    client.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await.unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+                });
+            }
+        });
+
+        Self { address, targets }
+    }
+
+    pub fn address(&self) -> String {
+        self.address.clone()
+    }
+
+    pub fn targets(&self) -> Vec<String> {
+        self.targets.lock().unwrap().clone()
+    }
+}
+
+// MockWebhook records the JSON body of every POST it receives
+pub struct MockWebhook {
+    url: String,
+    bodies: Arc<Mutex<Vec<serde_json::Value>>>,
+}
+
+impl MockWebhook {
+    pub async fn start() -> Self {
+        let bodies: Arc<Mutex<Vec<serde_json::Value>>> = Arc::default();
+        let router = Router::new()
+            .route(
+                "/",
+                post(
+                    |State(bodies): State<Arc<Mutex<Vec<serde_json::Value>>>>,
+                     Json(body): Json<serde_json::Value>| async move {
+                        bodies.lock().unwrap().push(body);
+                    },
+                ),
+            )
+            .with_state(bodies.clone());
+
+        Self {
+            url: format!("{}/", serve(router).await),
+            bodies,
+        }
+    }
+
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    pub fn bodies(&self) -> Vec<serde_json::Value> {
+        self.bodies.lock().unwrap().clone()
+    }
+}
+
+// MockDns is a UDP nameserver answering from a fixed set of records, NXDOMAIN for unknown names
+pub struct MockDns {
+    address: String,
+}
+
+impl MockDns {
+    pub async fn start(records: Vec<(&str, hickory_resolver::proto::rr::RData)>) -> Self {
+        use hickory_resolver::proto::op::{Message, ResponseCode};
+        use hickory_resolver::proto::rr::{Name, Record};
+
+        let records: Vec<Record> = records
+            .into_iter()
+            .map(|(name, data)| Record::from_rdata(Name::from_ascii(name).unwrap(), 60, data))
+            .collect();
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let (len, client) = socket.recv_from(&mut buf).await.unwrap();
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let query = request.queries[0].clone();
+                let mut response = Message::response(request.metadata.id, request.metadata.op_code);
+                if !records.iter().any(|record| record.name == *query.name()) {
+                    response.metadata.response_code = ResponseCode::NXDomain;
+                }
+                response.add_answers(
+                    records
+                        .iter()
+                        .filter(|record| record.name == *query.name() && record.record_type() == query.query_type())
+                        .cloned(),
+                );
+                response.add_query(query);
+                socket.send_to(&response.to_vec().unwrap(), client).await.unwrap();
+            }
+        });
+
+        Self { address }
+    }
+
+    pub fn address(&self) -> String {
+        self.address.clone()
+    }
+}
+
+// Envelope sender, recipients and data of a message received by MockSmtp
+type Mail = (String, Vec<String>, String);
+
+// MockSmtp is a plaintext SMTP server accepting any login and recording every message
+pub struct MockSmtp {
+    port: u16,
+    messages: Arc<Mutex<Vec<Mail>>>,
+}
+
+impl MockSmtp {
+    pub async fn start() -> Self {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let messages: Arc<Mutex<Vec<Mail>>> = Arc::default();
+
+        let recorded = messages.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+                    let (mut from, mut to) = (String::new(), Vec::new());
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let address = |line: &str| line.split(['<', '>']).nth(1).unwrap_or("").to_string();
+                        let command = line.to_uppercase();
+                        let reply: &[u8] = if command.starts_with("EHLO") {
+                            b"250-localhost\r\n250 AUTH PLAIN LOGIN\r\n"
+                        } else if command.starts_with("AUTH") {
+                            b"235 Authenticated\r\n"
+                        } else if command.starts_with("MAIL FROM") {
+                            from = address(&line);
+                            b"250 OK\r\n"
+                        } else if command.starts_with("RCPT TO") {
+                            to.push(address(&line));
+                            b"250 OK\r\n"
+                        } else if command == "DATA" {
+                            writer.write_all(b"354 Go ahead\r\n").await.unwrap();
+                            let mut data = Vec::new();
+                            while let Ok(Some(line)) = lines.next_line().await {
+                                if line == "." {
+                                    break;
+                                }
+                                data.push(line);
+                            }
+                            let mail = (std::mem::take(&mut from), std::mem::take(&mut to), data.join("\r\n"));
+                            recorded.lock().unwrap().push(mail);
+                            b"250 Queued\r\n"
+                        } else if command == "QUIT" {
+                            let _ = writer.write_all(b"221 Bye\r\n").await;
+                            break;
+                        } else {
+                            b"250 OK\r\n"
+                        };
+                        writer.write_all(reply).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        Self { port, messages }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn messages(&self) -> Vec<Mail> {
+        self.messages.lock().unwrap().clone()
+    }
+}
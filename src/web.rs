@@ -1,15 +1,32 @@
 use axum::{
     async_trait,
-    extract::{FromRequestParts, State},
-    http::{StatusCode, request::Parts},
-    response::Json,
-    routing::get,
+    body::Body,
+    extract::{DefaultBodyLimit, FromRequestParts, Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode, request::Parts},
+    middleware::{self, Next},
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        IntoResponse, Json, Response,
+    },
+    routing::{get, post},
     Router,
 };
+use futures::StreamExt;
+use tokio::sync::broadcast;
 use tower_http::cors::{Any, CorsLayer};
+use tower_http::decompression::RequestDecompressionLayer;
 use tower_http::services::ServeDir;
+use tower_http::timeout::TimeoutLayer;
 
-use crate::config::{AppState, ServiceState, Config};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{AppState, Config, Service, ServiceState, State as CheckState, Status};
+use crate::history::{CheckRecord, UptimeReport};
+use crate::metrics::{self, Summary, DEFAULT_METRICS_PREFIX};
+use crate::slo::{LatencyHistogram, SloReport};
 
 // Bearer token extractor for authentication
 pub struct BearerToken(pub String);
@@ -36,15 +53,188 @@ where
     }
 }
 
+// Validate the bearer token against the configured one. Without a configured token the
+// configuration endpoints are disabled rather than open to anyone.
+fn authorize(config: &Config, bearer: &BearerToken) -> Result<(), (StatusCode, &'static str)> {
+    let Some(expected_token) = &config.api_bearer_token else {
+        return Err((StatusCode::FORBIDDEN, "Configuration API is disabled, set api_bearer_token to enable it"));
+    };
+    if bearer.0 != *expected_token {
+        return Err((StatusCode::UNAUTHORIZED, "Invalid bearer token"));
+    }
+    Ok(())
+}
+
+// Validate and apply a new configuration, mapping failures to HTTP errors
+async fn apply_config(
+    state: &AppState,
+    new_config: Config,
+) -> Result<(StatusCode, &'static str), (StatusCode, String)> {
+    if let Err(e) = new_config.validate() {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid configuration: {}", e)));
+    }
+    // Whether commands may run on this host is only decided in the configuration file
+    if new_config.allow_command_checks != state.get_config().await.allow_command_checks {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "allow_command_checks can only be changed in the configuration file".to_string(),
+        ));
+    }
+
+    match state.update_config(new_config).await {
+        Ok(_) => {
+            tracing::info!("Configuration updated successfully via API");
+            Ok((StatusCode::OK, "Configuration updated successfully"))
+        }
+        Err(e) => {
+            tracing::error!("Failed to update configuration: {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to update configuration: {}", e),
+            ))
+        }
+    }
+}
+
 // Handler for getting all service states
 async fn get_services(State(state): State<AppState>) -> Json<Vec<ServiceState>> {
     let services = state.get_all_services().await;
     Json(services)
 }
 
-// Handler for health check endpoint
-async fn health_check() -> (StatusCode, &'static str) {
-    (StatusCode::OK, "OK")
+// Handler for the state of a single service
+async fn get_service(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<ServiceState>, (StatusCode, &'static str)> {
+    let id = state.get_config().await.service_id(&id).ok_or((StatusCode::NOT_FOUND, "Service not found"))?;
+    state
+        .get_service(&id)
+        .await
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Service not found"))
+}
+
+// Handler for the stream of recorded check results as server-sent events. A client reconnecting
+// with Last-Event-ID first receives the buffered events it missed.
+async fn get_events(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Sse<impl futures::Stream<Item = Result<SseEvent, axum::Error>>> {
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok());
+    let (missed, receiver) = state.subscribe_events(last_event_id);
+
+    let live = futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, receiver)),
+                // A slow client skips what it missed, it can reconnect with Last-Event-ID
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    let stream = futures::stream::iter(missed)
+        .chain(live)
+        .map(|event| SseEvent::default().id(event.id.to_string()).json_data(&event));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+// Handler for the status counts and overall health score
+async fn get_summary(State(state): State<AppState>) -> Json<Summary> {
+    Json(metrics::summary(&state.get_all_services().await))
+}
+
+// Optional date range for reports, defaults to the whole monitored period
+#[derive(Deserialize)]
+struct ReportRange {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+// Handler for the uptime report of a single service
+async fn get_service_report(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(range): Query<ReportRange>,
+) -> Result<Json<UptimeReport>, (StatusCode, &'static str)> {
+    let id = state.get_config().await.service_id(&id).ok_or((StatusCode::NOT_FOUND, "Service not found"))?;
+    state
+        .uptime_report(&id, range.from, range.to)
+        .await
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Service not found"))
+}
+
+// Handler for the most recent check results of a single service, oldest first
+async fn get_service_history(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<CheckRecord>>, (StatusCode, &'static str)> {
+    let id = state.get_config().await.service_id(&id).ok_or((StatusCode::NOT_FOUND, "Service not found"))?;
+    state
+        .check_history(&id)
+        .await
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Service not found"))
+}
+
+// Handler for latency percentiles and SLO compliance of a single service
+async fn get_service_slo(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<SloReport>, (StatusCode, &'static str)> {
+    let id = state.get_config().await.service_id(&id).ok_or((StatusCode::NOT_FOUND, "Service not found"))?;
+    state
+        .slo_report(&id)
+        .await
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Service not found"))
+}
+
+// Handler for the latency distribution of a single service
+async fn get_service_latency(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<LatencyHistogram>, (StatusCode, &'static str)> {
+    let id = state.get_config().await.service_id(&id).ok_or((StatusCode::NOT_FOUND, "Service not found"))?;
+    state
+        .latency_histogram(&id)
+        .await
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "Service not found"))
+}
+
+// Handler for Prometheus metrics of all services, in OpenMetrics format when the scraper accepts it
+async fn get_metrics(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let prefix = state
+        .get_config()
+        .await
+        .metrics_prefix
+        .unwrap_or_else(|| DEFAULT_METRICS_PREFIX.to_string());
+    let format = metrics::Format::negotiate(headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()));
+    let services = state.get_all_services().await;
+    (
+        [(header::CONTENT_TYPE, format.content_type())],
+        metrics::render(&prefix, &services, format),
+    )
+}
+
+// Handler for health check endpoint, reports stalled monitoring tasks
+async fn health_check(State(state): State<AppState>) -> (StatusCode, String) {
+    let stalled = state.stalled_services().await;
+    if stalled.is_empty() {
+        (StatusCode::OK, "OK".to_string())
+    } else {
+        let names: Vec<&str> = stalled.iter().map(|s| s.name.as_str()).collect();
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("Stalled monitoring tasks: {}", names.join(", ")),
+        )
+    }
 }
 
 // Handler for getting configuration (requires authentication)
@@ -53,14 +243,12 @@ async fn get_config(
     bearer: BearerToken,
 ) -> Result<Json<Config>, (StatusCode, &'static str)> {
     let config = state.get_config().await;
+    authorize(&config, &bearer)?;
 
-    // Check if bearer token is required and validate it
-    if let Some(expected_token) = &config.api_bearer_token {
-        if bearer.0 != *expected_token {
-            return Err((StatusCode::UNAUTHORIZED, "Invalid bearer token"));
-        }
-    }
-
+    // Values overridden from the environment aren't part of the editable configuration
+    let config = config
+        .without_overrides()
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to serialize configuration"))?;
     Ok(Json(config))
 }
 
@@ -71,41 +259,219 @@ async fn update_config(
     Json(new_config): Json<Config>,
 ) -> Result<(StatusCode, &'static str), (StatusCode, String)> {
     let current_config = state.get_config().await;
+    authorize(&current_config, &bearer).map_err(|(code, msg)| (code, msg.to_string()))?;
 
-    // Check if bearer token is required and validate it
-    if let Some(expected_token) = &current_config.api_bearer_token {
-        if bearer.0 != *expected_token {
-            return Err((StatusCode::UNAUTHORIZED, "Invalid bearer token".to_string()));
-        }
+    apply_config(&state, new_config).await
+}
+
+// Handler for exporting configuration as a downloadable YAML file (requires authentication)
+async fn export_config(
+    State(state): State<AppState>,
+    bearer: BearerToken,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let config = state.get_config().await;
+    authorize(&config, &bearer).map_err(|(code, msg)| (code, msg.to_string()))?;
+
+    let yaml = config.without_overrides().and_then(|config| Ok(serde_yaml::to_string(&config)?)).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to serialize configuration: {}", e),
+        )
+    })?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/yaml"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"healthcheck.yaml\""),
+        ],
+        yaml,
+    ))
+}
+
+// Handler for importing configuration from a YAML body (requires authentication)
+async fn import_config(
+    State(state): State<AppState>,
+    bearer: BearerToken,
+    body: String,
+) -> Result<(StatusCode, &'static str), (StatusCode, String)> {
+    let current_config = state.get_config().await;
+    authorize(&current_config, &bearer).map_err(|(code, msg)| (code, msg.to_string()))?;
+
+    let new_config = Config::parse(&body)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid YAML configuration: {}", e)))?;
+
+    apply_config(&state, new_config).await
+}
+
+// Services to test: ids of configured services and/or full service definitions
+#[derive(Deserialize)]
+struct TestRequest {
+    #[serde(default)]
+    ids: Vec<String>,
+    #[serde(default)]
+    services: Vec<Service>,
+}
+
+// Result of a test check, `id` is only set for configured services
+#[derive(Serialize, Deserialize, Debug)]
+struct TestResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    name: String,
+    state: CheckState,
+    status: Status,
+    latency_ms: u64,
+}
+
+// Handler running checks without recording their results or notifying (requires authentication)
+async fn test_services(
+    State(state): State<AppState>,
+    bearer: BearerToken,
+    Json(request): Json<TestRequest>,
+) -> Result<Json<Vec<TestResult>>, (StatusCode, String)> {
+    let config = state.get_config().await;
+    authorize(&config, &bearer).map_err(|(code, msg)| (code, msg.to_string()))?;
+
+    let mut services = Vec::new();
+    for id in request.ids {
+        let id = config
+            .service_id(&id)
+            .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Service '{}' not found", id)))?;
+        services.push((Some(id.clone()), config.services[&id].clone()));
+    }
+    for service in request.services {
+        service
+            .validate(&service.name)
+            .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid service: {}", e)))?;
+        services.push((None, service));
     }
 
-    match state.update_config(new_config).await {
-        Ok(_) => {
-            tracing::info!("Configuration updated successfully via API");
-            Ok((StatusCode::OK, "Configuration updated successfully"))
+    let ctx = state.check_context().await;
+    let results = futures::future::join_all(services.into_iter().map(|(id, service)| {
+        let ctx = ctx.clone();
+        async move {
+            let started = std::time::Instant::now();
+            let check_state = service.check(&ctx).await;
+            TestResult {
+                id,
+                name: service.name,
+                status: Status::from(&check_state),
+                state: check_state,
+                latency_ms: started.elapsed().as_millis() as u64,
+            }
         }
-        Err(e) => {
-            tracing::error!("Failed to update configuration: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to update configuration: {}", e),
-            ))
+    }))
+    .await;
+    Ok(Json(results))
+}
+
+// Default time a request may take before the server answers 408
+const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 10000;
+// Config changes write to disk and restart monitoring tasks, so they get longer
+const DEFAULT_CONFIG_REQUEST_TIMEOUT_MS: u64 = 60000;
+
+// Whether the query asks for indented JSON with `pretty`, `pretty=true` or `pretty=1`. Anything else
+// is left to the route, which may have query parameters of its own.
+fn wants_pretty(query: Option<&str>) -> bool {
+    query.is_some_and(|query| query.split('&').any(|pair| matches!(pair, "pretty" | "pretty=true" | "pretty=1")))
+}
+
+// Re-indent JSON responses for humans when the request has `?pretty`
+async fn pretty_json(request: Request, next: Next) -> Response {
+    let pretty = wants_pretty(request.uri().query());
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    if !pretty || !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, usize::MAX).await else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read response body").into_response();
+    };
+    let pretty = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .and_then(|value| serde_json::to_vec_pretty(&value))
+        .unwrap_or_else(|_| bytes.to_vec());
+    parts.headers.insert(header::CONTENT_LENGTH, HeaderValue::from(pretty.len()));
+    Response::from_parts(parts, Body::from(pretty))
+}
+
+// Largest configuration body accepted, counted after decompression to defuse zip bombs
+const CONFIG_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+// Default bind attempts and initial backoff when the port is still in use
+const DEFAULT_BIND_ATTEMPTS: u32 = 5;
+const DEFAULT_BIND_BACKOFF_MS: u64 = 500;
+
+// Bind the listener, retrying with exponential backoff while the address is in use
+async fn bind_with_retry(addr: &str, attempts: u32, backoff: Duration) -> std::io::Result<tokio::net::TcpListener> {
+    let mut delay = backoff;
+    let mut attempt = 1;
+    loop {
+        match tokio::net::TcpListener::bind(addr).await {
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && attempt < attempts => {
+                tracing::warn!("Address {} in use, retrying bind in {:?} (attempt {}/{})", addr, delay, attempt, attempts);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
         }
     }
 }
 
+// Answer 408 Request Timeout when a handler takes longer than `timeout_ms`
+fn with_timeout(router: Router<AppState>, timeout_ms: u64) -> Router<AppState> {
+    router.layer(TimeoutLayer::with_status_code(
+        StatusCode::REQUEST_TIMEOUT,
+        Duration::from_millis(timeout_ms),
+    ))
+}
+
 // Create the web server router
-pub fn create_router(app_state: AppState) -> Router {
+pub async fn create_router(app_state: AppState) -> Router {
     // Configure CORS to allow requests from any origin
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
 
-    Router::new()
+    let (request_timeout_ms, config_request_timeout_ms) = {
+        let config = app_state.get_config().await;
+        (
+            config.request_timeout_ms.unwrap_or(DEFAULT_REQUEST_TIMEOUT_MS),
+            config.config_request_timeout_ms.unwrap_or(DEFAULT_CONFIG_REQUEST_TIMEOUT_MS),
+        )
+    };
+
+    let api = Router::new()
         .route("/api/services", get(get_services))
-        .route("/api/config", get(get_config).put(update_config))
+        .route("/api/summary", get(get_summary))
+        .route("/api/events", get(get_events))
+        .route("/api/services/:id", get(get_service))
+        .route("/api/services/:id/report", get(get_service_report))
+        .route("/api/services/:id/slo", get(get_service_slo))
+        .route("/api/services/:id/latency", get(get_service_latency))
+        .route("/api/services/:id/history", get(get_service_history))
         .route("/api/health", get(health_check))
+        .route("/metrics", get(get_metrics));
+    // Config uploads may be gzip-compressed (`Content-Encoding: gzip`); test checks may run
+    // up to their check timeout, so they share the longer timeout
+    let config_api = Router::new()
+        .route("/api/config", get(get_config).put(update_config))
+        .route("/api/config/export", get(export_config))
+        .route("/api/config/import", post(import_config))
+        .route("/api/test", post(test_services))
+        .layer(DefaultBodyLimit::max(CONFIG_BODY_LIMIT))
+        .layer(RequestDecompressionLayer::new().gzip(true));
+
+    Router::new()
+        .merge(with_timeout(api, request_timeout_ms))
+        .merge(with_timeout(config_api, config_request_timeout_ms))
+        .layer(middleware::from_fn(pretty_json))
         .nest_service("/", ServeDir::new("frontend"))
         .layer(cors)
         .with_state(app_state)
@@ -113,9 +479,16 @@ pub fn create_router(app_state: AppState) -> Router {
 
 // Start the web server
 pub async fn start_server(app_state: AppState, port: u16) -> anyhow::Result<()> {
-    let app = create_router(app_state);
+    let (attempts, backoff_ms) = {
+        let config = app_state.get_config().await;
+        (
+            config.web_bind_attempts.unwrap_or(DEFAULT_BIND_ATTEMPTS),
+            config.web_bind_backoff_ms.unwrap_or(DEFAULT_BIND_BACKOFF_MS),
+        )
+    };
+    let app = create_router(app_state).await;
     let addr = format!("0.0.0.0:{}", port);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    let listener = bind_with_retry(&addr, attempts, Duration::from_millis(backoff_ms)).await?;
 
     tracing::info!("Web server listening on {}", addr);
 
@@ -123,3 +496,568 @@ pub async fn start_server(app_state: AppState, port: u16) -> anyhow::Result<()>
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::State;
+    use crate::failure::FailureCategory;
+    use axum::body::to_bytes;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    const CONFIG: &str = r#"
+telegram_token: "token"
+telegram_chat_id: 1
+check_interval_success: 60000
+check_interval_fail: 10000
+notify_failures: 3
+rereport: 10
+api_bearer_token: "secret"
+services:
+  website:
+    enabled: false
+    name: "Website"
+    description: "Main website"
+    check: !http
+      url: "http://127.0.0.1:1"
+  database:
+    enabled: false
+    name: "Database"
+    description: "Primary database"
+    notify_failures: 1
+    check: !tcpPing
+      host: "127.0.0.1"
+      port: 5432
+"#;
+
+    const EMPTY_CONFIG: &str = r#"
+telegram_token: "other"
+telegram_chat_id: 2
+check_interval_success: 1000
+check_interval_fail: 1000
+notify_failures: 1
+rereport: 1
+api_bearer_token: "secret"
+services: {}
+"#;
+
+    fn app_state(dir: &tempfile::TempDir, yaml: &str) -> AppState {
+        let path = dir.path().join("healthcheck.yaml");
+        std::fs::write(&path, yaml).unwrap();
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        AppState::new(config, vec![path.to_string_lossy().to_string()])
+    }
+
+    #[tokio::test]
+    async fn test_slow_request_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = app_state(&dir, CONFIG);
+        let slow = Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                "done"
+            }),
+        );
+
+        let response = with_timeout(slow, 50)
+            .with_state(state)
+            .oneshot(Request::get("/slow").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_bind_retried_while_port_in_use() {
+        let occupied = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = occupied.local_addr().unwrap().to_string();
+
+        // A single attempt fails right away
+        let error = bind_with_retry(&addr, 1, Duration::from_millis(50)).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+
+        // The port is released while retrying
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            drop(occupied);
+        });
+        let listener = bind_with_retry(&addr, 5, Duration::from_millis(50)).await.unwrap();
+        assert_eq!(listener.local_addr().unwrap().to_string(), addr);
+        release.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_metrics_use_configured_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let yaml = CONFIG.replace("enabled: false", "enabled: true").replace("services:", "metrics_prefix: \"acme\"\nservices:");
+        let state = app_state(&dir, &yaml);
+        // The website only alerts after 3 failures, no notification is sent
+        state.set_state("website".to_string(), State::failure(FailureCategory::Connection, "refused")).await;
+        state.set_state("database".to_string(), State::Success).await;
+
+        let response = create_router(state).await
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains("# TYPE acme_up gauge"));
+        assert!(body.contains("acme_up{service=\"Website\"} 0"));
+        assert!(body.contains("acme_up{service=\"Database\"} 1"));
+        assert!(body.contains("acme_total_checks{service=\"Website\"} 1"));
+        assert!(body.contains("# TYPE acme_failed_checks counter"));
+        assert!(body.contains("acme_failed_checks{service=\"Website\"} 1"));
+        assert!(body.contains("acme_failed_checks{service=\"Database\"} 0"));
+        assert!(body.contains("# TYPE acme_consecutive_failures gauge"));
+        assert!(body.contains("acme_consecutive_failures{service=\"Website\"} 1"));
+        assert!(!body.contains("healthcheck_"));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_in_openmetrics_format_when_accepted() {
+        let dir = tempfile::tempdir().unwrap();
+        let yaml = CONFIG.replace("enabled: false", "enabled: true");
+        let state = app_state(&dir, &yaml);
+        state.set_state("database".to_string(), State::Success).await;
+
+        let response = create_router(state).await
+            .oneshot(
+                Request::get("/metrics")
+                    .header(header::ACCEPT, "application/openmetrics-text; version=1.0.0,text/plain;q=0.5")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/openmetrics-text; version=1.0.0; charset=utf-8"
+        );
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.ends_with("# EOF\n"));
+        assert!(body.contains("# TYPE healthcheck_uptime_seconds gauge\n# UNIT healthcheck_uptime_seconds seconds\n"));
+        assert!(body.contains("healthcheck_uptime_seconds{service=\"Database\"} "));
+        assert!(body.contains("# TYPE healthcheck_total_checks counter"));
+        assert!(body.contains("healthcheck_total_checks_total{service=\"Database\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn test_service_endpoints_accept_slug() {
+        let dir = tempfile::tempdir().unwrap();
+        let yaml = CONFIG
+            .replace("enabled: false", "enabled: true")
+            .replace("name: \"Database\"", "name: \"Database\"\n    slug: \"primary-db\"");
+        let state = app_state(&dir, &yaml);
+        state.set_state("database".to_string(), State::failure(FailureCategory::Connection, "refused")).await;
+
+        let report = |path: &str| {
+            let state = state.clone();
+            let path = path.to_string();
+            async move {
+                let response = create_router(state).await
+                    .oneshot(Request::get(path).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<UptimeReport>(&body).ok())
+            }
+        };
+        let (status, by_slug) = report("/api/services/primary-db/report").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(by_slug.unwrap().incident_count, 1);
+        let (status, by_id) = report("/api/services/database/report").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(by_id.unwrap().incident_count, 1);
+        assert_eq!(report("/api/services/unknown/report").await.0, StatusCode::NOT_FOUND);
+
+        // Slugs must be unique
+        let duplicate = yaml.replace("name: \"Website\"", "name: \"Website\"\n    slug: \"primary-db\"");
+        let config: Config = serde_yaml::from_str(&duplicate).unwrap();
+        assert!(config.validate().unwrap_err().to_string().contains("share the slug 'primary-db'"));
+    }
+
+    #[tokio::test]
+    async fn test_get_single_service() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = app_state(&dir, &CONFIG.replace("enabled: false", "enabled: true"));
+        state.set_state("database".to_string(), State::failure(FailureCategory::Connection, "refused")).await;
+
+        let get = |path: &str| {
+            let state = state.clone();
+            let path = path.to_string();
+            async move {
+                let response = create_router(state).await
+                    .oneshot(Request::get(path).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<ServiceState>(&body).ok())
+            }
+        };
+        let (status, service) = get("/api/services/database").await;
+        assert_eq!(status, StatusCode::OK);
+        let service = service.unwrap();
+        assert_eq!(service.name, "Database");
+        // The state carries the message, the category comes separately
+        assert!(matches!(&service.state, State::Failure(reason) if reason.detail == "refused"));
+        assert_eq!(service.failure.map(|failure| failure.category), Some(FailureCategory::Connection));
+        assert_eq!(service.consecutive_failures, 1);
+        assert_eq!(get("/api/services/unknown").await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_service_history_keeps_most_recent_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = app_state(&dir, &format!("history_size: 3\n{}", CONFIG.replace("enabled: false", "enabled: true")));
+        // Below notify_failures, so nothing is notified
+        state.set_state("website".to_string(), State::failure(FailureCategory::Connection, "refused")).await;
+        for _ in 0..2 {
+            state.set_state("website".to_string(), State::Success).await;
+        }
+        state.set_state("website".to_string(), State::failure(FailureCategory::Timeout, "timeout")).await;
+
+        let response = create_router(state.clone()).await
+            .oneshot(Request::get("/api/services/website/history").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let history: Vec<CheckRecord> = serde_json::from_slice(&body).unwrap();
+        // The failure was dropped to stay within history_size
+        let states: Vec<_> = history.iter().map(|r| r.status).collect();
+        assert_eq!(states, vec![Status::Up, Status::Up, Status::Down]);
+        assert!(matches!(&history[2].state, State::Failure(reason) if reason.detail == "timeout"));
+        assert!(history.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        assert_eq!(history[2].status, Status::Down);
+
+        let response = create_router(state).await
+            .oneshot(Request::get("/api/services/unknown/history").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_latency_histogram() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = app_state(&dir, &format!("latency_buckets_ms: [100, 500]\n{}", CONFIG.replace("enabled: false", "enabled: true")));
+        let get = |path: &str| {
+            let state = state.clone();
+            let path = path.to_string();
+            async move {
+                let response = create_router(state).await
+                    .oneshot(Request::get(path).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice::<LatencyHistogram>(&body).ok())
+            }
+        };
+        let counts = |histogram: LatencyHistogram| histogram.buckets.iter().map(|b| (b.le_ms, b.count)).collect::<Vec<_>>();
+
+        // No checks yet, every bucket is empty
+        let (status, histogram) = get("/api/services/database/latency").await;
+        assert_eq!(status, StatusCode::OK);
+        let histogram = histogram.unwrap();
+        assert_eq!(histogram.samples, 0);
+        assert_eq!(counts(histogram), vec![(Some(100), 0), (Some(500), 0), (None, 0)]);
+
+        // Bounds are inclusive
+        for ms in [20, 100, 101, 480, 2000] {
+            state.record_latency("database", Duration::from_millis(ms)).await;
+        }
+        let histogram = get("/api/services/database/latency").await.1.unwrap();
+        assert_eq!(histogram.samples, 5);
+        assert_eq!(counts(histogram), vec![(Some(100), 2), (Some(500), 2), (None, 1)]);
+        assert_eq!(get("/api/services/unknown/latency").await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_events_replayed_after_last_event_id() {
+        use futures::StreamExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = app_state(&dir, &CONFIG.replace("enabled: false", "enabled: true"));
+        state.set_state("database".to_string(), State::Success).await;
+        state.set_state("website".to_string(), State::failure(FailureCategory::Connection, "refused")).await;
+        state.set_state("database".to_string(), State::Success).await;
+        let first = state.subscribe_events(Some(0)).0[0].id;
+
+        // The client saw the first event before disconnecting
+        let response = create_router(state.clone()).await
+            .oneshot(Request::get("/api/events").header("Last-Event-ID", first.to_string()).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+        let mut body = response.into_body().into_data_stream();
+        state.set_state("website".to_string(), State::failure(FailureCategory::Timeout, "timeout")).await;
+
+        let mut received = String::new();
+        while received.matches("\n\n").count() < 3 {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), body.next()).await.unwrap().unwrap().unwrap();
+            received.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        let events: Vec<crate::events::CheckEvent> = received
+            .split("\n\n")
+            .filter_map(|event| event.lines().find_map(|line| line.strip_prefix("data: ")))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+        let ids: Vec<(u64, &str, Status)> = events.iter().map(|e| (e.id, e.service_id.as_str(), e.status)).collect();
+        assert_eq!(
+            ids,
+            [(first + 1, "website", Status::Down), (first + 2, "database", Status::Up), (first + 3, "website", Status::Down)]
+        );
+        assert!(received.starts_with(&format!("id: {}\n", first + 1)));
+
+        // Ids continue from the startup time, an id seen before a restart with the clock set back replays everything
+        assert!(first > 1_000_000);
+        assert_eq!(state.subscribe_events(Some(first + 100)).0.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_summary_reports_weighted_health_score() {
+        let dir = tempfile::tempdir().unwrap();
+        let yaml = CONFIG
+            .replace("enabled: false", "enabled: true")
+            .replace("notify_failures: 1", "notify_failures: 3\n    health_weight: 3")
+            .replace("services:", "metrics_prefix: \"acme\"\nservices:");
+        let state = app_state(&dir, &yaml);
+        // Database (weight 3) is down, the website (weight 1) is up
+        state.set_state("website".to_string(), State::Success).await;
+        state.set_state("database".to_string(), State::failure(FailureCategory::Connection, "refused")).await;
+
+        let response = create_router(state.clone()).await
+            .oneshot(Request::get("/api/summary").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let summary: Summary = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            summary,
+            Summary { total: 2, up: 1, down: 1, warning: 0, unknown: 0, health_score: Some(0.25) }
+        );
+
+        let response = create_router(state).await
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains("acme_health_score 0.25\n"));
+    }
+
+    #[tokio::test]
+    async fn test_pretty_query_indents_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = app_state(&dir, CONFIG);
+        let get_summary = |uri: &'static str| {
+            let state = state.clone();
+            async move {
+                let response = create_router(state).await
+                    .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        let compact = get_summary("/api/summary").await;
+        assert!(!compact.contains('\n'));
+
+        let pretty = get_summary("/api/summary?pretty=true").await;
+        assert!(pretty.starts_with("{\n  \"total\": 0,\n"));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&pretty).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&compact).unwrap()
+        );
+        assert_eq!(get_summary("/api/summary?pretty=1").await, pretty);
+        assert_eq!(get_summary("/api/summary?pretty").await, pretty);
+
+        // Other values are ignored rather than rejected
+        for uri in ["/api/summary?pretty=false", "/api/summary?pretty=maybe", "/api/summary?pretty=%zz&x"] {
+            assert_eq!(get_summary(uri).await, compact, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_services_tested_without_touching_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = app_state(&dir, &CONFIG.replace("enabled: false", "enabled: true"));
+        let target = crate::test_util::serve(Router::new().route("/health", get(|| async { "ok" }))).await;
+        let request = |token: &str, body: serde_json::Value| {
+            Request::post("/api/test")
+                .header("Authorization", format!("Bearer {}", token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let body = serde_json::json!({
+            "ids": ["website"],
+            "services": [{
+                "enabled": true,
+                "name": "Candidate",
+                "description": "Not configured yet",
+                "check": { "http": { "url": format!("{}/health", target) } },
+            }],
+        });
+
+        let response = create_router(state.clone()).await.oneshot(request("wrong", body.clone())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = create_router(state.clone()).await.oneshot(request("secret", body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let results: Vec<TestResult> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!((results[0].id.as_deref(), results[0].name.as_str()), (Some("website"), "Website"));
+        assert_eq!(results[0].status, Status::Down);
+        assert!(matches!(&results[0].state, CheckState::Failure(reason) if reason.detail.starts_with("Request failed")));
+        assert_eq!((results[1].id.as_deref(), results[1].name.as_str()), (None, "Candidate"));
+        assert_eq!(results[1].state, CheckState::Success);
+
+        // Nothing was recorded for the configured services
+        for service in state.get_all_services().await {
+            assert_eq!((service.status, service.total_checks), (Status::Unknown, 0), "{}", service.name);
+        }
+
+        let missing = serde_json::json!({ "ids": ["missing"] });
+        let response = create_router(state).await.oneshot(request("secret", missing)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_export_import_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = app_state(&dir, CONFIG);
+
+        let response = create_router(source.clone()).await
+            .oneshot(
+                Request::get("/api/config/export")
+                    .header("Authorization", "Bearer secret")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"healthcheck.yaml\""
+        );
+        let exported = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        let target_dir = tempfile::tempdir().unwrap();
+        let target = app_state(&target_dir, EMPTY_CONFIG);
+
+        let response = create_router(target.clone()).await
+            .oneshot(
+                Request::post("/api/config/import")
+                    .header("Authorization", "Bearer secret")
+                    .body(Body::from(exported))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let expected = serde_json::to_value(source.get_config().await.services).unwrap();
+        let imported = serde_json::to_value(target.get_config().await.services).unwrap();
+        assert_eq!(expected, imported);
+        assert_eq!(target.get_config().await.telegram_token, "token");
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_gzip_config_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = app_state(&dir, CONFIG);
+        let put = |body: Vec<u8>| {
+            let state = state.clone();
+            async move {
+                create_router(state).await
+                    .oneshot(
+                        Request::put("/api/config")
+                            .header("Authorization", "Bearer secret")
+                            .header(header::CONTENT_TYPE, "application/json")
+                            .header(header::CONTENT_ENCODING, "gzip")
+                            .body(Body::from(body))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+
+        let mut config: serde_json::Value = serde_json::to_value(state.get_config().await).unwrap();
+        config["rereport"] = 20.into();
+        let status = put(gzip(config.to_string().as_bytes())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.get_config().await.rereport, 20);
+
+        // A small body inflating beyond the limit is rejected
+        config["description_padding"] = " ".repeat(CONFIG_BODY_LIMIT).into();
+        let bomb = gzip(config.to_string().as_bytes());
+        assert!(bomb.len() < CONFIG_BODY_LIMIT / 100);
+        assert_eq!(put(bomb).await, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(state.get_config().await.rereport, 20);
+    }
+
+    #[tokio::test]
+    async fn test_config_api_disabled_without_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = app_state(&dir, &CONFIG.replace("api_bearer_token: \"secret\"\n", ""));
+
+        let response = create_router(state.clone()).await
+            .oneshot(Request::get("/api/config").header("Authorization", "Bearer anything").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = create_router(state.clone()).await
+            .oneshot(
+                Request::post("/api/config/import")
+                    .header("Authorization", "Bearer anything")
+                    .body(Body::from(EMPTY_CONFIG))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(state.get_config().await.telegram_token, "token");
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_invalid_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = app_state(&dir, CONFIG);
+
+        let response = create_router(state.clone()).await
+            .oneshot(
+                Request::post("/api/config/import")
+                    .header("Authorization", "Bearer secret")
+                    .body(Body::from(CONFIG.replace("rereport: 10", "rereport: 0")))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(state.get_config().await.rereport, 10);
+    }
+}